@@ -0,0 +1,107 @@
+//! Verify a compressed proof of the profile updates pipeline
+//!
+//! ```text
+//! verify <verifier key file> <compressed proof file> <step count> <z0> <z_n>
+//! ```
+//!
+//! The verifier key is the `<pp digest>.vk.json` written next to the cached compressed SNARK keys,
+//! the compressed proof is a json serialized `CompressedSNARK` as kept in the proof store.
+//! z0 and z_n are comma separated Merkle root and timestamp, as hex ("0x...") or decimal numbers.
+//!
+//! Prints `pass` and exits with 0 if the proof shows that `step count` valid steps lead from z0 to z_n,
+//! prints `fail: <reason>` and exits with 1 otherwise.
+use anyhow::{anyhow, Result};
+use ff::PrimeField;
+use nova_scotia::{circom::circuit::CircomCircuit, F, S};
+use nova_snark::{
+    provider::secp_secq::{secp256k1, secq256k1},
+    traits::{circuit::TrivialCircuit, Group},
+    CompressedSNARK, VerifierKey,
+};
+use num_bigint::BigUint;
+use num_traits::Num;
+
+type G1 = secq256k1::Point;
+type G2 = secp256k1::Point;
+type C1<G> = CircomCircuit<<G as Group>::Scalar>;
+type C2<G> = TrivialCircuit<<G as Group>::Scalar>;
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() != 6 {
+        eprintln!(
+            "usage: {} <vk file> <proof file> <step count> <z0> <z_n>",
+            args[0]
+        );
+        std::process::exit(2);
+    }
+    match verify(&args[1], &args[2], &args[3], &args[4], &args[5]) {
+        Ok(()) => println!("pass"),
+        Err(e) => {
+            println!("fail: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn verify(vk_file: &str, proof_file: &str, num_steps: &str, z0: &str, z_n: &str) -> Result<()> {
+    let vk: VerifierKey<G1, G2, C1<G1>, C2<G2>, S<G1>, S<G2>> =
+        serde_json::from_str(&std::fs::read_to_string(vk_file)?)?;
+    let compressed_snark: CompressedSNARK<G1, G2, C1<G1>, C2<G2>, S<G1>, S<G2>> =
+        serde_json::from_str(&std::fs::read_to_string(proof_file)?)?;
+    let num_steps = num_steps.parse::<usize>()?;
+    let z0 = parse_public_values(z0)?;
+    let claimed_z_n = parse_public_values(z_n)?;
+    let z0_secondary = vec![<G2 as Group>::Scalar::zero()];
+
+    let (z_n, _) = compressed_snark
+        .verify(&vk, num_steps, z0, z0_secondary)
+        .map_err(|e| anyhow!("proof does not verify: {e:?}"))?;
+    if z_n != claimed_z_n {
+        return Err(anyhow!(
+            "proof output {:?} does not match the claimed z_n {:?}",
+            z_n,
+            claimed_z_n
+        ));
+    }
+    Ok(())
+}
+
+/// Comma separated field elements, each hex with "0x" prefix or decimal
+fn parse_public_values(values: &str) -> Result<Vec<F<G1>>> {
+    values
+        .split(',')
+        .map(|value| {
+            let value = value.trim();
+            let decimal = match value.strip_prefix("0x") {
+                Some(hex) => BigUint::from_str_radix(hex, 16)?.to_str_radix(10),
+                None => value.to_string(),
+            };
+            F::<G1>::from_str_vartime(&decimal).ok_or(anyhow!("invalid field element {value}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_public_values() {
+        let root = F::<G1>::from_str_vartime(
+            "57229376209049585136773117581839759840059304365154418192974084211719181400451",
+        )
+        .unwrap();
+        let timestamp = F::<G1>::from_str_vartime("170345900").unwrap();
+        let expected = vec![root, timestamp];
+
+        let decimal =
+            "57229376209049585136773117581839759840059304365154418192974084211719181400451, 170345900";
+        assert_eq!(parse_public_values(decimal).unwrap(), expected);
+        // as printed in the proof metadata
+        let hex = format!("{:?},{:?}", root, timestamp);
+        assert_eq!(parse_public_values(&hex).unwrap(), expected);
+        assert!(parse_public_values("0xzz,1").is_err());
+        assert!(parse_public_values("").is_err());
+    }
+}
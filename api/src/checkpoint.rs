@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use ff::PrimeField;
+use k256::FieldBytes;
+use merkle_tree::{Hash, Key, MerkleTree};
+use nova_scotia::F;
+use nova_snark::{traits::Group, PublicParams, RecursiveSNARK};
+use num_bigint::BigUint;
+use num_traits::Num;
+use serde::{Deserialize, Serialize};
+
+use crate::eff_ecdsa_input::fe_to_biguint;
+use crate::public_params::R1csDigest;
+use crate::{C1, C2, G1, G2};
+
+/// Folding state of `IVCProofFolder` after `num_steps` steps
+///
+/// Together with the public parameters and z0 it is enough to continue folding
+/// after a restart: the accumulated proof, z_i (Merkle root and timestamp) and the
+/// leaves of the Merkle tree with root z_i[0] and the profile messages they hash.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub recursive_snark: RecursiveSNARK<G1, G2, C1<G1>, C2<G2>>,
+    pub num_steps: usize,
+    /// hex digest of the r1cs the proof was folded for, see `public_params::r1cs_digest`,
+    /// missing in checkpoints saved before it was stored
+    #[serde(default)]
+    r1cs_digest: Option<String>,
+    /// z_i as hex strings
+    public_output: Vec<String>,
+    /// (key, leaf hash) in insertion order as hex strings
+    leaves: Vec<(String, String)>,
+    /// (key, profile message) of every leaf with hex keys, the next update of a key
+    /// proves that it replaces this message
+    #[serde(default)]
+    messages: Vec<(String, String)>,
+}
+
+impl Checkpoint {
+    pub fn new(
+        recursive_snark: RecursiveSNARK<G1, G2, C1<G1>, C2<G2>>,
+        num_steps: usize,
+        r1cs_digest: &R1csDigest,
+        public_output: &[F<G1>],
+        leaves: &[(Key, Hash)],
+        messages: &HashMap<Key, String>,
+    ) -> Self {
+        Self {
+            recursive_snark,
+            num_steps,
+            r1cs_digest: Some(hex::encode(r1cs_digest)),
+            public_output: public_output
+                .iter()
+                .map(|&x| format!("{:?}", x).strip_prefix("0x").unwrap().to_string())
+                .collect(),
+            leaves: leaves.iter().map(encode_leaf).collect(),
+            messages: messages
+                .iter()
+                .map(|(key, message)| (hex::encode(key), message.clone()))
+                .collect(),
+        }
+    }
+    pub fn public_output(&self) -> Result<Vec<F<G1>>> {
+        self.public_output
+            .iter()
+            .map(|x| {
+                let decimal = BigUint::from_str_radix(x, 16)?.to_str_radix(10);
+                F::<G1>::from_str_vartime(&decimal).ok_or(anyhow!("invalid public output {x}"))
+            })
+            .collect()
+    }
+    pub fn leaves(&self) -> Result<Vec<(Key, Hash)>> {
+        self.leaves.iter().map(decode_leaf).collect()
+    }
+    pub fn messages(&self) -> Result<HashMap<Key, String>> {
+        self.messages
+            .iter()
+            .map(|(key, message)| Ok((hex::decode(key)?, message.clone())))
+            .collect()
+    }
+    /// Merkle tree of depth `depth` rebuilt from the leaves
+    pub fn merkle_tree(&self, depth: usize) -> Result<MerkleTree> {
+        let mut tree = MerkleTree::new(depth);
+        for (key, hash) in self.leaves()? {
+            tree.insert_leaf_hash(&key, &hash)?;
+        }
+        Ok(tree)
+    }
+    /// Check that the checkpoint was folded for the r1cs with `r1cs_digest`
+    pub fn check_r1cs_digest(&self, r1cs_digest: &R1csDigest) -> Result<()> {
+        match &self.r1cs_digest {
+            Some(digest) if *digest != hex::encode(r1cs_digest) => Err(anyhow!(
+                "checkpoint was folded for r1cs {digest}, not for r1cs {}",
+                hex::encode(r1cs_digest)
+            )),
+            _ => Ok(()),
+        }
+    }
+    /// Verify the accumulated proof from `start_public_input` and check that it ends in the
+    /// stored z_i and that the stored leaves produce the Merkle root z_i[0]
+    ///
+    /// `pp` must be the public parameters of the r1cs with `r1cs_digest`.
+    /// Returns the rebuilt Merkle tree
+    pub fn verify(
+        &self,
+        pp: &PublicParams<G1, G2, C1<G1>, C2<G2>>,
+        r1cs_digest: &R1csDigest,
+        start_public_input: &[F<G1>],
+        depth: usize,
+    ) -> Result<MerkleTree> {
+        // the proof of another circuit does not verify, check the digest first for a clear error
+        self.check_r1cs_digest(r1cs_digest)?;
+        let z0_secondary = vec![<G2 as Group>::Scalar::zero()];
+        let (z_n, _) = self
+            .recursive_snark
+            .verify(pp, self.num_steps, start_public_input, &z0_secondary)
+            .map_err(|e| anyhow!("recursive snark does not verify: {e:?}"))?;
+        if z_n != self.public_output()? {
+            return Err(anyhow!(
+                "recursive snark output does not match the checkpoint"
+            ));
+        }
+        let tree = self.merkle_tree(depth)?;
+        let root = fe_to_biguint(&tree.root());
+        let expected_root = BigUint::from_str_radix(&self.public_output[0], 16)?;
+        if root != expected_root {
+            return Err(anyhow!("Merkle tree root does not match the checkpoint"));
+        }
+        Ok(tree)
+    }
+}
+
+fn encode_leaf((key, hash): &(Key, Hash)) -> (String, String) {
+    (hex::encode(key), hex::encode(hash.to_bytes()))
+}
+
+fn decode_leaf((key, hash): &(String, String)) -> Result<(Key, Hash)> {
+    let bytes = hex::decode(hash)?;
+    if bytes.len() != 32 {
+        return Err(anyhow!("invalid leaf hash {hash}"));
+    }
+    let hash = Option::from(Hash::from_bytes(FieldBytes::from_slice(&bytes)))
+        .ok_or(anyhow!("invalid leaf hash {hash}"))?;
+    Ok((hex::decode(key)?, hash))
+}
+
+/// Checkpoint file, replaced atomically on every save
+pub struct CheckpointStore {
+    path: PathBuf,
+}
+
+impl CheckpointStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+    pub fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        // write to a temporary file first, so a crash during the write keeps the previous checkpoint
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string(checkpoint)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+    pub fn load(&self) -> Result<Option<Checkpoint>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let checkpoint = serde_json::from_str(&std::fs::read_to_string(&self.path)?)?;
+        Ok(Some(checkpoint))
+    }
+    /// Load the checkpoint and verify it with `Checkpoint::verify`
+    ///
+    /// Returns the checkpoint, the rebuilt Merkle tree and the profile messages of its leaves.
+    /// Fails for unreadable checkpoints and for checkpoints of other public parameters.
+    pub fn load_verified(
+        &self,
+        pp: &PublicParams<G1, G2, C1<G1>, C2<G2>>,
+        r1cs_digest: &R1csDigest,
+        start_public_input: &[F<G1>],
+        depth: usize,
+    ) -> Result<Option<(Checkpoint, MerkleTree, HashMap<Key, String>)>> {
+        let Some(checkpoint) = self.load()? else {
+            return Ok(None);
+        };
+        let tree = checkpoint.verify(pp, r1cs_digest, start_public_input, depth)?;
+        let messages = checkpoint.messages()?;
+        Ok(Some((checkpoint, tree, messages)))
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merkle_tree::hash_data;
+
+    #[test]
+    fn test_leaf_encoding_round_trip() {
+        let leaf = (vec![1u8, 2, 3], hash_data(&b"profile".to_vec()));
+        assert_eq!(decode_leaf(&encode_leaf(&leaf)).unwrap(), leaf);
+        assert!(decode_leaf(&("01".to_string(), "02".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_load_missing_checkpoint() {
+        let path = std::env::temp_dir().join(format!("missing_{}.checkpoint", std::process::id()));
+        assert!(CheckpointStore::new(path).load().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_load_corrupt_checkpoint() {
+        let path = std::env::temp_dir().join(format!("corrupt_{}.checkpoint", std::process::id()));
+        std::fs::write(&path, "{\"num_steps\": 1}").unwrap();
+        let result = CheckpointStore::new(&path).load();
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -1,25 +1,37 @@
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use nova_scotia::S;
 
 use nova_snark::traits::Group;
-use nova_snark::{CompressedSNARK, PublicParams, RecursiveSNARK};
+use nova_snark::{CompressedSNARK, PublicParams};
 use tokio::time::Instant;
 
+use common::utils::time::get_current_timestamp_ms;
 use tokio::sync::mpsc::Receiver;
-use tracing::debug;
+use tracing::{debug, error};
 
-use crate::ivc_proof_folder::NUM_FOLDS;
-use crate::{C1, C2, G1, G2};
+use crate::ivc_proof_folder::FoldedProof;
+use crate::pipeline_events::{send_event, EventSender, PipelineEvent};
+use crate::proof_store::{ProofMetadata, ProofStore};
+use crate::update_tracker::UpdateTracker;
+use crate::{CompressedProof, CompressedProverKey, CompressedVerifierKey, C1, C2, G1, G2};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
 
 pub struct CompressedProofBuilder {
-    rx: Receiver<RecursiveSNARK<G1, G2, C1<G1>, C2<G2>>>,
+    rx: Receiver<FoldedProof>,
     pp: Arc<PublicParams<G1, G2, C1<G1>, C2<G2>>>,
     start_public_input: Vec<<G1 as Group>::Scalar>,
+    proof_store: Option<Arc<ProofStore>>,
+    key_cache_dir: Option<PathBuf>,
+    update_tracker: Option<Arc<UpdateTracker>>,
+    events: Option<EventSender>,
 }
 impl CompressedProofBuilder {
     pub fn new(
-        rx: Receiver<RecursiveSNARK<G1, G2, C1<G1>, C2<G2>>>,
+        rx: Receiver<FoldedProof>,
         pp: Arc<PublicParams<G1, G2, C1<G1>, C2<G2>>>,
         start_public_input: Vec<<G1 as Group>::Scalar>,
     ) -> Self {
@@ -28,25 +40,70 @@ impl CompressedProofBuilder {
             rx,
             pp, // tx,
             start_public_input,
+            proof_store: None,
+            key_cache_dir: None,
+            update_tracker: None,
+            events: None,
             // accumulator: Vec::with_capacity(UPDATE_LEN),
         }
     }
+    /// Persist every verified compressed proof to `proof_store`
+    pub fn with_proof_store(mut self, proof_store: Arc<ProofStore>) -> Self {
+        self.proof_store = Some(proof_store);
+        self
+    }
+    /// Cache the prover and verifier keys in `key_cache_dir` instead of running the setup on every start
+    pub fn with_key_cache(mut self, key_cache_dir: impl Into<PathBuf>) -> Self {
+        self.key_cache_dir = Some(key_cache_dir.into());
+        self
+    }
+    /// Report the updates covered by each stored proof to `update_tracker`
+    pub fn with_update_tracker(mut self, update_tracker: Arc<UpdateTracker>) -> Self {
+        self.update_tracker = Some(update_tracker);
+        self
+    }
+    /// Publish every verified compressed proof to `events`
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
     pub async fn run(&mut self) {
         debug!("CompressedProofBuilder started");
-        let (pk, vk) =
-            CompressedSNARK::<_, _, _, _, S<G1>, S<G2>>::setup(self.pp.as_ref()).unwrap();
-        let mut counter = 1;
+        let keys = match &self.key_cache_dir {
+            Some(key_cache_dir) => get_keys(key_cache_dir, self.pp.as_ref()),
+            None => setup_keys(self.pp.as_ref()),
+        };
+        let (pk, vk) = match keys {
+            Ok(keys) => keys,
+            Err(e) => {
+                error!("no compressed snark keys, folded proofs are not compressed: {e}");
+                return;
+            }
+        };
+        let vk_digest = format!(
+            "0x{}",
+            hex::encode(Keccak256::digest(serde_json::to_string(&vk).unwrap()))
+        );
         let z0_secondary = vec![<G2 as Group>::Scalar::zero()];
-        while let Some(update) = self.rx.recv().await {
-            let recursive_snark = update;
+        while let Some(folded_proof) = self.rx.recv().await {
+            let recursive_snark = &folded_proof.recursive_snark;
             let start = Instant::now();
 
-            let compressed_snark = CompressedSNARK::<_, _, _, _, S<G1>, S<G2>>::prove(
+            let compressed_snark = match CompressedSNARK::<_, _, _, _, S<G1>, S<G2>>::prove(
                 self.pp.as_ref(),
                 &pk,
-                &recursive_snark,
-            )
-            .unwrap();
+                recursive_snark,
+            ) {
+                Ok(compressed_snark) => compressed_snark,
+                Err(e) => {
+                    error!(
+                        "compressed snark at step {} failed: {e:?}",
+                        folded_proof.num_steps
+                    );
+                    self.report_failure(&folded_proof, &format!("compression failed: {e:?}"));
+                    continue;
+                }
+            };
             let duration = start.elapsed();
             debug!("compressed snark proof time {:?}", duration);
             debug!(
@@ -56,16 +113,185 @@ impl CompressedProofBuilder {
             let start = Instant::now();
             let res = compressed_snark.verify(
                 &vk,
-                counter * NUM_FOLDS,
+                folded_proof.num_steps,
                 self.start_public_input.clone(),
                 z0_secondary.clone(),
             );
             debug!(
-                "CompressedSNARK::verify: {:?}, took {:?}",
+                "CompressedSNARK::verify at step {}: {:?}, took {:?}",
+                folded_proof.num_steps,
                 res.is_ok(),
                 start.elapsed()
             );
-            counter += 1;
+            if let Err(e) = res {
+                error!(
+                    "compressed snark at step {} does not verify: {e:?}",
+                    folded_proof.num_steps
+                );
+                self.report_failure(
+                    &folded_proof,
+                    &format!("compressed proof does not verify: {e:?}"),
+                );
+                continue;
+            }
+            let proof_id = self.store_proof(&folded_proof, &compressed_snark, &vk_digest);
+            send_event(
+                &self.events,
+                PipelineEvent::ProofReady {
+                    num_steps: folded_proof.num_steps,
+                    proof_id,
+                },
+            );
+        }
+    }
+    /// Mark the updates `folded_proof` should have covered as failed, the builder goes on
+    /// with the next folded proof
+    fn report_failure(&self, folded_proof: &FoldedProof, reason: &str) {
+        if let Some(update_tracker) = &self.update_tracker {
+            if let Err(e) = update_tracker.set_compression_failed(folded_proof.num_steps, reason) {
+                error!(
+                    "failed to mark the updates of step {}: {e}",
+                    folded_proof.num_steps
+                );
+            }
+        }
+    }
+    /// Store the proof in the proof store if there is one and return its id
+    fn store_proof(
+        &self,
+        folded_proof: &FoldedProof,
+        compressed_snark: &CompressedProof,
+        vk_digest: &str,
+    ) -> Option<u64> {
+        let proof_store = self.proof_store.as_ref()?;
+        let metadata = ProofMetadata::new(
+            folded_proof.num_steps,
+            &self.start_public_input,
+            &folded_proof.public_output,
+            get_current_timestamp_ms(),
+            vk_digest.to_string(),
+        );
+        match proof_store.insert(metadata, compressed_snark) {
+            Ok(id) => {
+                debug!(
+                    "compressed snark at step {} stored with id {id}",
+                    folded_proof.num_steps
+                );
+                if let Some(update_tracker) = &self.update_tracker {
+                    if let Err(e) = update_tracker.set_proven(id, folded_proof.num_steps) {
+                        error!("failed to mark the updates covered by proof {id}: {e}");
+                    }
+                }
+                Some(id)
+            }
+            Err(e) => {
+                error!("failed to store compressed snark: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// Prover and verifier keys of the compressed SNARK for the public parameters with digest `pp_digest`
+#[derive(Serialize, Deserialize)]
+struct CachedKeys {
+    pp_digest: String,
+    pk: CompressedProverKey,
+    vk: CompressedVerifierKey,
+}
+
+/// Keys for `pp` from `<key_cache_dir>/<pp digest>.json`,
+/// runs the setup and writes the file if it is missing or does not belong to `pp`
+///
+/// The verifier key is written to `<key_cache_dir>/<pp digest>.vk.json` as well,
+/// to be published for the `verify` binary.
+pub fn get_keys(
+    key_cache_dir: &Path,
+    pp: &PublicParams<G1, G2, C1<G1>, C2<G2>>,
+) -> Result<(CompressedProverKey, CompressedVerifierKey)> {
+    let pp_digest = format!("{:?}", pp.digest());
+    let keys_file = key_cache_dir.join(format!("{pp_digest}.json"));
+    match read_cached_keys(&keys_file, &pp_digest) {
+        Some(keys) if vk_matches_pp(&keys.vk, pp) => {
+            debug!("Read compressed snark keys from {}", keys_file.display());
+            return Ok((keys.pk, keys.vk));
+        }
+        Some(_) => error!(
+            "{} holds keys of other public parameters",
+            keys_file.display()
+        ),
+        None => {}
+    }
+    let (pk, vk) = setup_keys(pp)?;
+    let keys = CachedKeys { pp_digest, pk, vk };
+    if let Err(e) = write_cached_keys(key_cache_dir, &keys_file, &keys) {
+        error!("failed to cache compressed snark keys: {e}");
+    }
+    Ok((keys.pk, keys.vk))
+}
+
+fn setup_keys(
+    pp: &PublicParams<G1, G2, C1<G1>, C2<G2>>,
+) -> Result<(CompressedProverKey, CompressedVerifierKey)> {
+    debug!("Computing compressed snark keys");
+    let start = Instant::now();
+    let keys = CompressedSNARK::<_, _, _, _, S<G1>, S<G2>>::setup(pp)
+        .map_err(|e| anyhow!("compressed snark setup failed: {e:?}"))?;
+    debug!("Compressed snark keys computed in {:?}", start.elapsed());
+    Ok(keys)
+}
+
+/// Whether `vk` was set up for `pp`
+///
+/// The verifier key records the digest of the public parameters it was derived from,
+/// a key without it is not trusted.
+fn vk_matches_pp(vk: &CompressedVerifierKey, pp: &PublicParams<G1, G2, C1<G1>, C2<G2>>) -> bool {
+    match (serde_json::to_value(vk), serde_json::to_value(pp.digest())) {
+        (Ok(vk), Ok(pp_digest)) => vk.get("digest") == Some(&pp_digest),
+        _ => false,
+    }
+}
+
+fn write_cached_keys(key_cache_dir: &Path, keys_file: &Path, keys: &CachedKeys) -> Result<()> {
+    std::fs::create_dir_all(key_cache_dir)?;
+    std::fs::write(keys_file, serde_json::to_string(keys)?)?;
+    let vk_file = key_cache_dir.join(format!("{}.vk.json", keys.pp_digest));
+    std::fs::write(vk_file, serde_json::to_string(&keys.vk)?)?;
+    Ok(())
+}
+
+/// Cached keys if `keys_file` can be read and was created for the public parameters with `pp_digest`
+fn read_cached_keys(keys_file: &Path, pp_digest: &str) -> Option<CachedKeys> {
+    let keys = std::fs::read_to_string(keys_file).ok()?;
+    match serde_json::from_str::<CachedKeys>(&keys) {
+        Ok(keys) if keys.pp_digest == pp_digest => Some(keys),
+        Ok(keys) => {
+            error!(
+                "{} was created for public parameters {}, expected {pp_digest}",
+                keys_file.display(),
+                keys.pp_digest
+            );
+            None
         }
+        Err(e) => {
+            error!("failed to read {}: {e}", keys_file.display());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_cached_keys_invalid_file() {
+        let keys_file =
+            std::env::temp_dir().join(format!("compressed_snark_keys_{}.json", std::process::id()));
+        assert!(read_cached_keys(&keys_file, "0x01").is_none());
+
+        std::fs::write(&keys_file, r#"{"pp_digest": "0x01"}"#).unwrap();
+        assert!(read_cached_keys(&keys_file, "0x01").is_none());
+        std::fs::remove_file(&keys_file).unwrap();
     }
 }
@@ -6,6 +6,7 @@ use std::sync::Arc;
 use std::{collections::BinaryHeap, sync::Mutex};
 extern crate serde;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::error;
 
 use crate::key_value_storage::KeyValueStorage;
 use crate::server::SignedUserProfileUpdate;
@@ -70,7 +71,9 @@ impl<S: KeyValueStorage + std::marker::Send + std::marker::Sync + 'static> Prior
     async fn push(self: Arc<Self>, update: SignedUserProfileUpdate) {
         let timestamp_ms = update.timestamp_ms();
         let update_str = serde_json::to_string(&update).unwrap();
-        self.persistent_storage.set(&update_str, "");
+        if let Err(e) = self.persistent_storage.set(&update_str, "") {
+            error!("failed to persist a queued profile update: {e}");
+        }
         let mut queue = self.queue.lock().unwrap();
         queue.push(update);
         self.len.fetch_add(1, Ordering::Relaxed);
@@ -96,7 +99,10 @@ impl<S: KeyValueStorage + std::marker::Send + std::marker::Sync + 'static> Prior
             .pop()
             .ok_or(anyhow::anyhow!("queue is empty"))?;
         self.len.fetch_sub(1, Ordering::Relaxed);
-        self.persistent_storage.del(&serde_json::to_string(&item)?);
+        // the update is served even if its persisted copy cannot be removed
+        if let Err(e) = self.persistent_storage.del(&serde_json::to_string(&item)?) {
+            error!("failed to remove a served profile update from the storage: {e}");
+        }
         self.result_tx.send(item).await?;
         Ok(())
     }
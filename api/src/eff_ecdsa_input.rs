@@ -7,6 +7,7 @@ use std::ops::Add;
 use std::ops::Mul;
 
 use elliptic_curve::point::DecompressPoint;
+use elliptic_curve::scalar::IsHigh;
 use elliptic_curve::AffinePoint;
 use elliptic_curve::PrimeField;
 use elliptic_curve::Scalar;
@@ -53,16 +54,134 @@ pub fn fe_to_biguint(fe: &FieldElement) -> BigUint {
     BigUint::from_bytes_be(&fe_bytes)
 }
 
+/// Convert a field element to a 0x prefixed 64 digit hex string
+pub fn fe_to_hex(fe: &FieldElement) -> String {
+    format!("0x{:064x}", fe_to_biguint(fe))
+}
+
 /// Convert a scalar to biguint
 #[inline(always)]
 pub fn scalar_to_biguint(scalar: &ScalarSecp) -> BigUint {
     let scalar_bytes = scalar.to_bytes();
     BigUint::from_bytes_be(&scalar_bytes)
 }
-pub fn hash_msg(msg: &[u8]) -> Vec<u8> {
-    let msg_padded = pad_msg(msg, BIT_SIZE);
+/// Why a hex encoded signature cannot be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    NotHex,
+    WrongLength,
+    InvalidRecoveryId,
+    /// r or s is not lower than the curve order
+    OutOfRange,
+}
+
+/// r, s and the recovery id of an Ethereum signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedSignature {
+    pub r: ScalarSecp,
+    pub s: ScalarSecp,
+    /// 0 or 1, `None` for a signature of r and s only
+    pub recovery_id: Option<i32>,
+}
+impl DecodedSignature {
+    /// Decode 0x prefixed hex of r, s and optionally v
+    ///
+    /// v is big endian on up to 8 bytes, in any of its encodings: 0/1, 27/28
+    /// or EIP-155 `chain_id * 2 + 35/36`.
+    pub fn from_hex(signature: &str) -> Result<Self, SignatureError> {
+        let signature = signature.strip_prefix("0x").unwrap_or(signature);
+        let bytes = hex::decode(signature).or(Err(SignatureError::NotHex))?;
+        if bytes.len() < 64 || bytes.len() > 64 + 8 {
+            return Err(SignatureError::WrongLength);
+        }
+        let scalar = |bytes: &[u8]| {
+            Option::<ScalarSecp>::from(ScalarSecp::from_repr(*FieldBytes::<ECrv>::from_slice(
+                bytes,
+            )))
+            .ok_or(SignatureError::OutOfRange)
+        };
+        let recovery_id = match bytes.len() {
+            64 => None,
+            _ => {
+                let v = bytes[64..].iter().fold(0u64, |v, b| v << 8 | *b as u64);
+                Some(recovery_id_from_v(v).ok_or(SignatureError::InvalidRecoveryId)?)
+            }
+        };
+        Ok(Self {
+            r: scalar(&bytes[..32])?,
+            s: scalar(&bytes[32..64])?,
+            recovery_id,
+        })
+    }
+    /// Whether s is in the upper half of the curve order, such signatures are malleable (EIP-2)
+    pub fn is_high_s(&self) -> bool {
+        self.s.is_high().into()
+    }
+    /// The equivalent signature with s in the lower half of the curve order
+    pub fn normalized(self) -> Self {
+        if !self.is_high_s() {
+            return self;
+        }
+        Self {
+            r: self.r,
+            s: -self.s,
+            recovery_id: self.recovery_id.map(|recovery_id| recovery_id ^ 1),
+        }
+    }
+    /// r and s as 64 big endian bytes
+    pub fn rs_bytes(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(&self.r.to_bytes());
+        bytes[32..].copy_from_slice(&self.s.to_bytes());
+        bytes
+    }
+}
+
+/// Recovery id of the v value of a signature
+pub fn recovery_id_from_v(v: u64) -> Option<i32> {
+    match v {
+        0 | 1 => Some(v as i32),
+        27 | 28 => Some(v as i32 - 27),
+        // EIP-155
+        v if v >= 35 => Some(((v - 35) % 2) as i32),
+        _ => None,
+    }
+}
+
+/// What wallets sign for a profile message posted as text
+///
+/// Updates posted as EIP-712 typed data are accepted next to them, see `crate::eip712`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashMode {
+    /// keccak256 of the message zero padded to `BIT_SIZE` bits, checked by `ivc.circom`
+    #[default]
+    Padded,
+    /// EIP-191 `personal_sign` of the message, checked by `ivc_eip191.circom`:
+    /// keccak256("\x19Ethereum Signed Message:\n" || len(message) || message)
+    ///
+    /// The circuit recovers the length from the zero padded message.
+    Eip191,
+}
+impl HashMode {
+    /// "padded" or "eip191"
+    pub fn from_config(config: &str) -> Result<Self, String> {
+        match config {
+            "padded" => Ok(HashMode::Padded),
+            "eip191" => Ok(HashMode::Eip191),
+            _ => Err(format!("unknown hash mode {config}")),
+        }
+    }
+}
+
+pub fn hash_msg(msg: &[u8], hash_mode: HashMode) -> Vec<u8> {
     let mut hasher = Keccak256::new();
-    hasher.update(msg_padded);
+    match hash_mode {
+        HashMode::Padded => hasher.update(pad_msg(msg, BIT_SIZE)),
+        HashMode::Eip191 => {
+            hasher.update(format!("\x19Ethereum Signed Message:\n{}", msg.len()));
+            hasher.update(msg);
+        }
+    }
     let mut hash = hasher.finalize().to_vec();
     hash.resize(32, 0);
     hash
@@ -94,7 +213,7 @@ pub fn eff_ecdsa_input(
     r: ScalarSecp,
     s: ScalarSecp,
     eth_address: &str,
-    msg: &str,
+    msg_hash: &[u8],
 ) -> (
     ScalarSecp,
     ScalarSecp,
@@ -103,8 +222,7 @@ pub fn eff_ecdsa_input(
     FieldElement,
     FieldElement,
 ) {
-    let msg_hash_bytes = hash_msg(msg.as_bytes());
-    let msg_hash_fb = FieldBytes::<ECrv>::from_slice(&msg_hash_bytes);
+    let msg_hash_fb = FieldBytes::<ECrv>::from_slice(msg_hash);
     let msg_hash = ScalarSecp::from_repr(*msg_hash_fb).unwrap();
     let eth_address = eth_address.to_lowercase();
     let r_inv = r.invert().unwrap();
@@ -134,6 +252,57 @@ pub fn eff_ecdsa_input(
 mod tests {
     use super::*;
     #[test]
+    fn test_decoded_signature() {
+        let rs = "7c62b0e515eb044b731e244904d6efc7cb6dad49b061095b92c33443cb9bfa68f1a61d9f25c990e3c6fc94b280b181bfc77266eca37e77d123e0e67892ee5efc";
+        // v as 27/28, 0/1, EIP-155 on chain 1 and on chain 137
+        for (v, recovery_id) in [
+            ("1b", 0),
+            ("1c", 1),
+            ("00", 0),
+            ("01", 1),
+            ("25", 0),
+            ("26", 1),
+            ("0135", 0),
+            ("0136", 1),
+        ] {
+            let signature = DecodedSignature::from_hex(&format!("0x{rs}{v}")).unwrap();
+            assert_eq!(signature.recovery_id, Some(recovery_id), "v {v}");
+            assert_eq!(hex::encode(signature.rs_bytes()), rs);
+        }
+        let signature = DecodedSignature::from_hex(&format!("0x{rs}")).unwrap();
+        assert_eq!(signature.recovery_id, None);
+
+        let signature = DecodedSignature::from_hex(&format!("0x{rs}1b")).unwrap();
+        assert!(signature.is_high_s());
+        let normalized = signature.normalized();
+        assert!(!normalized.is_high_s());
+        assert_eq!(normalized.recovery_id, Some(1));
+        assert_eq!(normalized.s, -signature.s);
+        assert_eq!(normalized.normalized(), normalized);
+
+        let cases = [
+            ("0xzz".to_string(), SignatureError::NotHex),
+            (format!("0x{}", &rs[..126]), SignatureError::WrongLength),
+            (
+                format!("0x{rs}{}", "00".repeat(9)),
+                SignatureError::WrongLength,
+            ),
+            (format!("0x{rs}05"), SignatureError::InvalidRecoveryId),
+            (format!("0x{rs}22"), SignatureError::InvalidRecoveryId),
+            (
+                format!("0x{}{}1b", "ff".repeat(32), &rs[64..]),
+                SignatureError::OutOfRange,
+            ),
+        ];
+        for (signature, error) in cases {
+            assert_eq!(
+                DecodedSignature::from_hex(&signature),
+                Err(error),
+                "{signature}"
+            );
+        }
+    }
+    #[test]
     fn test_eff_ecdsa_input() {
         let msg = "1703459910, 0x631438556b66c4908579Eab920dc162FF58958ea, Brad, Pitt, brad.pitt@gmail.com";
         let r = ScalarSecp::from_str_vartime(
@@ -178,7 +347,12 @@ mod tests {
             .unwrap(),
         );
         let eth_address = "0x631438556b66c4908579Eab920dc162FF58958ea";
-        let (r_inv_, s_, t_x_, t_y_, u_x_, u_y_) = eff_ecdsa_input(r, s, eth_address, msg);
+        let (r_inv_, s_, t_x_, t_y_, u_x_, u_y_) = eff_ecdsa_input(
+            r,
+            s,
+            eth_address,
+            &hash_msg(msg.as_bytes(), HashMode::Padded),
+        );
         assert_eq!(r_inv_, r_inv);
         assert_eq!(s_, s);
         assert_eq!(u_x_, u_x);
@@ -192,10 +366,45 @@ mod tests {
         let message = message_str.as_bytes();
         let true_hash_hex = "0x9e1d4c5dc7c5a0196d5d516ad5918c4eeee75df2daf6b8a55434df624b6771e3";
         let true_hash = hex::decode(&true_hash_hex[2..]).unwrap();
-        let hash = hash_msg(message);
+        let hash = hash_msg(message, HashMode::Padded);
         assert_eq!(hash, true_hash);
     }
     #[test]
+    fn test_keccak256_eip191() {
+        // hashMessage("Hello World") of ethers
+        let true_hash_hex = "0xa1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2";
+        let true_hash = hex::decode(&true_hash_hex[2..]).unwrap();
+        assert_eq!(hash_msg(b"Hello World", HashMode::Eip191), true_hash);
+
+        // the message is hashed without the zero padding
+        let message = "1703459910, 0x631438556b66c4908579Eab920dc162FF58958ea, Brad, Pitt, brad.pitt@gmail.com";
+        let hash = hash_msg(message.as_bytes(), HashMode::Eip191);
+        assert_eq!(hash, web3::signing::hash_message(message).as_bytes());
+        assert_ne!(hash, hash_msg(message.as_bytes(), HashMode::Padded));
+    }
+    #[test]
+    fn test_eff_ecdsa_input_eip191() {
+        use web3::signing::{Key, SecretKey, SecretKeyRef};
+        let secret_key = SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let key = SecretKeyRef::new(&secret_key);
+        let eth_address = format!("0x{}", hex::encode(key.address()));
+        let msg = format!("1703459910, {eth_address}, Brad, Pitt");
+        let msg_hash = hash_msg(msg.as_bytes(), HashMode::Eip191);
+        let signature = key.sign(&msg_hash, None).unwrap();
+        let r =
+            ScalarSecp::from_repr(*FieldBytes::<ECrv>::from_slice(signature.r.as_bytes())).unwrap();
+        let s =
+            ScalarSecp::from_repr(*FieldBytes::<ECrv>::from_slice(signature.s.as_bytes())).unwrap();
+
+        // U = -r^-1 * hash(m) * G
+        let (r_inv, _, _, _, u_x, u_y) = eff_ecdsa_input(r, s, &eth_address, &msg_hash);
+        let msg_hash = ScalarSecp::from_repr(*FieldBytes::<ECrv>::from_slice(&msg_hash)).unwrap();
+        let u_point = AffinePoint::<ECrv>::generator()
+            .mul(&-r_inv.mul(&msg_hash))
+            .to_affine();
+        assert_eq!((u_x, u_y), coordinates_fe(&u_point));
+    }
+    #[test]
     fn test_coordinates_biguint() {
         use num_traits::Num;
         let (x, _) = coordinates_biguint(&AffinePoint::<ECrv>::generator());
@@ -0,0 +1,397 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+
+/// Domain of the signed profile updates, the separator is a constant of `eip712_domain.circom`
+pub const DOMAIN_NAME: &str = "t3 proof system";
+pub const DOMAIN_VERSION: &str = "1";
+pub const DOMAIN_CHAIN_ID: u64 = 1;
+
+const PROFILE_UPDATE_TYPE: &str = "ProfileUpdate(uint256 timestamp,address account,string profile)";
+
+/// Byte offsets of the account and the profile fields in a profile message,
+/// `<timestamp>, <account>, <profile>`, like in `keccak_eip712.circom`
+const ACCOUNT_START: usize = 12;
+const PROFILE_START: usize = 56;
+
+/// `EIP712Domain` of the signed profile updates
+///
+/// The verifying contract and the salt are only part of the domain if they are set.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Eip712Domain {
+    pub name: String,
+    pub version: String,
+    pub chain_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verifying_contract: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+}
+impl Default for Eip712Domain {
+    fn default() -> Self {
+        Self {
+            name: DOMAIN_NAME.to_string(),
+            version: DOMAIN_VERSION.to_string(),
+            chain_id: DOMAIN_CHAIN_ID,
+            verifying_contract: None,
+            salt: None,
+        }
+    }
+}
+impl Eip712Domain {
+    /// Domain of `chain_id` (default 1), with an optional 0x prefixed verifying contract
+    /// address and 32 byte salt
+    pub fn from_config(
+        chain_id: Option<&str>,
+        verifying_contract: Option<&str>,
+        salt: Option<&str>,
+    ) -> Result<Self, String> {
+        let chain_id = match chain_id {
+            Some(chain_id) => chain_id
+                .parse()
+                .map_err(|_| format!("invalid chain id {chain_id}"))?,
+            None => DOMAIN_CHAIN_ID,
+        };
+        let hex_of_len = |value: &str, len: usize, what: &str| match decode_hex(value) {
+            Some(bytes) if bytes.len() == len => Ok(value.to_lowercase()),
+            _ => Err(format!("invalid {what} {value}")),
+        };
+        Ok(Self {
+            chain_id,
+            verifying_contract: verifying_contract
+                .map(|contract| hex_of_len(contract, 20, "verifying contract"))
+                .transpose()?,
+            salt: salt.map(|salt| hex_of_len(salt, 32, "salt")).transpose()?,
+            ..Self::default()
+        })
+    }
+    /// Fields of the `EIP712Domain` type
+    fn fields(&self) -> Vec<TypedDataField> {
+        let mut fields = vec![
+            TypedDataField::new("name", "string"),
+            TypedDataField::new("version", "string"),
+            TypedDataField::new("chainId", "uint256"),
+        ];
+        if self.verifying_contract.is_some() {
+            fields.push(TypedDataField::new("verifyingContract", "address"));
+        }
+        if self.salt.is_some() {
+            fields.push(TypedDataField::new("salt", "bytes32"));
+        }
+        fields
+    }
+    pub fn separator(&self) -> [u8; 32] {
+        let mut hasher = Keccak256::new();
+        hasher.update(Keccak256::digest(encode_type(
+            "EIP712Domain",
+            &self.fields(),
+        )));
+        hasher.update(Keccak256::digest(&self.name));
+        hasher.update(Keccak256::digest(&self.version));
+        hasher.update(uint256(self.chain_id));
+        if let Some(verifying_contract) = &self.verifying_contract {
+            hasher.update(word(&decode_hex(verifying_contract).unwrap_or_default()));
+        }
+        if let Some(salt) = &self.salt {
+            hasher.update(word(&decode_hex(salt).unwrap_or_default()));
+        }
+        hasher.finalize().into()
+    }
+    /// Fails unless the separator equals the one compiled into the circuit from the
+    /// source `eip712_domain_circom`, typed updates of another domain cannot be folded
+    pub fn check_circuit_separator(&self, eip712_domain_circom: &str) -> Result<(), String> {
+        let circuit_separator = circuit_separator(eip712_domain_circom)
+            .ok_or("eip712_domain.circom has no eip712_domain_separator bytes")?;
+        if circuit_separator != self.separator() {
+            return Err(format!(
+                "the circuit verifies the domain separator 0x{}, the configured domain has 0x{}",
+                hex::encode(circuit_separator),
+                hex::encode(self.separator())
+            ));
+        }
+        Ok(())
+    }
+    /// Whether `other` is the same domain, addresses and salts are not case sensitive
+    fn is_same(&self, other: &Eip712Domain) -> bool {
+        let same = |a: &Option<String>, b: &Option<String>| match (a, b) {
+            (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+            (a, b) => a == b,
+        };
+        self.name == other.name
+            && self.version == other.version
+            && self.chain_id == other.chain_id
+            && same(&self.verifying_contract, &other.verifying_contract)
+            && same(&self.salt, &other.salt)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TypedDataField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: String,
+}
+impl TypedDataField {
+    fn new(name: &str, field_type: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            field_type: field_type.to_string(),
+        }
+    }
+}
+
+/// `ProfileUpdate` struct of a profile message
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProfileUpdateStruct {
+    /// Unix seconds
+    pub timestamp: u64,
+    pub account: String,
+    /// profile fields of the message, e.g. `f3:toml5:hanks`
+    pub profile: String,
+}
+impl ProfileUpdateStruct {
+    /// Fields of `message`, `None` if it does not have the layout the circuit reads:
+    /// 10 timestamp digits, the account and the profile fields, separated by ", "
+    pub fn from_message(message: &str) -> Option<Self> {
+        let timestamp = message.get(..ACCOUNT_START - 2)?;
+        let account = message.get(ACCOUNT_START..PROFILE_START - 2)?;
+        if !timestamp.bytes().all(|b| b.is_ascii_digit())
+            || message.get(ACCOUNT_START - 2..ACCOUNT_START)? != ", "
+            || message.get(PROFILE_START - 2..PROFILE_START)? != ", "
+            || account_bytes(account).is_none()
+        {
+            return None;
+        }
+        Some(Self {
+            timestamp: timestamp.parse().ok()?,
+            account: account.to_string(),
+            profile: message[PROFILE_START..].to_string(),
+        })
+    }
+    /// Profile message of the struct, the inverse of `from_message`
+    pub fn message(&self) -> String {
+        format!("{:010}, {}, {}", self.timestamp, self.account, self.profile)
+    }
+    /// `hashStruct` of EIP-712
+    fn hash(&self) -> [u8; 32] {
+        let mut hasher = Keccak256::new();
+        hasher.update(Keccak256::digest(PROFILE_UPDATE_TYPE));
+        hasher.update(uint256(self.timestamp));
+        hasher.update(word(&account_bytes(&self.account).unwrap_or_default()));
+        hasher.update(Keccak256::digest(&self.profile));
+        hasher.finalize().into()
+    }
+}
+
+/// `eth_signTypedData_v4` payload of a profile update
+///
+/// The wallet signs the fields of the profile message, the server rebuilds the message
+/// from them and pads it for the circuit.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileUpdateTypedData {
+    pub types: BTreeMap<String, Vec<TypedDataField>>,
+    pub primary_type: String,
+    pub domain: Eip712Domain,
+    pub message: ProfileUpdateStruct,
+}
+impl ProfileUpdateTypedData {
+    /// Typed data a wallet signs for `profile_message` in `domain`
+    pub fn new(domain: &Eip712Domain, profile_message: &str) -> Option<Self> {
+        Some(Self {
+            types: types(domain),
+            primary_type: "ProfileUpdate".to_string(),
+            domain: domain.clone(),
+            message: ProfileUpdateStruct::from_message(profile_message)?,
+        })
+    }
+    /// Profile message of the typed data, an error for other types, another domain or
+    /// fields that do not make up a profile message
+    pub fn profile_message(&self, domain: &Eip712Domain) -> Result<String, &'static str> {
+        let profile_message = self.message.message();
+        let expected = Self::new(domain, &profile_message)
+            .filter(|expected| expected.message == self.message)
+            .ok_or("not the fields of a profile message")?;
+        if self.primary_type != expected.primary_type || self.types != expected.types {
+            return Err("not a ProfileUpdate");
+        }
+        if !self.domain.is_same(domain) {
+            return Err("not a ProfileUpdate of this domain");
+        }
+        Ok(profile_message)
+    }
+}
+
+fn types(domain: &Eip712Domain) -> BTreeMap<String, Vec<TypedDataField>> {
+    BTreeMap::from([
+        ("EIP712Domain".to_string(), domain.fields()),
+        (
+            "ProfileUpdate".to_string(),
+            vec![
+                TypedDataField::new("timestamp", "uint256"),
+                TypedDataField::new("account", "address"),
+                TypedDataField::new("profile", "string"),
+            ],
+        ),
+    ])
+}
+
+/// EIP-712 hash of the `ProfileUpdate` struct `profile_update` in `domain`
+pub fn digest(domain: &Eip712Domain, profile_update: &ProfileUpdateStruct) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update([0x19, 0x01]);
+    hasher.update(domain.separator());
+    hasher.update(profile_update.hash());
+    hasher.finalize().into()
+}
+
+fn encode_type(name: &str, fields: &[TypedDataField]) -> String {
+    let fields = fields
+        .iter()
+        .map(|field| format!("{} {}", field.field_type, field.name))
+        .collect::<Vec<_>>();
+    format!("{name}({})", fields.join(","))
+}
+
+/// Bytes returned by `eip712_domain_separator()` in the source of `eip712_domain.circom`
+fn circuit_separator(eip712_domain_circom: &str) -> Option<[u8; 32]> {
+    let (_, bytes) = eip712_domain_circom.split_once("return [")?;
+    let (bytes, _) = bytes.split_once(']')?;
+    let bytes = bytes
+        .split(',')
+        .map(|byte| byte.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<u8>>>()?;
+    bytes.try_into().ok()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    hex::decode(value.strip_prefix("0x")?).ok()
+}
+
+fn account_bytes(account: &str) -> Option<Vec<u8>> {
+    decode_hex(account).filter(|bytes| bytes.len() == 20)
+}
+
+fn uint256(value: u64) -> [u8; 32] {
+    word(&value.to_be_bytes())
+}
+
+/// `bytes` left padded to 32 bytes
+fn word(bytes: &[u8]) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[32 - bytes.len()..].copy_from_slice(bytes);
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const MESSAGE: &str = "1703459910, 0x631438556b66c4908579Eab920dc162FF58958ea, f4:Bradl4:Pitt";
+
+    #[test]
+    fn test_digest() {
+        // constants of keccak_eip712.circom and eip712_domain.circom
+        assert_eq!(
+            hex::encode(Keccak256::digest(PROFILE_UPDATE_TYPE)),
+            "47024f67a9f46cb30811436a2594364aa457ff83071caa1f3d80837dafbf98b0"
+        );
+        let separator = Eip712Domain::default().separator();
+        assert_eq!(
+            hex::encode(separator),
+            "c3bcae4c5415fa176f964a7a6cd682370465a44cbf21913996d6348dedd88a34"
+        );
+        let circuit =
+            std::fs::read_to_string("../circuits/src/merkle_tree/eip712_domain.circom").unwrap();
+        assert_eq!(circuit_separator(&circuit), Some(separator));
+        assert_eq!(
+            Eip712Domain::default().check_circuit_separator(&circuit),
+            Ok(())
+        );
+        let other_chain = Eip712Domain::from_config(Some("5"), None, None).unwrap();
+        assert!(other_chain.check_circuit_separator(&circuit).is_err());
+        assert!(Eip712Domain::default()
+            .check_circuit_separator("function eip712_domain_separator() {}")
+            .is_err());
+
+        // hashStruct = keccak256(typeHash || uint256(timestamp) || uint256(account) || keccak256(profile))
+        let profile_update = ProfileUpdateStruct::from_message(MESSAGE).unwrap();
+        let mut encoded = Keccak256::digest(PROFILE_UPDATE_TYPE).to_vec();
+        encoded.extend(uint256(1703459910));
+        encoded.extend([0u8; 12]);
+        encoded.extend(hex::decode("631438556b66c4908579Eab920dc162FF58958ea").unwrap());
+        encoded.extend(Keccak256::digest("f4:Bradl4:Pitt"));
+        let mut expected = vec![0x19, 0x01];
+        expected.extend(separator);
+        expected.extend(Keccak256::digest(encoded));
+        assert_eq!(
+            digest(&Eip712Domain::default(), &profile_update).to_vec(),
+            Keccak256::digest(expected).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_domain_from_config() {
+        assert_eq!(
+            Eip712Domain::from_config(None, None, None),
+            Ok(Eip712Domain::default())
+        );
+        let contract = "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC";
+        let salt = format!("0x{}", "ab".repeat(32));
+        let domain = Eip712Domain::from_config(Some("5"), Some(contract), Some(&salt)).unwrap();
+        assert_eq!(domain.chain_id, 5);
+        assert_eq!(domain.verifying_contract, Some(contract.to_lowercase()));
+        assert_eq!(
+            encode_type("EIP712Domain", &domain.fields()),
+            "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract,bytes32 salt)"
+        );
+        assert_ne!(domain.separator(), Eip712Domain::default().separator());
+
+        assert!(Eip712Domain::from_config(Some("one"), None, None).is_err());
+        assert!(Eip712Domain::from_config(None, Some("0x1234"), None).is_err());
+        assert!(Eip712Domain::from_config(None, None, Some(contract)).is_err());
+    }
+
+    #[test]
+    fn test_profile_message() {
+        let domain = Eip712Domain::default();
+        let typed_data = ProfileUpdateTypedData::new(&domain, MESSAGE).unwrap();
+        assert_eq!(typed_data.message.timestamp, 1703459910);
+        assert_eq!(typed_data.message.profile, "f4:Bradl4:Pitt");
+        assert_eq!(typed_data.profile_message(&domain).as_deref(), Ok(MESSAGE));
+        // as sent by a wallet library
+        let json = serde_json::to_string(&typed_data).unwrap();
+        assert!(json.contains(r#""primaryType":"ProfileUpdate""#));
+        assert!(json.contains(r#""chainId":1"#));
+        assert!(json.contains(r#""timestamp":1703459910"#));
+        let typed_data: ProfileUpdateTypedData = serde_json::from_str(&json).unwrap();
+        assert_eq!(typed_data.profile_message(&domain).as_deref(), Ok(MESSAGE));
+
+        let mut other_domain = typed_data.clone();
+        other_domain.domain.chain_id = 5;
+        assert!(other_domain.profile_message(&domain).is_err());
+        let mut other_type = typed_data.clone();
+        other_type.primary_type = "Mail".to_string();
+        assert!(other_type.profile_message(&domain).is_err());
+        let mut short_account = typed_data;
+        short_account.message.account = "0x6314".to_string();
+        assert!(short_account.profile_message(&domain).is_err());
+
+        assert_eq!(ProfileUpdateTypedData::new(&domain, "1703459910"), None);
+    }
+
+    #[test]
+    fn test_profile_message_with_verifying_contract() {
+        let contract = "0xcccccccccccccccccccccccccccccccccccccccc";
+        let domain = Eip712Domain::from_config(None, Some(contract), None).unwrap();
+        let mut typed_data = ProfileUpdateTypedData::new(&domain, MESSAGE).unwrap();
+        assert_eq!(typed_data.types["EIP712Domain"].len(), 4);
+        // checksummed by the wallet
+        typed_data.domain.verifying_contract = Some(contract.to_uppercase().replace("0X", "0x"));
+        assert_eq!(typed_data.profile_message(&domain).as_deref(), Ok(MESSAGE));
+        assert!(typed_data
+            .profile_message(&Eip712Domain::default())
+            .is_err());
+    }
+}
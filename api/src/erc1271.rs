@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use crate::server::{SignatureVerifier, VerifyFuture};
+
+/// `isValidSignature(bytes32,bytes)` selector, returned by the wallet contract for a valid signature
+pub const MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// ERC-1271 verification of smart-contract wallet signatures through `eth_call` on a JSON-RPC node
+pub struct Erc1271Verifier {
+    rpc_url: String,
+    client: reqwest::Client,
+}
+impl Erc1271Verifier {
+    pub fn new(rpc_url: &str) -> Self {
+        Self {
+            rpc_url: rpc_url.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+    async fn is_valid_signature(
+        &self,
+        address: &str,
+        m_hash: &[u8],
+        signature: &[u8],
+    ) -> Result<bool> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_call",
+            "params": [
+                { "to": address, "data": format!("0x{}", hex::encode(call_data(m_hash, signature))) },
+                "latest"
+            ],
+        });
+        let response: Value = self
+            .client
+            .post(&self.rpc_url)
+            .json(&request)
+            .send()
+            .await?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("eth_call failed: {error}"));
+        }
+        let result = response["result"]
+            .as_str()
+            .ok_or(anyhow!("eth_call returned no result"))?;
+        let result = hex::decode(result.strip_prefix("0x").unwrap_or(result))?;
+        Ok(result.len() >= 4 && result[..4] == MAGIC_VALUE)
+    }
+}
+impl SignatureVerifier for Erc1271Verifier {
+    fn verify<'a>(
+        &'a self,
+        address: &'a str,
+        m_hash: &'a [u8],
+        signature: &'a [u8],
+    ) -> VerifyFuture<'a> {
+        Box::pin(self.is_valid_signature(address, m_hash, signature))
+    }
+}
+
+/// ABI encoded call of `isValidSignature(m_hash, signature)`
+fn call_data(m_hash: &[u8], signature: &[u8]) -> Vec<u8> {
+    let mut data = MAGIC_VALUE.to_vec();
+    let word = |value: usize| {
+        let mut bytes = [0u8; 32];
+        bytes[24..].copy_from_slice(&(value as u64).to_be_bytes());
+        bytes
+    };
+    let offset = word(0x40);
+    let length = word(signature.len());
+    data.extend_from_slice(m_hash);
+    data.extend_from_slice(&offset);
+    data.extend_from_slice(&length);
+    data.extend_from_slice(signature);
+    data.resize(data.len() + (32 - signature.len() % 32) % 32, 0);
+    data
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use axum::{routing::post, Json, Router};
+    use tokio::net::TcpListener;
+
+    pub const WALLET: &str = "0x53e16f6d33c1809c14ba489a6917e9de849ab20c";
+    /// the only signature the mock wallet accepts
+    pub const WALLET_SIGNATURE: &str = "0x01020304";
+
+    /// JSON-RPC node with a single wallet contract, returns its url
+    pub async fn run_mock_node() -> String {
+        async fn handle(Json(request): Json<Value>) -> Json<Value> {
+            let call = &request["params"][0];
+            if call["to"] != WALLET {
+                return Json(json!({ "jsonrpc": "2.0", "id": request["id"], "result": "0x" }));
+            }
+            let data = hex::decode(&call["data"].as_str().unwrap()[2..]).unwrap();
+            let signature = &data[4 + 3 * 32..];
+            let accepted = hex::decode(&WALLET_SIGNATURE[2..]).unwrap();
+            let result = if signature.starts_with(&accepted) {
+                format!("0x{}{}", hex::encode(MAGIC_VALUE), "00".repeat(28))
+            } else {
+                format!("0x{}", "00".repeat(32))
+            };
+            Json(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, Router::new().route("/", post(handle)))
+                .await
+                .unwrap()
+        });
+        url
+    }
+
+    #[test]
+    fn test_call_data() {
+        let data = call_data(&[0xaa; 32], &[1, 2, 3]);
+        assert_eq!(data.len(), 4 + 4 * 32);
+        assert_eq!(data[..4], MAGIC_VALUE);
+        assert_eq!(data[4..36], [0xaa; 32]);
+        assert_eq!(data[67], 0x40);
+        assert_eq!(data[99], 3);
+        assert_eq!(data[100..103], [1, 2, 3]);
+        assert!(data[103..].iter().all(|b| *b == 0));
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let verifier = Erc1271Verifier::new(&run_mock_node().await);
+        let m_hash = [0u8; 32];
+        let signature = hex::decode(&WALLET_SIGNATURE[2..]).unwrap();
+        assert!(verifier.verify(WALLET, &m_hash, &signature).await.unwrap());
+        assert!(!verifier.verify(WALLET, &m_hash, &[5, 6]).await.unwrap());
+        // not a wallet contract
+        let eoa = "0x631438556b66c4908579eab920dc162ff58958ea";
+        assert!(!verifier.verify(eoa, &m_hash, &signature).await.unwrap());
+
+        let unreachable = Erc1271Verifier::new("http://127.0.0.1:1");
+        assert!(unreachable
+            .verify(WALLET, &m_hash, &signature)
+            .await
+            .is_err());
+    }
+}
@@ -1,173 +1,441 @@
-use std::{collections::HashMap, env::current_dir, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use nova_scotia::{circom::circuit::R1CS, FileLocation, F};
+use nova_scotia::{circom::circuit::R1CS, F};
 
+use ff::PrimeField;
 use nova_snark::{traits::Group, PublicParams, RecursiveSNARK};
 
 use serde::{Deserialize, Serialize};
 use tokio::{
-    sync::mpsc::{Receiver, Sender},
-    time::Instant,
+    sync::mpsc::{Receiver, Sender, UnboundedSender},
+    time::{Duration, Instant, Interval, MissedTickBehavior},
 };
-use tracing::debug;
-
-use crate::{proof_system_message::ProofSystemMessage, C1, C2, G1, G2};
-use nova_scotia::circom::reader::generate_witness_from_wasm;
+use tracing::{debug, error};
 
-use std::path::Path;
+use crate::checkpoint::{Checkpoint, CheckpointStore};
+use crate::eff_ecdsa_input::{fe_to_biguint, fe_to_hex};
+use crate::merkle_tree_updater::StepOutcome;
+use crate::pipeline_events::{send_event, EventSender, PipelineEvent};
+use crate::proof_system_message::{ProofSystemMessage, ProofSystemStep};
+use crate::public_params::{r1cs_digest, R1csDigest};
+use crate::witness_calculator::WitnessCalculator;
+use crate::{C1, C2, G1, G2};
+use merkle_tree::{Hash, Key};
+use nova_scotia::circom::reader::load_witness_from_file;
 
+use anyhow::{anyhow, Result};
 use nova_scotia::circom::circuit::CircomCircuit;
 use nova_snark::traits::circuit::TrivialCircuit;
-use num_bigint::BigInt;
+use num_bigint::{BigInt, BigUint};
 use num_traits::Num;
 use serde_json::Value;
 
-pub const NUM_FOLDS: usize = 2;
+/// Recursive SNARK after `num_steps` folded steps, sent to the compressed proof builder
+#[derive(Clone)]
+pub struct FoldedProof {
+    pub recursive_snark: RecursiveSNARK<G1, G2, C1<G1>, C2<G2>>,
+    pub num_steps: usize,
+    /// z_n
+    pub public_output: Vec<<G1 as Group>::Scalar>,
+}
+
+/// When the folder sends the recursive SNARK to the compressed proof builder
+///
+/// A compression requested through the compression trigger is sent with any policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionPolicy {
+    /// after every n folded steps
+    EveryNSteps(usize),
+    /// periodically, if steps were folded since the last compression
+    Interval(Duration),
+    /// only on request
+    OnDemand,
+}
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        CompressionPolicy::EveryNSteps(2)
+    }
+}
+impl CompressionPolicy {
+    /// `config` is one of "steps:<n>", "seconds:<t>" or "on_demand"
+    pub fn from_config(config: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid compression policy: {config}");
+        match config.split_once(':') {
+            Some(("steps", n)) => match n.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(CompressionPolicy::EveryNSteps(n)),
+                _ => Err(invalid()),
+            },
+            Some(("seconds", t)) => match t.parse::<u64>() {
+                Ok(t) if t > 0 => Ok(CompressionPolicy::Interval(Duration::from_secs(t))),
+                _ => Err(invalid()),
+            },
+            None if config == "on_demand" => Ok(CompressionPolicy::OnDemand),
+            _ => Err(invalid()),
+        }
+    }
+}
 
 pub struct IVCProofFolder {
-    rx: Receiver<ProofSystemMessage>,
-    tx: Sender<RecursiveSNARK<G1, G2, C1<G1>, C2<G2>>>,
+    rx: Receiver<ProofSystemStep>,
+    tx: Sender<FoldedProof>,
     pp: Arc<PublicParams<G1, G2, C1<G1>, C2<G2>>>,
     r1cs: R1CS<<G1 as Group>::Scalar>,
+    r1cs_digest: R1csDigest,
+    witness_generator: WitnessGenerator,
     start_public_input: Vec<<G1 as Group>::Scalar>,
     counter: usize,
+    recursive_snark: Option<RecursiveSNARK<G1, G2, C1<G1>, C2<G2>>>,
+    current_public_input: Vec<<G1 as Group>::Scalar>,
+    /// (key, leaf hash) of the folded tree updates in insertion order
+    leaves: Vec<(Key, Hash)>,
+    /// profile message of every folded leaf
+    messages: HashMap<Key, String>,
+    checkpoint_store: Option<CheckpointStore>,
+    checkpoint_interval: usize,
+    tx_outcome: Option<UnboundedSender<StepOutcome>>,
+    events: Option<EventSender>,
+    compression_policy: CompressionPolicy,
+    compression_trigger: Option<Receiver<()>>,
+    /// step count of the last recursive SNARK sent for compression
+    last_compressed_step: usize,
 }
 impl IVCProofFolder {
     pub fn new(
-        rx: Receiver<ProofSystemMessage>,
-        tx: Sender<RecursiveSNARK<G1, G2, C1<G1>, C2<G2>>>,
+        rx: Receiver<ProofSystemStep>,
+        tx: Sender<FoldedProof>,
         pp: Arc<PublicParams<G1, G2, C1<G1>, C2<G2>>>,
         r1cs: R1CS<<G1 as Group>::Scalar>,
+        witness_generator: WitnessGenerator,
         start_public_input: Vec<<G1 as Group>::Scalar>,
     ) -> Self {
         Self {
             rx,
             tx,
             pp,
+            r1cs_digest: r1cs_digest(&r1cs),
             r1cs,
+            witness_generator,
+            current_public_input: start_public_input.clone(),
             start_public_input,
             counter: 0,
+            recursive_snark: None,
+            leaves: vec![],
+            messages: HashMap::new(),
+            checkpoint_store: None,
+            checkpoint_interval: 1,
+            tx_outcome: None,
+            events: None,
+            compression_policy: CompressionPolicy::default(),
+            compression_trigger: None,
+            last_compressed_step: 0,
         }
     }
+    /// Send the recursive SNARK for compression according to `compression_policy`
+    /// and whenever `compression_trigger` receives a request
+    pub fn with_compression(
+        mut self,
+        compression_policy: CompressionPolicy,
+        compression_trigger: Receiver<()>,
+    ) -> Self {
+        self.compression_policy = compression_policy;
+        self.compression_trigger = Some(compression_trigger);
+        self
+    }
+    /// Report every folded or rejected step to `tx_outcome`
+    ///
+    /// The channel is unbounded: the tree updater feeds the folder, a folder waiting for
+    /// the updater to take its outcomes could block the whole pipeline.
+    pub fn with_step_outcomes(mut self, tx_outcome: UnboundedSender<StepOutcome>) -> Self {
+        self.tx_outcome = Some(tx_outcome);
+        self
+    }
+    /// Publish every folded step to `events`
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+    /// Save a checkpoint to `checkpoint_store` every `checkpoint_interval` steps
+    pub fn with_checkpoints(
+        mut self,
+        checkpoint_store: CheckpointStore,
+        checkpoint_interval: usize,
+    ) -> Self {
+        assert!(
+            checkpoint_interval > 0,
+            "checkpoint interval must be positive"
+        );
+        self.checkpoint_store = Some(checkpoint_store);
+        self.checkpoint_interval = checkpoint_interval;
+        self
+    }
+    /// Continue folding from a checkpoint that has been verified with `Checkpoint::verify`
+    ///
+    /// The folder is left unchanged if the checkpoint cannot be decoded.
+    pub fn resume(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        debug!("Resuming from checkpoint at step {}", checkpoint.num_steps);
+        let public_output = checkpoint.public_output()?;
+        let leaves = checkpoint.leaves()?;
+        let messages = checkpoint.messages()?;
+        self.counter = checkpoint.num_steps;
+        self.current_public_input = public_output;
+        self.leaves = leaves;
+        self.messages = messages;
+        self.recursive_snark = Some(checkpoint.recursive_snark);
+        Ok(())
+    }
     pub async fn run(&mut self) {
         debug!("Proof Folder started");
-        let witness_generator_file =
-            FileLocation::PathBuf("../circuits/src/merkle_tree/ivc_js/ivc.wasm".into());
-        let root = current_dir().unwrap();
-        let witness_generator_output = root.join("circom_witness.wtns");
-        let start_public_input_hex = self
-            .start_public_input
+        let circuit_secondary = TrivialCircuit::default();
+        // let z0_secondary = vec![<G2 as Group>::Scalar::ZERO];
+        let z0_secondary = vec![<G2 as Group>::Scalar::zero()];
+
+        let mut compression_interval = match self.compression_policy {
+            CompressionPolicy::Interval(period) => {
+                let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+                // folding blocks the task, don't catch up on the missed ticks afterwards
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                Some(interval)
+            }
+            _ => None,
+        };
+
+        loop {
+            tokio::select! {
+                step = self.rx.recv() => match step {
+                    Some(step) => self.handle_step(step, &circuit_secondary, &z0_secondary).await,
+                    None => break,
+                },
+                _ = tick(&mut compression_interval) => self.send_for_compression().await,
+                Some(()) = recv_trigger(&mut self.compression_trigger) => {
+                    debug!("compression requested");
+                    self.send_for_compression().await
+                }
+            }
+        }
+    }
+    async fn handle_step(
+        &mut self,
+        step: ProofSystemStep,
+        circuit_secondary: &C2<G2>,
+        z0_secondary: &[<G2 as Group>::Scalar],
+    ) {
+        debug!("received update");
+        if self.is_stale(&step) {
+            // built on top of a reverted tree update, the updater sends it again
+            debug!("discarding step that does not start from the current root");
+            return;
+        }
+        match self.fold_step(step.msg, circuit_secondary, z0_secondary) {
+            Ok(()) => {
+                for tree_update in step.tree_updates.iter() {
+                    let key = tree_update.key();
+                    match self.leaves.iter_mut().find(|(k, _)| *k == key) {
+                        Some(leaf) => leaf.1 = tree_update.new_leaf,
+                        None => self.leaves.push((key.clone(), tree_update.new_leaf)),
+                    }
+                    self.messages.insert(
+                        key,
+                        tree_update.update.profile_update.unparsed_profile.clone(),
+                    );
+                }
+                if let Some(last) = step.tree_updates.last() {
+                    send_event(
+                        &self.events,
+                        PipelineEvent::StepFolded {
+                            step: self.counter,
+                            addresses: step
+                                .tree_updates
+                                .iter()
+                                .map(|tree_update| tree_update.update.eth_address().to_lowercase())
+                                .collect(),
+                            root: fe_to_hex(&last.new_root),
+                        },
+                    );
+                }
+                self.report(StepOutcome::Folded {
+                    step: self.counter,
+                    tree_updates: step.tree_updates,
+                });
+                let compress = matches!(
+                    self.compression_policy,
+                    CompressionPolicy::EveryNSteps(n) if self.counter % n == 0
+                );
+                if compress {
+                    self.send_for_compression().await;
+                }
+                if self.counter % self.checkpoint_interval == 0 {
+                    self.save_checkpoint();
+                }
+            }
+            Err(e) => {
+                error!("step {} rejected: {e}", self.counter + 1);
+                self.report(StepOutcome::Failed {
+                    tree_updates: step.tree_updates,
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+    /// Send the current recursive SNARK to the compressed proof builder,
+    /// unless it has been sent already
+    async fn send_for_compression(&mut self) {
+        let Some(recursive_snark) = &self.recursive_snark else {
+            return;
+        };
+        if self.counter == self.last_compressed_step {
+            debug!("no steps folded since the last compression");
+            return;
+        }
+        let folded_proof = FoldedProof {
+            recursive_snark: recursive_snark.clone(),
+            num_steps: self.counter,
+            public_output: self.current_public_input.clone(),
+        };
+        let _ = self.tx.send(folded_proof).await;
+        self.last_compressed_step = self.counter;
+    }
+    /// Fold `msg` into the recursive SNARK and verify the result
+    ///
+    /// The folding state is only updated when the new proof verifies,
+    /// so a rejected step leaves the last good state in place.
+    fn fold_step(
+        &mut self,
+        msg: ProofSystemMessage,
+        circuit_secondary: &C2<G2>,
+        z0_secondary: &[<G2 as Group>::Scalar],
+    ) -> Result<()> {
+        let start = Instant::now();
+        let current_public_input_hex = self
+            .current_public_input
             .iter()
             .map(|&x| format!("{:?}", x).strip_prefix("0x").unwrap().to_string())
             .collect::<Vec<String>>();
-        let update = self.rx.recv().await.unwrap();
-        debug!("received first update");
-        let start = Instant::now();
-        let witness = compute_witness::<G1, G2>(
-            start_public_input_hex.clone(),
-            update,
-            witness_generator_file.clone(),
-            &witness_generator_output,
-        );
+        let witness =
+            compute_witness::<G1, G2>(current_public_input_hex, msg, &mut self.witness_generator)?;
         let duration = start.elapsed();
         debug!("witness creation time {:?}", duration);
         let circuit = CircomCircuit {
             r1cs: self.r1cs.clone(),
             witness: Some(witness),
         };
-        let circuit_secondary = TrivialCircuit::default();
-        // let z0_secondary = vec![<G2 as Group>::Scalar::ZERO];
-        let z0_secondary = vec![<G2 as Group>::Scalar::zero()];
+
+        let mut recursive_snark = match &self.recursive_snark {
+            Some(recursive_snark) => recursive_snark.clone(),
+            None => {
+                let start = Instant::now();
+                let recursive_snark = RecursiveSNARK::<G1, G2, C1<G1>, C2<G2>>::new(
+                    self.pp.as_ref(),
+                    &circuit,
+                    circuit_secondary,
+                    self.start_public_input.clone(),
+                    z0_secondary.to_vec(),
+                );
+                let duration = start.elapsed();
+                debug!("Recursive SNARK creation time {:?}", duration);
+                recursive_snark
+            }
+        };
+
+        // fold the new proofsystem message
+
         let start = Instant::now();
-        let mut recursive_snark = RecursiveSNARK::<G1, G2, C1<G1>, C2<G2>>::new(
-            self.pp.as_ref(),
-            &circuit,
-            &circuit_secondary,
-            self.start_public_input.clone(),
-            z0_secondary.clone(),
-        );
+        recursive_snark
+            .prove_step(
+                self.pp.as_ref(),
+                &circuit,
+                circuit_secondary,
+                self.start_public_input.clone(),
+                z0_secondary.to_vec(),
+            )
+            .map_err(|e| anyhow!("prove_step failed: {e:?}"))?;
         let duration = start.elapsed();
-        debug!("Recursive SNARK creation time {:?}", duration);
+        debug!("nova prove time {:?}", duration);
+        debug!(
+            "recursive snark proof size {:.2} Mb",
+            serde_json::to_string(&recursive_snark).unwrap().len() as f64 / 1_000_000.0
+        );
         let start = Instant::now();
-        let _ = recursive_snark.prove_step(
+        let res = recursive_snark.verify(
             self.pp.as_ref(),
-            &circuit,
-            &circuit_secondary,
-            self.start_public_input.clone(),
-            z0_secondary.clone(),
+            self.counter + 1,
+            &self.start_public_input,
+            z0_secondary,
         );
-        let duration = start.elapsed();
-        debug!("folding time {:?}", duration);
         debug!(
-            "recursive snark proof size {:.2} Mb",
-            serde_json::to_string(&recursive_snark).unwrap().len() as f64 / 1_000_000.0
+            "RecursiveSNARK::verify: {:?}, took {:?}",
+            res.is_ok(),
+            start.elapsed()
         );
-        let current_public_output = circuit.get_public_outputs();
-        let mut current_public_input: Vec<String> = current_public_output
-            .iter()
-            .map(|&x| format!("{:?}", x).strip_prefix("0x").unwrap().to_string())
-            .collect();
+        res.map_err(|e| anyhow!("RecursiveSNARK::verify failed: {e:?}"))?;
+
+        self.recursive_snark = Some(recursive_snark);
         self.counter += 1;
-        if self.counter % NUM_FOLDS == 0 {
-            let _ = self.tx.send(recursive_snark.clone()).await;
+        self.current_public_input = circuit.get_public_outputs();
+        Ok(())
+    }
+    /// Whether the step was built on a Merkle root other than the current z_i[0]
+    fn is_stale(&self, step: &ProofSystemStep) -> bool {
+        let Some(first) = step.tree_updates.first() else {
+            return false;
+        };
+        let current_root = format!("{:?}", self.current_public_input[0]);
+        let current_root =
+            BigUint::from_str_radix(current_root.strip_prefix("0x").unwrap(), 16).unwrap();
+        fe_to_biguint(&first.prev_root) != current_root
+    }
+    fn report(&self, outcome: StepOutcome) {
+        if let Some(tx_outcome) = &self.tx_outcome {
+            let _ = tx_outcome.send(outcome);
         }
-
-        while let Some(update) = self.rx.recv().await {
-            debug!("received update");
-            let start = Instant::now();
-
-            let witness = compute_witness::<G1, G2>(
-                current_public_input.clone(),
-                update,
-                witness_generator_file.clone(),
-                &witness_generator_output,
-            );
-            let duration = start.elapsed();
-            debug!("witness creation time {:?}", duration);
-            let circuit = CircomCircuit {
-                r1cs: self.r1cs.clone(),
-                witness: Some(witness),
-            };
-
-            // fold the new proofsystem message
-
-            let start = Instant::now();
-            let _ = recursive_snark.prove_step(
-                self.pp.as_ref(),
-                &circuit,
-                &circuit_secondary,
-                self.start_public_input.clone(),
-                z0_secondary.clone(),
-            );
-            let duration = start.elapsed();
-            debug!("nova prove time {:?}", duration);
-            debug!(
-                "recursive snark proof size {:.2} Mb",
-                serde_json::to_string(&recursive_snark).unwrap().len() as f64 / 1_000_000.0
-            );
-            self.counter += 1;
-            if self.counter % NUM_FOLDS == 0 {
-                let _ = self.tx.send(recursive_snark.clone()).await;
-            }
-            let current_public_output = circuit.get_public_outputs();
-            current_public_input = current_public_output
-                .iter()
-                .map(|&x| format!("{:?}", x).strip_prefix("0x").unwrap().to_string())
-                .collect();
-            let start = Instant::now();
-            let res = recursive_snark.verify(
-                self.pp.as_ref(),
+    }
+    fn save_checkpoint(&self) {
+        let (Some(checkpoint_store), Some(recursive_snark)) =
+            (&self.checkpoint_store, &self.recursive_snark)
+        else {
+            return;
+        };
+        let start = Instant::now();
+        let checkpoint = Checkpoint::new(
+            recursive_snark.clone(),
+            self.counter,
+            &self.r1cs_digest,
+            &self.current_public_input,
+            &self.leaves,
+            &self.messages,
+        );
+        match checkpoint_store.save(&checkpoint) {
+            Ok(()) => debug!(
+                "checkpoint at step {} saved in {:?}",
                 self.counter,
-                &self.start_public_input,
-                &z0_secondary,
-            );
-            debug!(
-                "RecursiveSNARK::verify: {:?}, took {:?}",
-                res.is_ok(),
                 start.elapsed()
-            );
-            assert!(res.is_ok());
+            ),
+            Err(e) => error!("failed to save checkpoint at step {}: {e}", self.counter),
+        }
+    }
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
         }
+        None => std::future::pending().await,
+    }
+}
+
+async fn recv_trigger(trigger: &mut Option<Receiver<()>>) -> Option<()> {
+    match trigger {
+        Some(trigger) => trigger.recv().await,
+        None => std::future::pending().await,
     }
 }
 
@@ -178,37 +446,202 @@ struct CircomInput {
     #[serde(flatten)]
     extra: HashMap<String, Value>,
 }
+
+/// Circom artifact used to compute the witness of a folding step
+pub enum WitnessGenerator {
+    /// `<circuit>_js/<circuit>.wasm`, run with node through `generate_witness.js`
+    Wasm(PathBuf),
+    /// `<circuit>_cpp/<circuit>` binary built from the circom C++ output (`circom --c` and `make`),
+    /// its inputs are read from the symbols file `<circuit>.sym` of the same compilation (`--sym`)
+    Native { binary: PathBuf, sym: PathBuf },
+    /// `<circuit>_js/<circuit>.wasm` loaded once and run in-process, without temporary files
+    InProcess(WitnessCalculator),
+}
+impl WitnessGenerator {
+    /// Witness generator for `circuit_name` compiled to `circuit_dir`
+    ///
+    /// `kind` is one of "wasm", "native" or "in_process"
+    pub fn from_config(kind: &str, circuit_dir: &str, circuit_name: &str) -> Result<Self, String> {
+        let witness_wasm = PathBuf::from(format!(
+            "{circuit_dir}/{circuit_name}_js/{circuit_name}.wasm"
+        ));
+        match kind {
+            "wasm" => Ok(WitnessGenerator::Wasm(witness_wasm)),
+            "native" => Ok(WitnessGenerator::Native {
+                binary: format!("{circuit_dir}/{circuit_name}_cpp/{circuit_name}").into(),
+                sym: format!("{circuit_dir}/{circuit_name}.sym").into(),
+            }),
+            "in_process" => WitnessCalculator::from_file(&witness_wasm)
+                .map(WitnessGenerator::InProcess)
+                .map_err(|e| format!("failed to load {}: {e}", witness_wasm.display())),
+            _ => Err(format!("unknown witness generator: {kind}")),
+        }
+    }
+    /// Whether the circuit has the input signal `name`
+    pub fn has_input(&self, name: &str) -> Result<bool> {
+        Ok(self.input_size(name)? > 0)
+    }
+    /// Number of field elements of the input signal `name`, 0 if the circuit has no such input
+    pub fn input_size(&self, name: &str) -> Result<usize> {
+        match self {
+            WitnessGenerator::Wasm(witness_wasm) => {
+                WitnessCalculator::from_file(witness_wasm)?.input_size(name)
+            }
+            WitnessGenerator::Native { sym, .. } => {
+                let symbols = fs::read_to_string(sym).map_err(|e| {
+                    anyhow!(
+                        "failed to read {}, compile the circuit with --sym: {e}",
+                        sym.display()
+                    )
+                })?;
+                Ok(sym_input_size(&symbols, name))
+            }
+            WitnessGenerator::InProcess(calculator) => calculator.input_size(name),
+        }
+    }
+}
+
+/// Number of elements of the signal `main.<name>` in the circom symbols file `symbols`
+///
+/// Every line is `<label id>,<signal id>,<component id>,<name>`, with one line per element
+/// of an array signal, e.g. `main.siblings[0]`.
+fn sym_input_size(symbols: &str, name: &str) -> usize {
+    let signal = format!("main.{name}");
+    let element = format!("main.{name}[");
+    symbols
+        .lines()
+        .filter_map(|line| line.splitn(4, ',').nth(3))
+        .filter(|symbol| *symbol == signal || symbol.starts_with(&element))
+        .count()
+}
+
+static WITNESS_FILES_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Input and output files of a single witness generation
+///
+/// Every instance gets its own paths in the system temp directory, so several folders
+/// (or tests) can generate witnesses side by side. The files are removed on drop,
+/// i.e. also when witness generation fails.
+struct WitnessFiles {
+    input: PathBuf,
+    output: PathBuf,
+}
+impl WitnessFiles {
+    fn new() -> Self {
+        let id = format!(
+            "{}_{}",
+            std::process::id(),
+            WITNESS_FILES_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let dir = std::env::temp_dir();
+        Self {
+            input: dir.join(format!("circom_input_{id}.json")),
+            output: dir.join(format!("circom_witness_{id}.wtns")),
+        }
+    }
+}
+impl Drop for WitnessFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.input);
+        let _ = fs::remove_file(&self.output);
+    }
+}
+
 fn compute_witness<G1, G2>(
     current_public_input: Vec<String>,
     private_input: HashMap<String, Value>,
-    witness_generator_file: FileLocation,
-    witness_generator_output: &Path,
-) -> Vec<<G1 as Group>::Scalar>
+    witness_generator: &mut WitnessGenerator,
+) -> Result<Vec<<G1 as Group>::Scalar>>
 where
     G1: Group<Base = <G2 as Group>::Scalar>,
     G2: Group<Base = <G1 as Group>::Scalar>,
 {
     let decimal_stringified_input: Vec<String> = current_public_input
         .iter()
-        .map(|x| BigInt::from_str_radix(x, 16).unwrap().to_str_radix(10))
-        .collect();
+        .map(|x| BigInt::from_str_radix(x, 16).map(|x| x.to_str_radix(10)))
+        .collect::<Result<_, _>>()?;
 
     let input = CircomInput {
         step_in: decimal_stringified_input.clone(),
         extra: private_input.clone(),
     };
 
-    let is_wasm = true;
-    let input_json = serde_json::to_string(&input).unwrap();
+    let input_json = serde_json::to_string(&input)?;
 
-    if is_wasm {
-        generate_witness_from_wasm::<F<G1>>(
-            &witness_generator_file,
-            &input_json,
-            &witness_generator_output,
-        )
-    } else {
-        unimplemented!()
+    match witness_generator {
+        WitnessGenerator::Wasm(witness_wasm) => {
+            generate_witness_from_wasm::<F<G1>>(witness_wasm, &input_json, &WitnessFiles::new())
+        }
+        WitnessGenerator::Native { binary, .. } => {
+            generate_witness_from_bin::<F<G1>>(binary, &input_json, &WitnessFiles::new())
+        }
+        WitnessGenerator::InProcess(calculator) => {
+            calculator.calculate_witness::<F<G1>>(&input_json)
+        }
+    }
+}
+
+/// Same as `nova_scotia::circom::reader::generate_witness_from_wasm`
+/// but with the input and output files of `witness_files` instead of fixed paths in the current directory
+fn generate_witness_from_wasm<Fr: PrimeField>(
+    witness_wasm: &Path,
+    witness_input_json: &str,
+    witness_files: &WitnessFiles,
+) -> Result<Vec<Fr>> {
+    fs::write(&witness_files.input, witness_input_json)?;
+    let witness_js = witness_wasm
+        .parent()
+        .ok_or_else(|| anyhow!("invalid witness wasm path"))?
+        .join("generate_witness.js");
+    let output = Command::new("node")
+        .arg(&witness_js)
+        .arg(witness_wasm)
+        .arg(&witness_files.input)
+        .arg(&witness_files.output)
+        .output()?;
+    log_witness_generator_output(&output);
+    load_generated_witness(&output, witness_files)
+}
+
+/// Same as `nova_scotia::circom::reader::generate_witness_from_bin`
+/// but with the input and output files of `witness_files`
+fn generate_witness_from_bin<Fr: PrimeField>(
+    witness_bin: &Path,
+    witness_input_json: &str,
+    witness_files: &WitnessFiles,
+) -> Result<Vec<Fr>> {
+    fs::write(&witness_files.input, witness_input_json)?;
+    let output = Command::new(witness_bin)
+        .arg(&witness_files.input)
+        .arg(&witness_files.output)
+        .output()?;
+    log_witness_generator_output(&output);
+    load_generated_witness(&output, witness_files)
+}
+
+/// The witness written by a generator process, an error if the process failed,
+/// e.g. because the input does not satisfy the circuit constraints
+fn load_generated_witness<Fr: PrimeField>(
+    output: &Output,
+    witness_files: &WitnessFiles,
+) -> Result<Vec<Fr>> {
+    if !output.status.success() || !witness_files.output.exists() {
+        return Err(anyhow!(
+            "witness generation failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(load_witness_from_file(&witness_files.output))
+}
+
+fn log_witness_generator_output(output: &Output) {
+    if !output.stdout.is_empty() || !output.stderr.is_empty() {
+        debug!(
+            "witness generator output: {}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
     }
 }
 
@@ -216,17 +649,40 @@ where
 mod tests {
 
     use super::*;
-    use crate::get_pp;
-    use crate::proof_system_message::make_proof_system_msg;
+    use crate::eff_ecdsa_input::HashMode;
+    use crate::merkle_tree_updater::{address_key, MerkleTreeUpdater};
     use crate::proof_system_message::tests::{
-        dummy_first_hash, dummy_siblings, dummy_signature, dummy_user_profile_update, zero_hash,
+        dummy_first_hash, dummy_siblings, dummy_signature, dummy_user_profile_update,
+        signed_test_update, zero_hash,
+    };
+    use crate::proof_system_message::{
+        make_proof_system_msg, ProofSystemMessageBuilder, OPTIONAL_INPUTS,
     };
     use crate::server::SignedUserProfileUpdate;
-    use ff::PrimeField;
+    use crate::MERKLE_TREE_DEPTH;
+    use crate::{get_pp, StaleParamsPolicy};
+    use common::utils::bits::pad_msg;
+    use common::BIT_SIZE;
+    use merkle_tree::MerkleTree;
     use nova_scotia::circom::reader::load_r1cs;
+    use nova_scotia::FileLocation;
     use tracing::debug;
     use tracing_test::traced_test;
 
+    /// Step input `witness_generator` accepts, the committed artifact may predate
+    /// `OPTIONAL_INPUTS`
+    fn step_input(
+        mut msg: ProofSystemMessage,
+        witness_generator: &WitnessGenerator,
+    ) -> ProofSystemMessage {
+        for input in OPTIONAL_INPUTS {
+            if !witness_generator.has_input(input).unwrap() {
+                msg.remove(input);
+            }
+        }
+        msg
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_run() {
@@ -234,24 +690,40 @@ mod tests {
         let (tx, rx_folder) = tokio::sync::mpsc::channel(100);
         let (tx_folder, mut rx) = tokio::sync::mpsc::channel(100);
 
-        let update = dummy_user_profile_update();
-        let signature = dummy_signature();
-        let signed_update = SignedUserProfileUpdate::from_profile_update(update, signature);
-        let prev_leaf_hash = zero_hash();
-        let siblings = dummy_siblings();
-        let prev_leaf_hash_new = dummy_first_hash();
-
-        let mut proof_system_msg =
-            make_proof_system_msg(&signed_update, &prev_leaf_hash, &siblings);
-        let proof_system_msg1 =
-            make_proof_system_msg(&signed_update, &prev_leaf_hash_new, &siblings);
+        // a new profile and a later version of it, the circuit rejects replays of the same message
+        let first = signed_test_update(1703459910);
+        let second = signed_test_update(1703459911);
+        let mut tree = MerkleTree::new(MERKLE_TREE_DEPTH);
+        let mut step_msg = |update: &SignedUserProfileUpdate, prev_message: Option<&str>| {
+            let key = address_key(&update.eth_address());
+            let prev_leaf_hash = tree.get_leaf(&key).unwrap();
+            let padded_msg = pad_msg(update.profile_update.unparsed_profile.as_bytes(), BIT_SIZE);
+            let (_, _, siblings) = tree.insert_leaf(&key, &padded_msg).unwrap();
+            make_proof_system_msg(
+                update,
+                &prev_leaf_hash,
+                prev_message,
+                &siblings,
+                HashMode::Padded,
+            )
+        };
+        let witness_generator =
+            WitnessGenerator::Wasm("../circuits/src/merkle_tree/ivc_js/ivc.wasm".into());
+        let mut proof_system_msg = step_input(step_msg(&first, None), &witness_generator);
+        let proof_system_msg1 = step_input(
+            step_msg(
+                &second,
+                Some(first.profile_update.unparsed_profile.as_str()),
+            ),
+            &witness_generator,
+        );
         debug!("Created proof system message {:?}", proof_system_msg);
 
         let circuit_file = "../circuits/src/merkle_tree/ivc.r1cs";
         let r1cs = load_r1cs::<G1, G2>(&FileLocation::PathBuf(circuit_file.into()));
         debug!("Loaded r1cs");
         let public_params_file = "../circuits/src/merkle_tree/ivc.params";
-        let pp = get_pp(public_params_file, &r1cs);
+        let pp = get_pp(public_params_file, &r1cs, StaleParamsPolicy::Regenerate).unwrap();
         debug!("Created pp");
         // Folder process instance
         let start_public_input = vec![
@@ -266,6 +738,7 @@ mod tests {
             tx_folder,
             Arc::clone(&pp),
             r1cs,
+            witness_generator,
             start_public_input,
         );
         debug!("Created Folder");
@@ -276,14 +749,276 @@ mod tests {
         });
         let num_tries = 2;
         for i in 0..num_tries {
-            tx.send(proof_system_msg.clone()).await.unwrap();
+            let step = ProofSystemStep {
+                msg: proof_system_msg.clone(),
+                tree_updates: vec![],
+            };
+            tx.send(step).await.unwrap();
             debug!("Sent Update to Folder");
             if i == 0 {
                 proof_system_msg = proof_system_msg1.clone();
             }
-            if (i + 1) % NUM_FOLDS == 0 {
-                rx.recv().await.unwrap();
+            if i + 1 == num_tries {
+                // the default policy compresses every 2 steps
+                let folded_proof = rx.recv().await.unwrap();
+                assert_eq!(folded_proof.num_steps, 2);
+                assert_eq!(folded_proof.public_output.len(), 2);
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_run_rejects_failed_step() {
+        let (tx, rx_folder) = tokio::sync::mpsc::channel(100);
+        let (tx_folder, _rx) = tokio::sync::mpsc::channel(100);
+        let (tx_outcome, mut rx_outcome) = tokio::sync::mpsc::unbounded_channel();
+
+        let update = dummy_user_profile_update();
+        let signed_update = SignedUserProfileUpdate::from_profile_update(update, dummy_signature());
+        let witness_generator =
+            WitnessGenerator::Wasm("../circuits/src/merkle_tree/ivc_js/ivc.wasm".into());
+        // the old leaf of the second update is not in the initial tree
+        let invalid_msg = make_proof_system_msg(
+            &signed_update,
+            &dummy_first_hash(),
+            None,
+            &dummy_siblings(),
+            HashMode::Padded,
+        );
+        let valid_msg = make_proof_system_msg(
+            &signed_update,
+            &zero_hash(),
+            None,
+            &dummy_siblings(),
+            HashMode::Padded,
+        );
+        let invalid_msg = step_input(invalid_msg, &witness_generator);
+        let valid_msg = step_input(valid_msg, &witness_generator);
+
+        let circuit_file = "../circuits/src/merkle_tree/ivc.r1cs";
+        let r1cs = load_r1cs::<G1, G2>(&FileLocation::PathBuf(circuit_file.into()));
+        let pp = get_pp(
+            "../circuits/src/merkle_tree/ivc.params",
+            &r1cs,
+            StaleParamsPolicy::Regenerate,
+        )
+        .unwrap();
+        let start_public_input = vec![
+            F::<G1>::from_str_vartime(
+                "57229376209049585136773117581839759840059304365154418192974084211719181400451",
+            )
+            .unwrap(),
+            F::<G1>::from_str_vartime("170345900").unwrap(),
+        ];
+        let mut folder = IVCProofFolder::new(
+            rx_folder,
+            tx_folder,
+            pp,
+            r1cs,
+            witness_generator,
+            start_public_input,
+        )
+        .with_step_outcomes(tx_outcome);
+        let _ = tokio::spawn(async move {
+            folder.run().await;
+        });
+
+        for msg in [invalid_msg, valid_msg] {
+            let step = ProofSystemStep {
+                msg,
+                tree_updates: vec![],
+            };
+            tx.send(step).await.unwrap();
+        }
+        assert!(matches!(
+            rx_outcome.recv().await.unwrap(),
+            StepOutcome::Failed { .. }
+        ));
+        // the folder keeps serving from the last good state
+        assert!(matches!(
+            rx_outcome.recv().await.unwrap(),
+            StepOutcome::Folded { step: 1, .. }
+        ));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    #[ignore = "needs ../circuits/src/merkle_tree/ivc_batch.r1cs, see \"Batched proofs\" in README.md"]
+    async fn test_run_batched() {
+        let (tx_updater, rx_updater) = tokio::sync::mpsc::channel(100);
+        let (tx_tree_update, rx_tree_update) = tokio::sync::mpsc::channel(100);
+        let (tx_step, rx_folder) = tokio::sync::mpsc::channel(100);
+        let (tx_folder, _rx) = tokio::sync::mpsc::channel(100);
+        let (tx_outcome, mut rx_outcome) = tokio::sync::mpsc::unbounded_channel();
+
+        let witness_generator = WitnessGenerator::Wasm(
+            "../circuits/src/merkle_tree/ivc_batch_js/ivc_batch.wasm".into(),
+        );
+        let missing_inputs = OPTIONAL_INPUTS
+            .into_iter()
+            .filter(|input| !witness_generator.has_input(input).unwrap())
+            .collect::<Vec<_>>();
+        let mut updater = MerkleTreeUpdater::new(
+            MerkleTree::new(MERKLE_TREE_DEPTH),
+            rx_updater,
+            tx_tree_update,
+        );
+        let mut builder = ProofSystemMessageBuilder::new(rx_tree_update, tx_step)
+            .with_batch_size(2)
+            .with_flush_timeout(Duration::from_millis(100))
+            .with_missing_inputs(missing_inputs);
+
+        let circuit_file = "../circuits/src/merkle_tree/ivc_batch.r1cs";
+        let r1cs = load_r1cs::<G1, G2>(&FileLocation::PathBuf(circuit_file.into()));
+        let pp = get_pp(
+            "../circuits/src/merkle_tree/ivc_batch.params",
+            &r1cs,
+            StaleParamsPolicy::Regenerate,
+        )
+        .unwrap();
+        let start_public_input = vec![
+            F::<G1>::from_str_vartime(
+                "57229376209049585136773117581839759840059304365154418192974084211719181400451",
+            )
+            .unwrap(),
+            F::<G1>::from_str_vartime("170345900").unwrap(),
+        ];
+        let mut folder = IVCProofFolder::new(
+            rx_folder,
+            tx_folder,
+            pp,
+            r1cs,
+            witness_generator,
+            start_public_input,
+        )
+        .with_step_outcomes(tx_outcome);
+        tokio::spawn(async move { updater.run().await });
+        tokio::spawn(async move { builder.run().await });
+        tokio::spawn(async move { folder.run().await });
+
+        // a full batch and a partial one flushed with a padding slot
+        for timestamp in [1703459910, 1703459911, 1703459912] {
+            tx_updater
+                .send(signed_test_update(timestamp))
+                .await
+                .unwrap();
+        }
+        for (expected_step, expected_updates) in [(1, 2), (2, 1)] {
+            match rx_outcome.recv().await.unwrap() {
+                StepOutcome::Folded { step, tree_updates } => {
+                    assert_eq!(step, expected_step);
+                    assert_eq!(tree_updates.len(), expected_updates);
+                }
+                StepOutcome::Failed { reason, .. } => panic!("batch failed: {reason}"),
             }
         }
     }
+
+    /// Witness of the first step of `ivc` computed with the witness generator `kind`
+    fn ivc_step_witness(kind: &str) -> Vec<F<G1>> {
+        let update = dummy_user_profile_update();
+        let signed_update = SignedUserProfileUpdate::from_profile_update(update, dummy_signature());
+        let proof_system_msg = make_proof_system_msg(
+            &signed_update,
+            &zero_hash(),
+            None,
+            &dummy_siblings(),
+            HashMode::Padded,
+        );
+        let start_public_input = vec![
+            F::<G1>::from_str_vartime(
+                "57229376209049585136773117581839759840059304365154418192974084211719181400451",
+            )
+            .unwrap(),
+            F::<G1>::from_str_vartime("170345900").unwrap(),
+        ]
+        .iter()
+        .map(|&x| format!("{:?}", x).strip_prefix("0x").unwrap().to_string())
+        .collect::<Vec<String>>();
+        // the inputs of the committed wasm
+        let in_process =
+            WitnessGenerator::from_config("in_process", "../circuits/src/merkle_tree", "ivc")
+                .unwrap();
+        let proof_system_msg = step_input(proof_system_msg, &in_process);
+        let mut witness_generator =
+            WitnessGenerator::from_config(kind, "../circuits/src/merkle_tree", "ivc").unwrap();
+        compute_witness::<G1, G2>(start_public_input, proof_system_msg, &mut witness_generator)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_witness_generators_match() {
+        assert_eq!(ivc_step_witness("wasm"), ivc_step_witness("in_process"));
+    }
+
+    #[test]
+    #[ignore = "needs ../circuits/src/merkle_tree/ivc_cpp/ivc, see \"Native witness generator\" in README.md"]
+    fn test_native_witness_generator_matches() {
+        assert_eq!(ivc_step_witness("wasm"), ivc_step_witness("native"));
+    }
+
+    #[test]
+    fn test_witness_generator_from_config() {
+        assert!(matches!(
+            WitnessGenerator::from_config("native", "circuits", "ivc"),
+            Ok(WitnessGenerator::Native { binary, sym })
+                if binary == PathBuf::from("circuits/ivc_cpp/ivc")
+                    && sym == PathBuf::from("circuits/ivc.sym")
+        ));
+        assert!(WitnessGenerator::from_config("js", "circuits", "ivc").is_err());
+    }
+
+    #[test]
+    fn test_sym_input_size() {
+        let symbols = "1,1,0,main.step_out[0]\n\
+                       2,2,0,main.step_out[1]\n\
+                       3,3,0,main.typed\n\
+                       4,4,0,main.old_message[0]\n\
+                       5,5,0,main.old_message[1]\n\
+                       6,-1,1,main.old_message_poseidon_hash[0]\n\
+                       7,6,1,main.msg_hash.old_message[0]\n";
+        assert_eq!(sym_input_size(symbols, "typed"), 1);
+        assert_eq!(sym_input_size(symbols, "old_message"), 2);
+        assert_eq!(sym_input_size(symbols, "message"), 0);
+
+        // a native generator without symbols file fails instead of assuming the input exists
+        let witness_generator =
+            WitnessGenerator::from_config("native", "no_circuits", "ivc").unwrap();
+        assert!(witness_generator.has_input("old_message").is_err());
+    }
+
+    #[test]
+    fn test_compression_policy_from_config() {
+        assert_eq!(
+            CompressionPolicy::from_config("steps:4"),
+            Ok(CompressionPolicy::EveryNSteps(4))
+        );
+        assert_eq!(
+            CompressionPolicy::from_config("seconds:60"),
+            Ok(CompressionPolicy::Interval(Duration::from_secs(60)))
+        );
+        assert_eq!(
+            CompressionPolicy::from_config("on_demand"),
+            Ok(CompressionPolicy::OnDemand)
+        );
+        assert!(CompressionPolicy::from_config("steps:0").is_err());
+        assert!(CompressionPolicy::from_config("minutes:1").is_err());
+        assert!(CompressionPolicy::from_config("steps").is_err());
+    }
+
+    #[test]
+    fn test_witness_files_are_unique_and_removed() {
+        let files = WitnessFiles::new();
+        let other_files = WitnessFiles::new();
+        assert_ne!(files.input, other_files.input);
+        assert_ne!(files.output, other_files.output);
+
+        fs::write(&files.input, "{}").unwrap();
+        fs::write(&files.output, "").unwrap();
+        let (input, output) = (files.input.clone(), files.output.clone());
+        drop(files);
+        assert!(!input.exists());
+        assert!(!output.exists());
+    }
 }
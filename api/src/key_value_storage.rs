@@ -1,28 +1,39 @@
 #![allow(dead_code)]
 use std::sync::{Arc, Mutex};
 
+use anyhow::Result;
 use redis::Commands;
+/// Errors of a storage that is not reachable are returned to the caller
 pub trait KeyValueStorage {
-    fn get(&self, key: &str) -> Option<String>;
-    fn set(&self, key: &str, value: &str);
-    fn del(&self, key: &str);
+    fn get(&self, key: &str) -> Result<Option<String>>;
+    fn set(&self, key: &str, value: &str) -> Result<()>;
+    fn del(&self, key: &str) -> Result<()>;
 }
 pub struct RedisStorage {
     client: redis::Client,
 }
+impl RedisStorage {
+    pub fn new(url: &str) -> redis::RedisResult<Self> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+        })
+    }
+}
 impl KeyValueStorage for RedisStorage {
-    fn get(&self, key: &str) -> Option<String> {
-        let mut con = self.client.get_connection().unwrap();
-        let value: Option<String> = con.get(key).unwrap();
-        value
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let mut con = self.client.get_connection()?;
+        let value: Option<String> = con.get(key)?;
+        Ok(value)
     }
-    fn set(&self, key: &str, value: &str) {
-        let mut con = self.client.get_connection().unwrap();
-        let _: () = con.set(key, value).unwrap();
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        let mut con = self.client.get_connection()?;
+        let _: () = con.set(key, value)?;
+        Ok(())
     }
-    fn del(&self, key: &str) {
-        let mut con = self.client.get_connection().unwrap();
-        let _: () = con.del(key).unwrap();
+    fn del(&self, key: &str) -> Result<()> {
+        let mut con = self.client.get_connection()?;
+        let _: () = con.del(key)?;
+        Ok(())
     }
 }
 
@@ -38,16 +49,46 @@ impl LocalStorage {
 }
 
 impl KeyValueStorage for LocalStorage {
-    fn get(&self, key: &str) -> Option<String> {
-        self.data.lock().unwrap().get(key).cloned()
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.data.lock().unwrap().get(key).cloned())
     }
-    fn set(&self, key: &str, value: &str) {
+    fn set(&self, key: &str, value: &str) -> Result<()> {
         self.data
             .lock()
             .unwrap()
             .insert(key.to_string(), value.to_string());
+        Ok(())
     }
-    fn del(&self, key: &str) {
+    fn del(&self, key: &str) -> Result<()> {
         self.data.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Storage whose every operation fails, like a RedisStorage without a server
+    pub struct UnavailableStorage;
+    impl KeyValueStorage for UnavailableStorage {
+        fn get(&self, _key: &str) -> Result<Option<String>> {
+            Err(anyhow::anyhow!("storage unavailable"))
+        }
+        fn set(&self, _key: &str, _value: &str) -> Result<()> {
+            Err(anyhow::anyhow!("storage unavailable"))
+        }
+        fn del(&self, _key: &str) -> Result<()> {
+            Err(anyhow::anyhow!("storage unavailable"))
+        }
+    }
+
+    #[test]
+    fn test_redis_storage_unavailable() {
+        // nothing listens on the port, the connection fails on first use
+        let storage = RedisStorage::new("redis://127.0.0.1:1/").unwrap();
+        assert!(storage.get("key").is_err());
+        assert!(storage.set("key", "value").is_err());
+        assert!(storage.del("key").is_err());
     }
 }
@@ -1,43 +1,74 @@
+mod checkpoint;
 mod compressed_proof_builder;
 mod delayed_priority_queue;
 mod eff_ecdsa_input;
+mod eip712;
+mod erc1271;
 mod ivc_proof_folder;
 mod key_value_storage;
 mod merkle_tree_updater;
+mod pipeline_events;
+mod proof_store;
 mod proof_system_message;
+mod public_params;
+mod replay_guard;
 mod server;
+mod update_tracker;
 mod user;
-use tracing::debug;
+mod witness_calculator;
+use tracing::{debug, info, warn};
 
 use delayed_priority_queue::{PriorityDelayQueue, PriorityDelayQueueRunner};
+use eff_ecdsa_input::HashMode;
+use eip712::Eip712Domain;
+use erc1271::Erc1271Verifier;
 use ff::PrimeField;
-use key_value_storage::LocalStorage;
+use key_value_storage::{LocalStorage, RedisStorage};
 use nova_scotia::circom::circuit::{CircomCircuit, R1CS};
 use nova_snark::traits::circuit::TrivialCircuit;
-use nova_snark::PublicParams;
 use nova_snark::{provider::secp_secq::secp256k1, provider::secp_secq::secq256k1, traits::Group};
+use nova_snark::{CompressedSNARK, ProverKey, PublicParams, VerifierKey};
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
-use nova_scotia::F;
 use nova_scotia::{circom::reader::load_r1cs, create_public_params, FileLocation};
+use nova_scotia::{F, S};
 
+use checkpoint::CheckpointStore;
 use compressed_proof_builder::CompressedProofBuilder;
-use ivc_proof_folder::IVCProofFolder;
+use ivc_proof_folder::{CompressionPolicy, IVCProofFolder, WitnessGenerator};
 use merkle_tree_updater::MerkleTreeUpdater;
-use proof_system_message::ProofSystemMessageBuilder;
+use proof_store::ProofStore;
+use proof_system_message::{ProofSystemMessageBuilder, DEFAULT_FLUSH_TIMEOUT, OPTIONAL_INPUTS};
+use public_params::StoredParams;
+use replay_guard::ReplayGuard;
 
-use server::run_server;
+use pipeline_events::EVENT_CHANNEL_CAPACITY;
+use server::{run_server, AppState, TimestampWindow};
 use tokio::{sync::mpsc::channel, time::Instant};
+use update_tracker::UpdateTracker;
 
 use merkle_tree::MerkleTree;
 
 const PORT: u16 = 3000;
+/// Levels of the tree including the leaves, the circuits get `MERKLE_TREE_DEPTH - 1` siblings
 const MERKLE_TREE_DEPTH: usize = 3;
+/// Default number of profile updates folded per Nova step, see `PROOF_BATCH_SIZE` in `main`
+const DEFAULT_PROOF_BATCH_SIZE: usize = 1;
+/// Whether the public parameters file is zlib compressed, smaller but slower to read
+const COMPRESS_PUBLIC_PARAMS: bool = false;
+/// Number of folded steps between two checkpoints of the recursive SNARK
+const CHECKPOINT_INTERVAL: usize = 1;
 pub type G1 = secq256k1::Point;
 pub type G2 = secp256k1::Point;
 
 pub type C1<G> = CircomCircuit<<G as Group>::Scalar>;
 pub type C2<G> = TrivialCircuit<<G as Group>::Scalar>;
+pub type CompressedProof = CompressedSNARK<G1, G2, C1<G1>, C2<G2>, S<G1>, S<G2>>;
+pub type CompressedProverKey = ProverKey<G1, G2, C1<G1>, C2<G2>, S<G1>, S<G2>>;
+pub type CompressedVerifierKey = VerifierKey<G1, G2, C1<G1>, C2<G2>, S<G1>, S<G2>>;
 
 #[tokio::main]
 async fn main() {
@@ -45,12 +76,98 @@ async fn main() {
         .with_max_level(tracing::Level::DEBUG)
         .init();
     debug!("Starting application");
-    let tree = MerkleTree::new(MERKLE_TREE_DEPTH);
-    let circuit_file = "../circuits/src/merkle_tree/ivc.r1cs";
+    // "padded" (default) or "eip191" for wallets signing with personal_sign
+    let hash_mode = match std::env::var("HASH_MODE") {
+        Ok(config) => HashMode::from_config(&config).unwrap(),
+        Err(_) => HashMode::default(),
+    };
+    // values above 1 require ivc_batch.circom compiled with N_SIGS = PROOF_BATCH_SIZE
+    let proof_batch_size = match std::env::var("PROOF_BATCH_SIZE") {
+        Ok(config) => match config.parse::<usize>() {
+            Ok(size) if size > 0 => size,
+            _ => panic!("PROOF_BATCH_SIZE has to be a positive number, got {config}"),
+        },
+        Err(_) => DEFAULT_PROOF_BATCH_SIZE,
+    };
+    // milliseconds a partial batch waits for more updates before it is padded
+    let batch_flush_timeout = match std::env::var("BATCH_FLUSH_TIMEOUT_MS") {
+        Ok(config) => Duration::from_millis(config.parse().unwrap()),
+        Err(_) => DEFAULT_FLUSH_TIMEOUT,
+    };
+    let circuit_name = match (proof_batch_size, hash_mode) {
+        (1, HashMode::Padded) => "ivc",
+        (1, HashMode::Eip191) => "ivc_eip191",
+        (_, HashMode::Padded) => "ivc_batch",
+        (_, hash_mode) => panic!("batches of {hash_mode:?} signed updates are not supported"),
+    };
+    let circuit_file = format!("../circuits/src/merkle_tree/{circuit_name}.r1cs");
     let r1cs = load_r1cs::<G1, G2>(&FileLocation::PathBuf(circuit_file.into()));
-    let public_params_file = "../circuits/src/merkle_tree/ivc.params";
-    // if file exists read params from file, else compute params and save to file as json
-    let pp = get_pp(public_params_file, &r1cs);
+    // "wasm" (default), "native" for the binary built from the circom C++ output
+    // or "in_process" to run the wasm inside this process
+    let witness_generator_kind =
+        std::env::var("WITNESS_GENERATOR").unwrap_or_else(|_| "wasm".to_string());
+    let witness_generator = WitnessGenerator::from_config(
+        &witness_generator_kind,
+        "../circuits/src/merkle_tree",
+        circuit_name,
+    )
+    .unwrap();
+    // artifacts compiled before inputs were added to the circuit: without old_message replays
+    // are only rejected by the api, without typed no typed updates are accepted.
+    // "refuse" (default) to start without the replay check in the circuit or "allow" it
+    let allow_missing_inputs = match std::env::var("MISSING_CIRCUIT_INPUTS").as_deref() {
+        Ok("allow") => true,
+        Ok("refuse") | Err(_) => false,
+        Ok(policy) => panic!("unknown missing circuit inputs policy: {policy}"),
+    };
+    let missing_inputs = OPTIONAL_INPUTS
+        .into_iter()
+        .filter(|input| !witness_generator.has_input(input).unwrap())
+        .collect::<Vec<_>>();
+    if missing_inputs.contains(&"old_message") && !allow_missing_inputs {
+        panic!(
+            "{circuit_name} has no old_message input to reject replays, recompile it \
+             or set MISSING_CIRCUIT_INPUTS=allow to run without the check"
+        );
+    }
+    if !missing_inputs.is_empty() {
+        warn!("{circuit_name} has no {missing_inputs:?} inputs, recompile it to prove them");
+    }
+    check_circuit_shape(&witness_generator, proof_batch_size).unwrap();
+    // domain of the typed updates, its separator is a constant of eip712_domain.circom
+    let eip712_domain = Eip712Domain::from_config(
+        std::env::var("EIP712_CHAIN_ID").ok().as_deref(),
+        std::env::var("EIP712_VERIFYING_CONTRACT").ok().as_deref(),
+        std::env::var("EIP712_SALT").ok().as_deref(),
+    )
+    .unwrap();
+    info!(
+        "EIP-712 domain separator: 0x{}",
+        hex::encode(eip712_domain.separator())
+    );
+    let typed_updates = !missing_inputs.contains(&"typed");
+    if typed_updates {
+        let eip712_domain_circom =
+            std::fs::read_to_string("../circuits/src/merkle_tree/eip712_domain.circom").unwrap();
+        if let Err(e) = eip712_domain.check_circuit_separator(&eip712_domain_circom) {
+            panic!("{e}, update eip712_domain.circom and recompile or change the EIP712_* config");
+        }
+    }
+    // "steps:<n>" (default "steps:2"), "seconds:<t>" or "on_demand",
+    // a compression can always be requested with POST /compress
+    let compression_policy = match std::env::var("COMPRESSION_POLICY") {
+        Ok(config) => CompressionPolicy::from_config(&config).unwrap(),
+        Err(_) => CompressionPolicy::default(),
+    };
+    let public_params_file = format!("../circuits/src/merkle_tree/{circuit_name}.params");
+    // "regenerate" (default) or "refuse" public parameters generated for another r1cs
+    let stale_params_policy = match std::env::var("STALE_PUBLIC_PARAMS").as_deref() {
+        Ok("refuse") => StaleParamsPolicy::Refuse,
+        Ok("regenerate") | Err(_) => StaleParamsPolicy::Regenerate,
+        Ok(policy) => panic!("unknown stale public parameters policy: {policy}"),
+    };
+    // if file exists and matches the r1cs read params from file, else compute params and save to file
+    let pp = get_pp(&public_params_file, &r1cs, stale_params_policy).unwrap();
     let start_public_input = vec![
         F::<G1>::from_str_vartime(
             "57229376209049585136773117581839759840059304365154418192974084211719181400451",
@@ -58,6 +175,37 @@ async fn main() {
         .unwrap(),
         F::<G1>::from_str_vartime("170345900").unwrap(),
     ];
+    // resume from the last checkpoint if there is one, so a restart does not drop the folded steps
+    let checkpoint_store = CheckpointStore::new(format!(
+        "../circuits/src/merkle_tree/{circuit_name}.checkpoint"
+    ));
+    // "discard" (default) starts from an empty tree or "refuse" to start with a checkpoint
+    // that cannot be resumed, e.g. after the public parameters were regenerated
+    let refuse_stale_checkpoint = match std::env::var("STALE_CHECKPOINT").as_deref() {
+        Ok("refuse") => true,
+        Ok("discard") | Err(_) => false,
+        Ok(policy) => panic!("unknown stale checkpoint policy: {policy}"),
+    };
+    let r1cs_digest = public_params::r1cs_digest(&r1cs);
+    let checkpoint = match checkpoint_store.load_verified(
+        &pp,
+        &r1cs_digest,
+        &start_public_input,
+        MERKLE_TREE_DEPTH,
+    ) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) if refuse_stale_checkpoint => panic!(
+            "cannot resume from checkpoint {}: {e}, remove it to start from an empty tree",
+            checkpoint_store.path().display()
+        ),
+        Err(e) => {
+            warn!(
+                "cannot resume from checkpoint {}: {e}, starting from an empty tree",
+                checkpoint_store.path().display()
+            );
+            None
+        }
+    };
     // create channels
     // merkle tree
     let (tx, rx_delayed_priority_queue) = channel(100);
@@ -68,6 +216,48 @@ async fn main() {
     let (tx_msg_builder, rx_proof_folder) = channel(100);
     // compressed proof builder
     let (tx_proof_folder, rx_compressed_proof_builder) = channel(100);
+    // compression requests from the api
+    let (tx_compression_trigger, rx_compression_trigger) = channel(1);
+    // folded and rejected steps, reported back to the merkle tree updater; unbounded since
+    // the updater can be blocked sending to the folder while the folder reports to it
+    let (tx_step_outcome, rx_step_outcome) = tokio::sync::mpsc::unbounded_channel();
+    // pipeline events streamed by the api
+    let (tx_events, _) = tokio::sync::broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+    // the store moves into the folder, its path is still needed if resuming fails
+    let checkpoint_path = checkpoint_store.path().to_path_buf();
+    let mut proof_folder = IVCProofFolder::new(
+        rx_proof_folder,
+        tx_proof_folder,
+        Arc::clone(&pp),
+        r1cs,
+        witness_generator,
+        start_public_input.clone(),
+    )
+    .with_checkpoints(checkpoint_store, CHECKPOINT_INTERVAL)
+    .with_step_outcomes(tx_step_outcome)
+    .with_compression(compression_policy, rx_compression_trigger)
+    .with_events(tx_events.clone());
+    let (tree, messages) = match checkpoint {
+        Some((checkpoint, tree, messages)) => {
+            debug!("Verified checkpoint at step {}", checkpoint.num_steps);
+            match proof_folder.resume(checkpoint) {
+                Ok(()) => (tree, messages),
+                Err(e) if refuse_stale_checkpoint => panic!(
+                    "cannot resume from checkpoint {}: {e}, remove it to start from an empty tree",
+                    checkpoint_path.display()
+                ),
+                Err(e) => {
+                    warn!(
+                        "cannot resume from checkpoint {}: {e}, starting from an empty tree",
+                        checkpoint_path.display()
+                    );
+                    (MerkleTree::new(MERKLE_TREE_DEPTH), HashMap::new())
+                }
+            }
+        }
+        None => (MerkleTree::new(MERKLE_TREE_DEPTH), HashMap::new()),
+    };
 
     let delay_ms = 200;
     let storage = LocalStorage::new();
@@ -78,18 +268,40 @@ async fn main() {
     ));
     let mut delayed_priority_queue =
         PriorityDelayQueueRunner::new(rx_delayed_priority_queue, queue);
-    let mut merkle_tree_updater = MerkleTreeUpdater::new(tree, rx_merkle_tree, tx_merkle_tree);
+    // update statuses are kept in redis if REDIS_URL is set, in memory otherwise
+    let update_tracker = Arc::new(match std::env::var("REDIS_URL") {
+        Ok(url) => UpdateTracker::new(RedisStorage::new(&url).unwrap()),
+        Err(_) => UpdateTracker::new(LocalStorage::new()),
+    });
+    // last timestamp of every address, in the same storage as the update statuses
+    let replay_guard = Arc::new(match std::env::var("REDIS_URL") {
+        Ok(url) => ReplayGuard::new(RedisStorage::new(&url).unwrap()),
+        Err(_) => ReplayGuard::new(LocalStorage::new()),
+    });
+    let mut merkle_tree_updater = MerkleTreeUpdater::new(tree, rx_merkle_tree, tx_merkle_tree)
+        .with_profile_messages(messages)
+        .with_step_outcomes(rx_step_outcome)
+        .with_update_tracker(Arc::clone(&update_tracker))
+        .with_replay_guard(Arc::clone(&replay_guard))
+        .with_events(tx_events.clone());
+    let tree_state = merkle_tree_updater.tree_state();
     let mut prove_system_msg_builder =
-        ProofSystemMessageBuilder::new(rx_msg_builder, tx_msg_builder);
-    let mut proof_folder = IVCProofFolder::new(
-        rx_proof_folder,
-        tx_proof_folder,
-        Arc::clone(&pp),
-        r1cs,
-        start_public_input.clone(),
-    );
+        ProofSystemMessageBuilder::new(rx_msg_builder, tx_msg_builder)
+            .with_batch_size(proof_batch_size)
+            .with_flush_timeout(batch_flush_timeout)
+            .with_hash_mode(hash_mode)
+            .with_missing_inputs(missing_inputs.clone());
+    // compressed proofs are kept in redis if REDIS_URL is set, in memory otherwise
+    let proof_store = Arc::new(match std::env::var("REDIS_URL") {
+        Ok(url) => ProofStore::new(RedisStorage::new(&url).unwrap()),
+        Err(_) => ProofStore::new(LocalStorage::new()),
+    });
     let mut compressed_proof_builder =
-        CompressedProofBuilder::new(rx_compressed_proof_builder, pp, start_public_input);
+        CompressedProofBuilder::new(rx_compressed_proof_builder, pp, start_public_input)
+            .with_proof_store(Arc::clone(&proof_store))
+            .with_key_cache("../circuits/src/merkle_tree/keys")
+            .with_update_tracker(Arc::clone(&update_tracker))
+            .with_events(tx_events.clone());
 
     tokio::spawn(async move {
         delayed_priority_queue.run().await;
@@ -107,29 +319,121 @@ async fn main() {
         compressed_proof_builder.run().await;
     });
 
-    run_server(PORT, tx).await;
+    // accepted clock skew of update timestamps (default 60 s) and maximum age (unbounded if not set)
+    let timestamp_window = TimestampWindow {
+        max_future_secs: Some(match std::env::var("MAX_CLOCK_SKEW_SECS") {
+            Ok(secs) => secs.parse().unwrap(),
+            Err(_) => 60,
+        }),
+        max_age_secs: std::env::var("MAX_UPDATE_AGE_SECS")
+            .ok()
+            .map(|secs| secs.parse().unwrap()),
+    };
+    // "accept" to also accept messages with a legacy profile encoding while clients migrate,
+    // only canonical profiles by default
+    let legacy_profiles = match std::env::var("LEGACY_PROFILES").as_deref() {
+        Ok("accept") => true,
+        Ok("reject") | Err(_) => false,
+        Ok(policy) => panic!("unknown legacy profiles policy: {policy}"),
+    };
+    let mut state = AppState::new(tx)
+        .with_hash_mode(hash_mode)
+        .with_eip712_domain(eip712_domain)
+        .with_typed_updates(typed_updates)
+        .with_legacy_profiles(legacy_profiles)
+        .with_compression_trigger(tx_compression_trigger)
+        .with_proof_store(proof_store)
+        .with_tree_state(tree_state)
+        .with_update_tracker(update_tracker)
+        .with_replay_guard(replay_guard)
+        .with_timestamp_window(timestamp_window)
+        .with_events(tx_events);
+    // JSON-RPC node to verify the signatures of smart-contract wallets, accepted as attested-only updates
+    if let Ok(rpc_url) = std::env::var("ERC1271_RPC_URL") {
+        state = state.with_contract_verifier(Arc::new(Erc1271Verifier::new(&rpc_url)));
+    }
+    run_server(PORT, state).await;
 }
 
+/// Checks the circuit of `witness_generator` takes `batch_size` updates of a
+/// `MERKLE_TREE_DEPTH` tree per step
+fn check_circuit_shape(
+    witness_generator: &WitnessGenerator,
+    batch_size: usize,
+) -> anyhow::Result<()> {
+    let expected = [
+        ("signatures", batch_size * 6),
+        ("siblings", batch_size * (MERKLE_TREE_DEPTH - 1)),
+    ];
+    for (input, expected_size) in expected {
+        let size = witness_generator.input_size(input)?;
+        if size != expected_size {
+            return Err(anyhow::anyhow!(
+                "circuit input {input} has {size} elements, {expected_size} expected for \
+                 batches of {batch_size} and a tree of depth {MERKLE_TREE_DEPTH}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// What `get_pp` does with a public parameters file generated for another circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleParamsPolicy {
+    Refuse,
+    Regenerate,
+}
+
+/// Public parameters for `r1cs`
+///
+/// Read from `public_params_file` if it exists and was generated for the same r1cs,
+/// else computed and saved in the binary format of `public_params`.
+/// Fails for parameters of another circuit with `StaleParamsPolicy::Refuse`.
 pub fn get_pp(
     public_params_file: &str,
     r1cs: &R1CS<<G1 as Group>::Scalar>,
-) -> Arc<PublicParams<G1, G2, C1<G1>, C2<G2>>> {
-    let pp = if std::path::Path::new(public_params_file).exists() {
+    stale_params_policy: StaleParamsPolicy,
+) -> anyhow::Result<Arc<PublicParams<G1, G2, C1<G1>, C2<G2>>>> {
+    let public_params_file = Path::new(public_params_file);
+    let r1cs_digest = public_params::r1cs_digest(r1cs);
+    if public_params_file.exists() {
         debug!("Reading public parameters from file");
-        let pp =
-            serde_json::from_str(&std::fs::read_to_string(public_params_file).unwrap()).unwrap();
-        Arc::new(pp)
-    } else {
-        debug!("Computing public parameters");
-        let start = Instant::now();
-        let pp = create_public_params::<G1, G2>(r1cs.clone());
-        let duration = start.elapsed();
-        debug!("Public parameters computed in {:?}", duration);
-        std::fs::write(public_params_file, serde_json::to_string(&pp).unwrap()).unwrap();
-        Arc::new(pp)
-    };
-    pp
+        match public_params::read_params(public_params_file, &r1cs_digest)? {
+            StoredParams::Current(pp) => return Ok(Arc::new(pp)),
+            StoredParams::Stale { r1cs_digest: stale }
+                if stale_params_policy == StaleParamsPolicy::Refuse =>
+            {
+                return Err(anyhow::anyhow!(
+                    "{} was generated for r1cs {}, the loaded r1cs is {}",
+                    public_params_file.display(),
+                    hex::encode(stale),
+                    hex::encode(r1cs_digest)
+                ));
+            }
+            StoredParams::Stale { .. } => {
+                info!("Public parameters were generated for another circuit")
+            }
+            StoredParams::Outdated => info!("Public parameters file has an outdated format"),
+        }
+    }
+    debug!("Computing public parameters");
+    let start = Instant::now();
+    let pp = create_public_params::<G1, G2>(r1cs.clone());
+    let duration = start.elapsed();
+    debug!("Public parameters computed in {:?}", duration);
+    public_params::write_params(public_params_file, &pp, r1cs_digest, COMPRESS_PUBLIC_PARAMS)?;
+    Ok(Arc::new(pp))
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_circuit_shape() {
+        let witness_generator =
+            WitnessGenerator::Wasm("../circuits/src/merkle_tree/ivc_js/ivc.wasm".into());
+        check_circuit_shape(&witness_generator, 1).unwrap();
+        assert!(check_circuit_shape(&witness_generator, 2).is_err());
+    }
+}
@@ -1,63 +1,360 @@
-use crate::{eff_ecdsa_input::fe_to_biguint, server::SignedUserProfileUpdate};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+use crate::eff_ecdsa_input::{fe_to_biguint, fe_to_hex};
+use crate::pipeline_events::{send_event, EventSender, PipelineEvent};
+use crate::replay_guard::ReplayGuard;
+use crate::server::{SignedUserProfileUpdate, UserProfileUpdate};
+use crate::update_tracker::{UpdateStatus, UpdateTracker};
+use anyhow::Result;
 use common::utils::bits::pad_msg;
-use merkle_tree::{Hash, MerkleTree, Sibling};
-use tokio::sync::mpsc::{Receiver, Sender};
-use tracing::debug;
+use merkle_tree::{Hash, Key, MerkleTree, Sibling};
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver};
+use tracing::{debug, error};
 
 use common::BIT_SIZE;
 
+/// A profile update applied to the Merkle tree
+#[derive(Debug, Clone)]
+pub struct TreeUpdate {
+    pub update: SignedUserProfileUpdate,
+    pub prev_root: Hash,
+    pub new_root: Hash,
+    pub old_leaf: Hash,
+    /// profile message of the old leaf, `None` if the address had no profile
+    pub old_message: Option<String>,
+    pub new_leaf: Hash,
+    /// siblings of the leaf before (and after) the update
+    pub siblings: Vec<Sibling>,
+}
+impl TreeUpdate {
+    /// Key of the updated leaf
+    pub fn key(&self) -> Key {
+        address_key(&self.update.eth_address())
+    }
+    fn is_same(&self, other: &TreeUpdate) -> bool {
+        self.prev_root == other.prev_root && self.new_root == other.new_root
+    }
+}
+
+/// Merkle tree key of a wallet address, addresses are not case sensitive
+pub fn address_key(address: &str) -> Key {
+    address.to_lowercase().into_bytes()
+}
+
+/// Merkle tree of the updater, shared with the server to answer root and proof queries
+pub struct TreeState {
+    pub merkle_tree: MerkleTree,
+    /// step at which the leaf of a key was last folded
+    pub last_folded_step: HashMap<Key, usize>,
+    /// profile message of every leaf, the circuit checks an update against the message it replaces
+    pub messages: HashMap<Key, String>,
+}
+pub type SharedTreeState = Arc<RwLock<TreeState>>;
+
+/// Result of folding the tree updates of one step, reported back by `IVCProofFolder`
+#[derive(Debug, Clone)]
+pub enum StepOutcome {
+    /// the tree updates were folded into the recursive SNARK at step `step`
+    Folded {
+        step: usize,
+        tree_updates: Vec<TreeUpdate>,
+    },
+    /// the step was rejected and the tree updates have to be reverted
+    Failed {
+        tree_updates: Vec<TreeUpdate>,
+        reason: String,
+    },
+}
+
 pub struct MerkleTreeUpdater {
-    merkle_tree: MerkleTree,
+    state: SharedTreeState,
     rx: Receiver<SignedUserProfileUpdate>,
-    tx: Sender<(Hash, SignedUserProfileUpdate, Hash, Vec<Sibling>)>,
+    tx: Sender<TreeUpdate>,
+    rx_outcome: Option<UnboundedReceiver<StepOutcome>>,
+    /// tree updates sent to the folder that are not folded yet, oldest first
+    pending: VecDeque<TreeUpdate>,
+    update_tracker: Option<Arc<UpdateTracker>>,
+    replay_guard: Option<Arc<ReplayGuard>>,
+    events: Option<EventSender>,
 }
 impl MerkleTreeUpdater {
     pub fn new(
         merkle_tree: MerkleTree,
         rx: Receiver<SignedUserProfileUpdate>,
-        tx: Sender<(Hash, SignedUserProfileUpdate, Hash, Vec<Sibling>)>,
+        tx: Sender<TreeUpdate>,
     ) -> Self {
         Self {
-            merkle_tree,
+            state: Arc::new(RwLock::new(TreeState {
+                merkle_tree,
+                last_folded_step: HashMap::new(),
+                messages: HashMap::new(),
+            })),
             rx,
             tx,
+            rx_outcome: None,
+            pending: VecDeque::new(),
+            update_tracker: None,
+            replay_guard: None,
+            events: None,
         }
     }
+    /// Profile messages of the leaves of the initial tree, e.g. restored from a checkpoint
+    pub fn with_profile_messages(self, messages: HashMap<Key, String>) -> Self {
+        self.state.write().unwrap().messages = messages;
+        self
+    }
+    /// Revert the tree updates of steps the folder rejects
+    ///
+    /// Unbounded since the folder must never wait for the updater, see
+    /// `IVCProofFolder::with_step_outcomes`.
+    pub fn with_step_outcomes(mut self, rx_outcome: UnboundedReceiver<StepOutcome>) -> Self {
+        self.rx_outcome = Some(rx_outcome);
+        self
+    }
+    /// Report applied, folded and failed updates to `update_tracker`
+    pub fn with_update_tracker(mut self, update_tracker: Arc<UpdateTracker>) -> Self {
+        self.update_tracker = Some(update_tracker);
+        self
+    }
+    /// Rewind the timestamps `replay_guard` recorded for updates that are reverted, so the
+    /// same messages can be submitted again
+    pub fn with_replay_guard(mut self, replay_guard: Arc<ReplayGuard>) -> Self {
+        self.replay_guard = Some(replay_guard);
+        self
+    }
+    /// Publish the new root of every applied update to `events`
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+    /// Merkle tree shared with readers outside of the updater
+    pub fn tree_state(&self) -> SharedTreeState {
+        Arc::clone(&self.state)
+    }
     pub async fn run(&mut self) {
         debug!("Merkle Tree Updater started");
-        debug!("Merkle Tree depth: {}", self.merkle_tree.depth);
-        debug!(
-            "Merkle Tree root: {:?}",
-            fe_to_biguint(&self.merkle_tree.root())
-        );
-        while let Some(update) = self.rx.recv().await {
-            let padded_msg = pad_msg(update.profile_update.unparsed_profile.as_bytes(), BIT_SIZE);
-            let eth_address = update.profile_update.parsed_profile.wallet_address.clone();
-            let old_leaf = self
-                .merkle_tree
-                .get_leaf(&eth_address.as_bytes().to_vec())
-                .unwrap();
-            let (_, new_root, siblings) = self
-                .merkle_tree
-                .insert_leaf(&eth_address.as_bytes().to_vec(), &padded_msg)
-                .unwrap();
-            debug!("New root: {:?}", fe_to_biguint(&new_root));
-            let _ = self.tx.send((new_root, update, old_leaf, siblings)).await;
+        {
+            let state = self.state.read().unwrap();
+            debug!("Merkle Tree depth: {}", state.merkle_tree.depth);
+            debug!(
+                "Merkle Tree root: {:?}",
+                fe_to_biguint(&state.merkle_tree.root())
+            );
+        }
+        loop {
+            tokio::select! {
+                update = self.rx.recv() => match update {
+                    Some(update) => self.apply(update).await,
+                    None => break,
+                },
+                Some(outcome) = recv_outcome(&mut self.rx_outcome) => {
+                    self.handle_outcome(outcome).await
+                }
+            }
+        }
+    }
+    async fn apply(&mut self, update: SignedUserProfileUpdate) {
+        let receipt_id = update.receipt_id();
+        match self.insert(update) {
+            Ok(tree_update) => {
+                let root = fe_to_hex(&tree_update.new_root);
+                send_event(
+                    &self.events,
+                    PipelineEvent::RootUpdated {
+                        receipt_id: receipt_id.clone(),
+                        address: tree_update.update.eth_address().to_lowercase(),
+                        root: root.clone(),
+                    },
+                );
+                self.set_status(&receipt_id, UpdateStatus::Applied { root });
+                if self.rx_outcome.is_some() {
+                    self.pending.push_back(tree_update.clone());
+                }
+                let _ = self.tx.send(tree_update).await;
+            }
+            Err(e) => {
+                error!("failed to insert profile update into the Merkle tree: {e}");
+                let reason = format!("Merkle tree update failed: {e}");
+                self.set_status(&receipt_id, UpdateStatus::Failed { reason });
+            }
+        }
+    }
+    fn set_status(&self, receipt_id: &str, status: UpdateStatus) {
+        if let Some(update_tracker) = &self.update_tracker {
+            if let Err(e) = update_tracker.set_status(receipt_id, status) {
+                error!("failed to store the status of update {receipt_id}: {e}");
+            }
+        }
+    }
+    fn insert(&mut self, update: SignedUserProfileUpdate) -> Result<TreeUpdate> {
+        let padded_msg = pad_msg(update.profile_update.unparsed_profile.as_bytes(), BIT_SIZE);
+        let key = address_key(&update.eth_address());
+        let mut state = self.state.write().unwrap();
+        let merkle_tree = &mut state.merkle_tree;
+        let prev_root = merkle_tree.root();
+        let old_leaf = merkle_tree.get_leaf(&key)?;
+        let (new_leaf, new_root, siblings) = merkle_tree.insert_leaf(&key, &padded_msg)?;
+        debug!("New root: {:?}", fe_to_biguint(&new_root));
+        let old_message = state
+            .messages
+            .insert(key, update.profile_update.unparsed_profile.clone());
+        Ok(TreeUpdate {
+            update,
+            prev_root,
+            new_root,
+            old_leaf,
+            old_message,
+            new_leaf,
+            siblings,
+        })
+    }
+    async fn handle_outcome(&mut self, outcome: StepOutcome) {
+        match outcome {
+            StepOutcome::Folded { step, tree_updates } => {
+                {
+                    let mut state = self.state.write().unwrap();
+                    for tree_update in tree_updates.iter() {
+                        state.last_folded_step.insert(tree_update.key(), step);
+                        if self
+                            .pending
+                            .front()
+                            .is_some_and(|pending| pending.is_same(tree_update))
+                        {
+                            self.pending.pop_front();
+                        }
+                    }
+                }
+                // the tracker storage is written without holding the tree lock readers wait for
+                for tree_update in tree_updates.iter() {
+                    self.set_status(
+                        &tree_update.update.receipt_id(),
+                        UpdateStatus::Folded { step },
+                    );
+                }
+            }
+            StepOutcome::Failed {
+                tree_updates,
+                reason,
+            } => {
+                for tree_update in tree_updates.iter() {
+                    error!(
+                        "profile update of {} failed: {reason}",
+                        tree_update.update.eth_address()
+                    );
+                    self.set_status(
+                        &tree_update.update.receipt_id(),
+                        UpdateStatus::Failed {
+                            reason: reason.clone(),
+                        },
+                    );
+                }
+                self.revert(&tree_updates).await;
+            }
         }
     }
+    /// Revert the failed tree updates and all updates applied after them, then apply the
+    /// later updates again on top of the reverted tree
+    ///
+    /// Steps the folder received for the later updates start from a root it never reaches
+    /// and are discarded by it. If the tree cannot be reverted the later updates are marked
+    /// failed instead of applied again.
+    async fn revert(&mut self, failed: &[TreeUpdate]) {
+        let Some(first_failed) = failed.first() else {
+            return;
+        };
+        // updates of a step that was discarded as stale have been reverted already
+        let Some(position) = self
+            .pending
+            .iter()
+            .position(|pending| pending.is_same(first_failed))
+        else {
+            return;
+        };
+        let reverted = self.pending.split_off(position);
+        let reverted_tree = {
+            let mut state = self.state.write().unwrap();
+            let reverted_tree = reverted.iter().rev().try_for_each(|tree_update| {
+                let key = tree_update.key();
+                state.merkle_tree.revert_leaf(&key, &tree_update.old_leaf)?;
+                match &tree_update.old_message {
+                    Some(old_message) => state.messages.insert(key, old_message.clone()),
+                    None => state.messages.remove(&key),
+                };
+                Ok::<(), anyhow::Error>(())
+            });
+            debug!(
+                "Reverted {} tree updates, root: {:?}",
+                reverted.len(),
+                fe_to_biguint(&state.merkle_tree.root())
+            );
+            // timestamps of the messages the addresses have again after the revert,
+            // which may have been accepted with a legacy profile
+            reverted_tree.map(|()| {
+                failed
+                    .iter()
+                    .map(|tree_update| {
+                        state
+                            .messages
+                            .get(&tree_update.key())
+                            .and_then(|message| UserProfileUpdate::parse(message, true).ok())
+                            .map(|profile_update| profile_update.timestamp_ms)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        let previous_timestamps = match reverted_tree {
+            Ok(previous_timestamps) => previous_timestamps,
+            Err(e) => {
+                error!("failed to revert the Merkle tree: {e}");
+                let reason = format!("Merkle tree revert failed: {e}");
+                for tree_update in reverted.iter().skip(failed.len()) {
+                    self.set_status(
+                        &tree_update.update.receipt_id(),
+                        UpdateStatus::Failed {
+                            reason: reason.clone(),
+                        },
+                    );
+                }
+                return;
+            }
+        };
+        if let Some(replay_guard) = &self.replay_guard {
+            for (tree_update, previous) in failed.iter().zip(previous_timestamps) {
+                let address = tree_update.update.eth_address();
+                if let Err(e) =
+                    replay_guard.rewind(&address, tree_update.update.timestamp_ms(), previous)
+                {
+                    error!("failed to rewind the last timestamp of {address}: {e}");
+                }
+            }
+        }
+        for tree_update in reverted.into_iter().skip(failed.len()) {
+            self.apply(tree_update.update).await;
+        }
+    }
+}
+
+async fn recv_outcome(
+    rx_outcome: &mut Option<UnboundedReceiver<StepOutcome>>,
+) -> Option<StepOutcome> {
+    match rx_outcome {
+        Some(rx_outcome) => rx_outcome.recv().await,
+        None => std::future::pending().await,
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::server::UserProfileUpdate;
+    use crate::key_value_storage::LocalStorage;
 
     use super::*;
     #[tokio::test]
     async fn test_merkle_tree_updater() {
         // Create a test UserProfileUpdate
         let profile_update: UserProfileUpdate =
-            "10234345, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, tom, hanks"
+            "1023434500, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, f3:toml5:hanks"
                 .try_into()
                 .unwrap();
         let signature = "not real".to_string();
@@ -67,12 +364,9 @@ mod tests {
         // Create a MerkleTreeUpdater instance
         let (tx, rx) = tokio::sync::mpsc::channel(10);
         let (tx_result, mut rx_result) = tokio::sync::mpsc::channel(10);
-        let mut updater = MerkleTreeUpdater {
-            merkle_tree: MerkleTree::new(3),
-            rx,
-            tx: tx_result,
-        };
-        let old_root = updater.merkle_tree.root();
+        let mut updater = MerkleTreeUpdater::new(MerkleTree::new(3), rx, tx_result);
+        let tree_state = updater.tree_state();
+        let old_root = tree_state.read().unwrap().merkle_tree.root();
 
         // Spawn the updater task
         tokio::task::spawn(async move {
@@ -83,9 +377,157 @@ mod tests {
         tx.send(signed_profile_update).await.unwrap();
 
         // Receive the new root from the updater
-        let (new_root, _, _, _) = rx_result.recv().await.unwrap();
+        let tree_update = rx_result.recv().await.unwrap();
 
         // Assert that the new root is not empty
-        assert_ne!(old_root, new_root);
+        assert_ne!(old_root, tree_update.new_root);
+        assert_eq!(old_root, tree_update.prev_root);
+        assert_eq!(
+            tree_update.key(),
+            b"0x53e16f6d33c1809c14ba489a6917e9de849ab20c".to_vec()
+        );
+        assert_eq!(
+            tree_state.read().unwrap().merkle_tree.root(),
+            tree_update.new_root
+        );
+        assert_eq!(tree_update.old_message, None);
+
+        // the next update of the address replaces the message of the first one
+        let profile_update: UserProfileUpdate =
+            "1023434600, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, f3:toml5:hanks"
+                .try_into()
+                .unwrap();
+        tx.send(SignedUserProfileUpdate::from_profile_update(
+            profile_update,
+            "not real".to_string(),
+        ))
+        .await
+        .unwrap();
+        let second_update = rx_result.recv().await.unwrap();
+        assert_eq!(
+            second_update.old_message.as_deref(),
+            Some("1023434500, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, f3:toml5:hanks")
+        );
+        assert_eq!(second_update.old_leaf, tree_update.new_leaf);
+    }
+
+    #[tokio::test]
+    async fn test_merkle_tree_updater_reverts_failed_step() {
+        let signed_update = |profile: &str| {
+            let profile_update: UserProfileUpdate = profile.try_into().unwrap();
+            SignedUserProfileUpdate::from_profile_update(profile_update, "not real".to_string())
+        };
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+        let (tx_result, mut rx_result) = tokio::sync::mpsc::channel(10);
+        let (tx_outcome, rx_outcome) = tokio::sync::mpsc::unbounded_channel();
+        let replay_guard = Arc::new(ReplayGuard::new(LocalStorage::new()));
+        let mut updater = MerkleTreeUpdater::new(MerkleTree::new(3), rx, tx_result)
+            .with_step_outcomes(rx_outcome)
+            .with_replay_guard(Arc::clone(&replay_guard));
+        let old_root = updater.tree_state().read().unwrap().merkle_tree.root();
+        tokio::task::spawn(async move {
+            updater.run().await;
+        });
+
+        tx.send(signed_update(
+            "1023434500, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, f3:toml5:hanks",
+        ))
+        .await
+        .unwrap();
+        tx.send(signed_update(
+            "1023434600, 0x8ba1f109551bd432803012645ac136ddd64dba72, f3:megl4:ryan",
+        ))
+        .await
+        .unwrap();
+        // the server records the timestamps before it queues the updates
+        let failed_address = "0x53e16f6d33c1809c14ba489a6917e9de849ab20c";
+        let other_address = "0x8ba1f109551bd432803012645ac136ddd64dba72";
+        replay_guard
+            .advance(failed_address, 1023434500)
+            .unwrap()
+            .unwrap();
+        replay_guard
+            .advance(other_address, 1023434600)
+            .unwrap()
+            .unwrap();
+        let failed = rx_result.recv().await.unwrap();
+        let stale = rx_result.recv().await.unwrap();
+        assert_eq!(stale.prev_root, failed.new_root);
+
+        tx_outcome
+            .send(StepOutcome::Failed {
+                tree_updates: vec![failed],
+                reason: "test".to_string(),
+            })
+            .unwrap();
+
+        // the second update is applied again on top of the reverted tree
+        let reapplied = rx_result.recv().await.unwrap();
+        assert_eq!(reapplied.prev_root, old_root);
+        assert_eq!(reapplied.key(), stale.key());
+        assert_eq!(reapplied.new_leaf, stale.new_leaf);
+
+        // the failed message can be submitted again, the re-applied one cannot
+        assert_eq!(replay_guard.last_timestamp(failed_address).unwrap(), None);
+        assert_eq!(
+            replay_guard.last_timestamp(other_address).unwrap(),
+            Some(1023434600)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outcomes_exceeding_channel_capacity() {
+        // more updates than any channel of the pipeline holds, the folder reports every
+        // step while the updater may be blocked on sending the next tree update
+        let num_updates = 300;
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        let (tx_result, mut rx_result) = tokio::sync::mpsc::channel(1);
+        let (tx_outcome, rx_outcome) = tokio::sync::mpsc::unbounded_channel();
+        let mut updater = MerkleTreeUpdater::new(MerkleTree::new(3), rx, tx_result)
+            .with_step_outcomes(rx_outcome);
+        tokio::task::spawn(async move {
+            updater.run().await;
+        });
+        // stands in for the folder, every step is folded
+        let folder = tokio::task::spawn(async move {
+            let mut step = 0;
+            while let Some(tree_update) = rx_result.recv().await {
+                step += 1;
+                tx_outcome
+                    .send(StepOutcome::Folded {
+                        step,
+                        tree_updates: vec![tree_update],
+                    })
+                    .unwrap();
+            }
+            step
+        });
+
+        let send_updates = async move {
+            for i in 0..num_updates {
+                let profile_update: UserProfileUpdate = format!(
+                    "{}, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, f3:toml5:hanks",
+                    1023434500 + i
+                )
+                .as_str()
+                .try_into()
+                .unwrap();
+                tx.send(SignedUserProfileUpdate::from_profile_update(
+                    profile_update,
+                    "not real".to_string(),
+                ))
+                .await
+                .unwrap();
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(60), send_updates)
+            .await
+            .expect("pipeline deadlocked");
+        // the updater stops once the sender is dropped, then the folder
+        let folded = tokio::time::timeout(std::time::Duration::from_secs(60), folder)
+            .await
+            .expect("pipeline deadlocked")
+            .unwrap();
+        assert_eq!(folded, num_updates);
     }
 }
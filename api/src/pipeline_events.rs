@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Capacity of the event channel, slow subscribers skip the events they fall behind on
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+pub type EventSender = broadcast::Sender<PipelineEvent>;
+
+/// Progress of profile updates through the pipeline, streamed to the clients of `GET /events`
+///
+/// Addresses are lowercase.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PipelineEvent {
+    /// a signed update was accepted by the server
+    UpdateAccepted { receipt_id: String, address: String },
+    /// a signed update was accepted as attested-only, see `UpdateStatus::Attested`
+    UpdateAttested { receipt_id: String, address: String },
+    /// `MerkleTreeUpdater` applied the update to the tree
+    RootUpdated {
+        receipt_id: String,
+        address: String,
+        root: String,
+    },
+    /// `IVCProofFolder` folded the updates of `addresses` at step `step`
+    StepFolded {
+        step: usize,
+        addresses: Vec<String>,
+        root: String,
+    },
+    /// `CompressedProofBuilder` built a compressed proof of the first `num_steps` steps
+    ProofReady {
+        num_steps: usize,
+        proof_id: Option<u64>,
+    },
+}
+impl PipelineEvent {
+    /// Event name in the stream
+    pub fn name(&self) -> &'static str {
+        match self {
+            PipelineEvent::UpdateAccepted { .. } => "update_accepted",
+            PipelineEvent::UpdateAttested { .. } => "update_attested",
+            PipelineEvent::RootUpdated { .. } => "root_updated",
+            PipelineEvent::StepFolded { .. } => "step_folded",
+            PipelineEvent::ProofReady { .. } => "proof_ready",
+        }
+    }
+    /// Whether a client following `address` is interested in the event,
+    /// compressed proofs cover the updates of all addresses
+    pub fn concerns(&self, address: &str) -> bool {
+        let address = address.to_lowercase();
+        match self {
+            PipelineEvent::UpdateAccepted { address: a, .. }
+            | PipelineEvent::UpdateAttested { address: a, .. }
+            | PipelineEvent::RootUpdated { address: a, .. } => *a == address,
+            PipelineEvent::StepFolded { addresses, .. } => addresses.contains(&address),
+            PipelineEvent::ProofReady { .. } => true,
+        }
+    }
+}
+
+/// Publish `event` if the events are enabled
+pub fn send_event(events: &Option<EventSender>, event: PipelineEvent) {
+    if let Some(events) = events {
+        // fails only if nobody is subscribed
+        let _ = events.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concerns() {
+        let address = "0x631438556b66c4908579eab920dc162ff58958ea";
+        let accepted = PipelineEvent::UpdateAccepted {
+            receipt_id: "0x01".to_string(),
+            address: address.to_string(),
+        };
+        assert!(accepted.concerns("0x631438556b66c4908579Eab920dc162FF58958ea"));
+        assert!(!accepted.concerns("0x53e16f6d33c1809c14ba489a6917e9de849ab20c"));
+        let folded = PipelineEvent::StepFolded {
+            step: 1,
+            addresses: vec![address.to_string()],
+            root: "0x02".to_string(),
+        };
+        assert!(folded.concerns(address));
+        assert!(!folded.concerns("0x53e16f6d33c1809c14ba489a6917e9de849ab20c"));
+        let proof_ready = PipelineEvent::ProofReady {
+            num_steps: 1,
+            proof_id: Some(1),
+        };
+        assert!(proof_ready.concerns(address));
+        assert_eq!(
+            serde_json::to_value(&folded).unwrap()["event"],
+            serde_json::json!(folded.name())
+        );
+    }
+}
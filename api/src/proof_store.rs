@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::key_value_storage::KeyValueStorage;
+use crate::{CompressedProof, G1};
+use nova_snark::traits::Group;
+
+const LATEST_KEY: &str = "proof:latest";
+
+/// Metadata of a compressed proof
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProofMetadata {
+    pub id: u64,
+    pub num_steps: usize,
+    /// z0 as hex strings
+    pub z0: Vec<String>,
+    /// z_n (Merkle root and timestamp) as hex strings
+    pub z_n: Vec<String>,
+    pub created_at_ms: u64,
+    /// keccak256 of the json serialized verifier key
+    pub vk_digest: String,
+}
+impl ProofMetadata {
+    pub fn new(
+        num_steps: usize,
+        z0: &[<G1 as Group>::Scalar],
+        z_n: &[<G1 as Group>::Scalar],
+        created_at_ms: u64,
+        vk_digest: String,
+    ) -> Self {
+        let to_hex = |z: &[<G1 as Group>::Scalar]| z.iter().map(|x| format!("{:?}", x)).collect();
+        Self {
+            id: 0,
+            num_steps,
+            z0: to_hex(z0),
+            z_n: to_hex(z_n),
+            created_at_ms,
+            vk_digest,
+        }
+    }
+}
+
+/// Compressed proofs and their metadata in a key value storage
+///
+/// Proof ids are assigned sequentially starting from 1, the metadata is stored
+/// at `proof:<id>:metadata` and the proof at `proof:<id>`.
+pub struct ProofStore {
+    storage: Box<dyn KeyValueStorage + Send + Sync>,
+}
+impl ProofStore {
+    pub fn new(storage: impl KeyValueStorage + Send + Sync + 'static) -> Self {
+        Self {
+            storage: Box::new(storage),
+        }
+    }
+    /// Store the proof and return its id
+    pub fn insert(
+        &self,
+        metadata: ProofMetadata,
+        compressed_proof: &CompressedProof,
+    ) -> Result<u64> {
+        self.insert_serialized(metadata, &serde_json::to_string(compressed_proof)?)
+    }
+    fn insert_serialized(
+        &self,
+        mut metadata: ProofMetadata,
+        compressed_proof: &str,
+    ) -> Result<u64> {
+        let id = self.latest_id()?.unwrap_or(0) + 1;
+        metadata.id = id;
+        self.storage.set(&format!("proof:{id}"), compressed_proof)?;
+        self.storage.set(
+            &format!("proof:{id}:metadata"),
+            &serde_json::to_string(&metadata)?,
+        )?;
+        // written last, so the latest id always refers to a complete proof
+        self.storage.set(LATEST_KEY, &id.to_string())?;
+        Ok(id)
+    }
+    pub fn latest_id(&self) -> Result<Option<u64>> {
+        self.storage
+            .get(LATEST_KEY)?
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| anyhow!("invalid latest proof id {id}"))
+            })
+            .transpose()
+    }
+    pub fn metadata(&self, id: u64) -> Result<Option<ProofMetadata>> {
+        self.storage
+            .get(&format!("proof:{id}:metadata"))?
+            .map(|metadata| serde_json::from_str(&metadata))
+            .transpose()
+            .map_err(Into::into)
+    }
+    pub fn compressed_proof(&self, id: u64) -> Result<Option<CompressedProof>> {
+        self.storage
+            .get(&format!("proof:{id}"))?
+            .map(|proof| serde_json::from_str(&proof))
+            .transpose()
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_value_storage::tests::UnavailableStorage;
+    use crate::key_value_storage::LocalStorage;
+    use ff::PrimeField;
+    use nova_scotia::F;
+
+    #[test]
+    fn test_insert_serialized() {
+        let store = ProofStore::new(LocalStorage::new());
+        assert_eq!(store.latest_id().unwrap(), None);
+
+        let z0 = vec![F::<G1>::from(1), F::<G1>::from(170345900)];
+        let z_n = vec![F::<G1>::from(2), F::<G1>::from(170345901)];
+        let metadata = ProofMetadata::new(2, &z0, &z_n, 1703459910000, "0x01".to_string());
+        assert_eq!(store.insert_serialized(metadata.clone(), "{}").unwrap(), 1);
+        assert_eq!(store.insert_serialized(metadata.clone(), "{}").unwrap(), 2);
+
+        assert_eq!(store.latest_id().unwrap(), Some(2));
+        let stored = store.metadata(1).unwrap().unwrap();
+        assert_eq!(stored.id, 1);
+        assert_eq!(stored.num_steps, 2);
+        assert_eq!(stored.z_n, metadata.z_n);
+        assert_eq!(
+            F::<G1>::from_str_vartime("170345901").map(|x| format!("{:?}", x)),
+            Some(stored.z_n[1].clone())
+        );
+        assert_eq!(store.metadata(3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_storage_unavailable() {
+        let store = ProofStore::new(UnavailableStorage);
+        assert!(store.latest_id().is_err());
+        assert!(store.metadata(1).is_err());
+        assert!(store.compressed_proof(1).is_err());
+        let metadata = ProofMetadata::new(1, &[], &[], 1703459910000, "0x01".to_string());
+        assert!(store.insert_serialized(metadata, "{}").is_err());
+    }
+}
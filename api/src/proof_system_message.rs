@@ -1,39 +1,157 @@
 use std::collections::HashMap;
 
-use crate::eff_ecdsa_input::ECrv;
-use crate::eff_ecdsa_input::{eff_ecdsa_input, fe_to_biguint, scalar_to_biguint, ScalarSecp};
+use crate::eff_ecdsa_input::{
+    eff_ecdsa_input, fe_to_biguint, scalar_to_biguint, DecodedSignature, HashMode,
+};
+use crate::merkle_tree_updater::TreeUpdate;
 use crate::server::SignedUserProfileUpdate;
 use crate::{server::Signature, server::UserProfileUpdate};
 use bitvec::prelude::*;
 use common::utils::bits::pad_msg;
 use common::BIT_SIZE;
-use elliptic_curve::FieldBytes;
-use elliptic_curve::PrimeField;
 use merkle_tree::{Hash, HashDirection, Sibling};
 use serde_json::Value;
+use std::time::Duration;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{sleep_until, Instant};
 use tracing::debug;
 
 pub type ProofSystemMessage = HashMap<String, Value>;
 
+/// Keys of a single step input of `ivc.circom`
+const PROOF_SYSTEM_MSG_KEYS: [&str; 7] = [
+    "message",
+    "typed",
+    "signatures",
+    "old_message_poseidon_hash",
+    "old_message",
+    "pathIndices",
+    "siblings",
+];
+
+/// Inputs added to the circuits after artifacts were compiled,
+/// step inputs for artifacts without them leave them out
+pub const OPTIONAL_INPUTS: [&str; 2] = ["old_message", "typed"];
+
+/// Default time a partial batch waits for more updates before it is padded and emitted
+pub const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Step input of the folding circuit together with the tree updates it proves
+#[derive(Debug, Clone)]
+pub struct ProofSystemStep {
+    pub msg: ProofSystemMessage,
+    pub tree_updates: Vec<TreeUpdate>,
+}
+
 pub struct ProofSystemMessageBuilder {
-    rx: Receiver<(Hash, SignedUserProfileUpdate, Hash, Vec<Sibling>)>,
-    tx: Sender<ProofSystemMessage>,
+    rx: Receiver<TreeUpdate>,
+    tx: Sender<ProofSystemStep>,
+    batch_size: usize,
+    flush_timeout: Duration,
+    hash_mode: HashMode,
+    missing_inputs: Vec<&'static str>,
 }
 
 impl ProofSystemMessageBuilder {
-    pub fn new(
-        rx: Receiver<(Hash, SignedUserProfileUpdate, Hash, Vec<Sibling>)>,
-        tx: Sender<ProofSystemMessage>,
-    ) -> Self {
-        Self { rx, tx }
+    pub fn new(rx: Receiver<TreeUpdate>, tx: Sender<ProofSystemStep>) -> Self {
+        Self {
+            rx,
+            tx,
+            batch_size: 1,
+            flush_timeout: DEFAULT_FLUSH_TIMEOUT,
+            hash_mode: HashMode::default(),
+            missing_inputs: vec![],
+        }
+    }
+    /// Group `batch_size` consecutive updates into one step input
+    /// for the `ivc(N_DEPTH, N_SIGS)` circuit of `ivc_batch.circom` with `N_SIGS = batch_size`.
+    ///
+    /// A batch size of 1 emits the flat messages expected by `ivc.circom`.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be positive");
+        self.batch_size = batch_size;
+        self
+    }
+    /// Emit a partial batch `flush_timeout` after its first update arrived,
+    /// the missing updates are padded with disabled slots
+    pub fn with_flush_timeout(mut self, flush_timeout: Duration) -> Self {
+        self.flush_timeout = flush_timeout;
+        self
+    }
+    /// Hash the signed messages like `hash_mode`, has to match the circuit
+    pub fn with_hash_mode(mut self, hash_mode: HashMode) -> Self {
+        self.hash_mode = hash_mode;
+        self
+    }
+    /// `OPTIONAL_INPUTS` the compiled circuit does not have, they are left out of the step
+    /// inputs since circom rejects inputs it does not know
+    pub fn with_missing_inputs(mut self, missing_inputs: Vec<&'static str>) -> Self {
+        self.missing_inputs = missing_inputs;
+        self
     }
     pub async fn run(&mut self) {
-        debug!("Proof System Message Builder started");
-        while let Some((_, update, prev_leaf_hash, siblings)) = self.rx.recv().await {
-            let proof_system_msg = make_proof_system_msg(&update, &prev_leaf_hash, &siblings);
-            let _ = self.tx.send(proof_system_msg).await;
+        debug!(
+            "Proof System Message Builder started, batch size {}",
+            self.batch_size
+        );
+        let mut batch = Vec::with_capacity(self.batch_size);
+        // deadline of the current partial batch
+        let mut flush_at = Instant::now();
+        loop {
+            let tree_update = tokio::select! {
+                tree_update = self.rx.recv() => tree_update,
+                _ = sleep_until(flush_at), if !batch.is_empty() => {
+                    debug!("Flushing partial batch of {} updates", batch.len());
+                    self.send_batch(&mut batch).await;
+                    continue;
+                }
+            };
+            let Some(tree_update) = tree_update else {
+                break;
+            };
+            if self.batch_size == 1 {
+                let mut proof_system_msg = make_proof_system_msg(
+                    &tree_update.update,
+                    &tree_update.old_leaf,
+                    tree_update.old_message.as_deref(),
+                    &tree_update.siblings,
+                    self.hash_mode,
+                );
+                for input in self.missing_inputs.iter() {
+                    proof_system_msg.remove(*input);
+                }
+                let step = ProofSystemStep {
+                    msg: proof_system_msg,
+                    tree_updates: vec![tree_update],
+                };
+                let _ = self.tx.send(step).await;
+                continue;
+            }
+            // updates arrive in the order they were inserted into the tree,
+            // so the siblings of each update already account for the previous ones
+            if batch.is_empty() {
+                flush_at = Instant::now() + self.flush_timeout;
+            }
+            batch.push(tree_update);
+            if batch.len() == self.batch_size {
+                self.send_batch(&mut batch).await;
+            }
+        }
+        if !batch.is_empty() {
+            debug!("Flushing partial batch of {} updates", batch.len());
+            self.send_batch(&mut batch).await;
+        }
+    }
+    async fn send_batch(&self, batch: &mut Vec<TreeUpdate>) {
+        let mut msg = make_batch_proof_system_msg(batch, self.batch_size, self.hash_mode);
+        for input in self.missing_inputs.iter() {
+            msg.remove(*input);
         }
+        let step = ProofSystemStep {
+            msg,
+            tree_updates: std::mem::take(batch),
+        };
+        let _ = self.tx.send(step).await;
     }
 }
 
@@ -60,23 +178,35 @@ impl ProofSystemMessageBuilder {
 //     signature: String, // Signature of the message, "0x...."
 // }
 
+/// `prev_message` is the profile message of `prev_leaf_hash`, `None` for an address without a profile
 pub fn make_proof_system_msg(
     update: &SignedUserProfileUpdate,
     prev_leaf_hash: &Hash,
+    prev_message: Option<&str>,
     siblings: &[Sibling],
+    hash_mode: HashMode,
 ) -> ProofSystemMessage {
     let eth_address = &update.eth_address();
     let mut proof_system_msg = HashMap::new();
     let msg_val = make_message_val(&update.profile_update.unparsed_profile);
     proof_system_msg.insert("message".to_string(), msg_val);
+    let typed = if update.eip712_domain.is_some() {
+        "1"
+    } else {
+        "0"
+    };
+    proof_system_msg.insert("typed".to_string(), Value::from(vec![typed]));
     let signatures_val = make_signatures_val(
         &update.user_signature,
         eth_address,
-        &update.profile_update.unparsed_profile,
+        &update.message_hash(hash_mode),
     );
     proof_system_msg.insert("signatures".to_string(), signatures_val);
     let prev_leaf_hash_val = Value::from(vec![fe_to_biguint(&prev_leaf_hash).to_str_radix(10)]);
     proof_system_msg.insert("old_message_poseidon_hash".to_string(), prev_leaf_hash_val);
+    // all zero bits for an address without a profile
+    let prev_msg_val = make_message_val(prev_message.unwrap_or_default());
+    proof_system_msg.insert("old_message".to_string(), prev_msg_val);
     let path_indices = siblings
         .iter()
         .map(|s| match s.direction {
@@ -95,15 +225,58 @@ pub fn make_proof_system_msg(
     proof_system_msg
 }
 
-fn make_signatures_val(signature: &Signature, eth_address: &str, msg: &str) -> Value {
-    let decoded_sig = hex::decode(signature[2..].to_string()).unwrap();
-    let r_bytes = &decoded_sig[..32];
-    let s_bytes = &decoded_sig[32..64];
-    let (r, s) = (
-        ScalarSecp::from_repr(*FieldBytes::<ECrv>::from_slice(r_bytes)).unwrap(),
-        ScalarSecp::from_repr(*FieldBytes::<ECrv>::from_slice(s_bytes)).unwrap(),
+/// Step input for `ivc_batch.circom`
+///
+/// Every key of the single update message becomes an array with one entry per update,
+/// e.g. "message":[["0","1",...],["1","0",...]], "old_message_poseidon_hash":[["..."],["..."]].
+/// The updates have to be given in the order they were applied to the Merkle tree.
+///
+/// Fewer than `batch_size` updates are padded by repeating the last one in slots with
+/// "enabled" 0, which leave the root unchanged.
+pub fn make_batch_proof_system_msg(
+    tree_updates: &[TreeUpdate],
+    batch_size: usize,
+    hash_mode: HashMode,
+) -> ProofSystemMessage {
+    assert!(
+        !tree_updates.is_empty() && tree_updates.len() <= batch_size,
+        "a batch has between 1 and {batch_size} updates"
     );
-    let (r_inv, s, t_x, t_y, u_x, u_y) = eff_ecdsa_input(r, s, eth_address, msg);
+    let mut msgs = tree_updates
+        .iter()
+        .map(|tree_update| {
+            make_proof_system_msg(
+                &tree_update.update,
+                &tree_update.old_leaf,
+                tree_update.old_message.as_deref(),
+                &tree_update.siblings,
+                hash_mode,
+            )
+        })
+        .collect::<Vec<ProofSystemMessage>>();
+    let padding = msgs.last().cloned().expect("non-empty batch");
+    msgs.resize(batch_size, padding);
+    let mut batch_msg = HashMap::new();
+    for key in PROOF_SYSTEM_MSG_KEYS {
+        let values = msgs
+            .iter()
+            .map(|msg| msg[key].clone())
+            .collect::<Vec<Value>>();
+        batch_msg.insert(key.to_string(), Value::from(values));
+    }
+    let enabled = (0..batch_size)
+        .map(|i| if i < tree_updates.len() { "1" } else { "0" })
+        .collect::<Vec<&str>>();
+    batch_msg.insert("enabled".to_string(), Value::from(enabled));
+    batch_msg
+}
+
+fn make_signatures_val(signature: &Signature, eth_address: &str, msg_hash: &[u8]) -> Value {
+    // checked by the server, s is normalized like there
+    let DecodedSignature { r, s, .. } = DecodedSignature::from_hex(signature)
+        .expect("signature of a checked update")
+        .normalized();
+    let (r_inv, s, t_x, t_y, u_x, u_y) = eff_ecdsa_input(r, s, eth_address, msg_hash);
     let r_inv_str = scalar_to_biguint(&r_inv).to_str_radix(10);
     let s_str = scalar_to_biguint(&s).to_str_radix(10);
     let u_x_str = fe_to_biguint(&u_x).to_str_radix(10);
@@ -135,13 +308,16 @@ fn get_bits_string(input_string: &str, bit_size: usize) -> Vec<String> {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::eff_ecdsa_input::hash_msg;
+    use elliptic_curve::PrimeField;
     use merkle_tree::HashDirection;
     use tokio::sync::mpsc;
 
-    // Helper function to create a dummy UserProfileUpdate (placeholder)
+    // Helper function to create a dummy UserProfileUpdate (placeholder), the message of
+    // `dummy_signature` has a legacy profile
     pub fn dummy_user_profile_update() -> UserProfileUpdate {
         let msg = "1703459910, 0x631438556b66c4908579Eab920dc162FF58958ea, Brad, Pitt, brad.pitt@gmail.com";
-        msg.try_into().unwrap()
+        UserProfileUpdate::parse(msg, true).unwrap()
     }
 
     // Helper function to create a dummy Signature (placeholder)
@@ -149,6 +325,27 @@ pub mod tests {
         String::from("0x7c62b0e515eb044b731e244904d6efc7cb6dad49b061095b92c33443cb9bfa68f1a61d9f25c990e3c6fc94b280b181bfc77266eca37e77d123e0e67892ee5efc")
     }
 
+    // Helper function to create an update of the address of a test key, signed for `ivc.circom`
+    pub fn signed_test_update(timestamp: u64) -> SignedUserProfileUpdate {
+        use web3::signing::{Key, SecretKey, SecretKeyRef};
+        let secret_key = SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let key = SecretKeyRef::new(&secret_key);
+        let msg = format!(
+            "{timestamp}, 0x{}, f4:Bradl4:Pitt",
+            hex::encode(key.address())
+        );
+        let signature = key
+            .sign(&hash_msg(msg.as_bytes(), HashMode::Padded), None)
+            .unwrap();
+        let signature = format!(
+            "0x{}{}{:02x}",
+            hex::encode(signature.r),
+            hex::encode(signature.s),
+            signature.v
+        );
+        SignedUserProfileUpdate::from_profile_update(msg.as_str().try_into().unwrap(), signature)
+    }
+
     // Helper function to create a dummy Hash (placeholder)
     pub fn zero_hash() -> Hash {
         Hash::from_str_vartime(
@@ -183,6 +380,19 @@ pub mod tests {
             },
         ]
     }
+    // Helper function to create a TreeUpdate of the dummy profile update
+    pub fn dummy_tree_update(old_leaf: Hash) -> TreeUpdate {
+        let update = dummy_user_profile_update();
+        TreeUpdate {
+            update: SignedUserProfileUpdate::from_profile_update(update, dummy_signature()),
+            prev_root: zero_hash(),
+            new_root: dummy_first_hash(),
+            old_leaf,
+            old_message: None,
+            new_leaf: dummy_first_hash(),
+            siblings: dummy_siblings(),
+        }
+    }
     pub fn dummy_eth_address() -> String {
         "0x631438556b66c4908579Eab920dc162FF58958ea".to_string()
     }
@@ -195,16 +405,120 @@ pub mod tests {
         let prev_leaf_hash = zero_hash();
         let siblings = dummy_siblings();
 
-        let proof_system_msg = make_proof_system_msg(&signed_update, &prev_leaf_hash, &siblings);
+        let proof_system_msg = make_proof_system_msg(
+            &signed_update,
+            &prev_leaf_hash,
+            None,
+            &siblings,
+            HashMode::Padded,
+        );
 
         assert!(proof_system_msg.contains_key("message"));
         assert!(proof_system_msg.contains_key("signatures"));
         assert!(proof_system_msg.contains_key("old_message_poseidon_hash"));
         assert!(proof_system_msg.contains_key("pathIndices"));
         assert!(proof_system_msg.contains_key("siblings"));
+        // no previous profile
+        let old_message = proof_system_msg["old_message"].as_array().unwrap();
+        assert_eq!(old_message.len(), BIT_SIZE);
+        assert!(old_message.iter().all(|bit| *bit == "0"));
         // Additional assertions to check the structure of the HashMap...
     }
 
+    #[tokio::test]
+    async fn test_batch_proof_system_message_structure() {
+        let siblings = dummy_siblings();
+        let updates = vec![
+            dummy_tree_update(zero_hash()),
+            dummy_tree_update(dummy_first_hash()),
+        ];
+
+        let batch_msg = make_batch_proof_system_msg(&updates, 2, HashMode::Padded);
+
+        for key in PROOF_SYSTEM_MSG_KEYS {
+            let values = batch_msg[key].as_array().unwrap();
+            assert_eq!(values.len(), 2);
+        }
+        assert_eq!(batch_msg["enabled"], Value::from(vec!["1", "1"]));
+        let message = batch_msg["message"].as_array().unwrap();
+        assert_eq!(message[0].as_array().unwrap().len(), BIT_SIZE);
+        let old_hashes = batch_msg["old_message_poseidon_hash"].as_array().unwrap();
+        assert_eq!(
+            old_hashes[1],
+            Value::from(vec![fe_to_biguint(&dummy_first_hash()).to_str_radix(10)])
+        );
+        let batch_siblings = batch_msg["siblings"].as_array().unwrap();
+        assert_eq!(batch_siblings[0].as_array().unwrap().len(), siblings.len());
+    }
+
+    #[tokio::test]
+    async fn test_padded_batch_proof_system_message() {
+        let updates = vec![dummy_tree_update(zero_hash())];
+
+        let batch_msg = make_batch_proof_system_msg(&updates, 3, HashMode::Padded);
+
+        for key in PROOF_SYSTEM_MSG_KEYS {
+            let values = batch_msg[key].as_array().unwrap();
+            assert_eq!(values.len(), 3);
+            // padding slots repeat the last update
+            assert_eq!(values[1], values[0]);
+            assert_eq!(values[2], values[0]);
+        }
+        assert_eq!(batch_msg["enabled"], Value::from(vec!["1", "0", "0"]));
+    }
+
+    #[tokio::test]
+    async fn test_proof_system_message_builder_batched_run() {
+        let (tx, rx) = mpsc::channel(10);
+        let (psmb_tx, mut psmb_rx) = mpsc::channel(10);
+        let mut builder = ProofSystemMessageBuilder::new(rx, psmb_tx).with_batch_size(2);
+
+        tokio::spawn(async move {
+            builder.run().await;
+        });
+
+        tx.send(dummy_tree_update(zero_hash())).await.unwrap();
+        // a single update does not fill the batch
+        tokio::task::yield_now().await;
+        assert!(psmb_rx.try_recv().is_err());
+
+        tx.send(dummy_tree_update(dummy_first_hash()))
+            .await
+            .unwrap();
+        let step = psmb_rx.recv().await.unwrap();
+        assert_eq!(step.msg["signatures"].as_array().unwrap().len(), 2);
+        assert_eq!(step.msg["enabled"], Value::from(vec!["1", "1"]));
+        assert_eq!(step.tree_updates.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_proof_system_message_builder_flushes_partial_batch() {
+        let (tx, rx) = mpsc::channel(10);
+        let (psmb_tx, mut psmb_rx) = mpsc::channel(10);
+        let mut builder = ProofSystemMessageBuilder::new(rx, psmb_tx)
+            .with_batch_size(2)
+            .with_flush_timeout(Duration::from_millis(50));
+
+        tokio::spawn(async move {
+            builder.run().await;
+        });
+
+        tx.send(dummy_tree_update(zero_hash())).await.unwrap();
+        let step = psmb_rx.recv().await.unwrap();
+        assert_eq!(step.msg["signatures"].as_array().unwrap().len(), 2);
+        assert_eq!(step.msg["enabled"], Value::from(vec!["1", "0"]));
+        assert_eq!(step.tree_updates.len(), 1);
+
+        // the remaining update is flushed when the tree updater stops
+        tx.send(dummy_tree_update(dummy_first_hash()))
+            .await
+            .unwrap();
+        drop(tx);
+        let step = psmb_rx.recv().await.unwrap();
+        assert_eq!(step.tree_updates.len(), 1);
+        assert!(psmb_rx.recv().await.is_none());
+    }
+
     #[tokio::test]
     async fn test_make_signatures_val() {
         // You need a real signature and real message to test this function properly.
@@ -213,8 +527,11 @@ pub mod tests {
         let eth_address = dummy_eth_address();
         let signature = dummy_signature();
 
-        let signatures_val =
-            make_signatures_val(&signature, &eth_address, &update.unparsed_profile);
+        let signatures_val = make_signatures_val(
+            &signature,
+            &eth_address,
+            &hash_msg(update.unparsed_profile.as_bytes(), HashMode::Padded),
+        );
 
         // Verify that signatures_val contains the expected number of elements
         // and that each element is a correctly formatted number string.
@@ -245,32 +562,21 @@ pub mod tests {
         let (psmb_tx, mut psmb_rx) = mpsc::channel(1);
 
         // Create a dummy ProofSystemMessageBuilder
-        let mut builder = ProofSystemMessageBuilder::new(rx, psmb_tx.clone());
+        let mut builder = ProofSystemMessageBuilder::new(rx, psmb_tx);
 
-        // Simulate sending a message to the ProofSystemMessageBuilder
-        let update = dummy_user_profile_update();
-        let signature = dummy_signature();
-        let signed_update = SignedUserProfileUpdate::from_profile_update(update, signature);
-        let prev_leaf_hash = zero_hash();
-        let siblings = dummy_siblings();
-        let proof_system_message =
-            make_proof_system_msg(&signed_update, &prev_leaf_hash, &siblings);
-
-        tx.send((zero_hash(), signed_update, prev_leaf_hash, siblings))
-            .await
-            .unwrap();
+        // Simulate sending a tree update to the ProofSystemMessageBuilder
+        tx.send(dummy_tree_update(zero_hash())).await.unwrap();
 
         // Run the builder in a separate async task
         tokio::spawn(async move {
             builder.run().await;
         });
 
-        // Send the signed message to the ProofSystemMessageBuilder
-        psmb_tx.send(proof_system_message).await.unwrap();
-
         // Receive the result from the builder
         // Here we expect to receive a ProofSystemMessage generated by the builder
-        if let Some(proof_system_msg) = psmb_rx.recv().await {
+        if let Some(step) = psmb_rx.recv().await {
+            assert_eq!(step.tree_updates.len(), 1);
+            let proof_system_msg = step.msg;
             assert!(proof_system_msg.contains_key("message"));
             assert!(proof_system_msg.contains_key("signatures"));
             assert!(proof_system_msg.contains_key("old_message_poseidon_hash"));
@@ -0,0 +1,192 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use ff::PrimeField;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use nova_scotia::circom::circuit::R1CS;
+use serde::{de::DeserializeOwned, Serialize};
+use sha3::{Digest, Keccak256};
+
+/// Public parameters file layout:
+///
+/// ```text
+/// magic (4 bytes) | version (u16 le) | flags (u8) | r1cs digest (32 bytes) | bincode payload
+/// ```
+///
+/// The payload is zlib compressed if `FLAG_COMPRESSED` is set.
+const MAGIC: &[u8; 4] = b"NVPP";
+pub const FORMAT_VERSION: u16 = 1;
+const FLAG_COMPRESSED: u8 = 1;
+
+pub type R1csDigest = [u8; 32];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamsHeader {
+    pub version: u16,
+    pub compressed: bool,
+    pub r1cs_digest: R1csDigest,
+}
+impl ParamsHeader {
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        let flags = if self.compressed { FLAG_COMPRESSED } else { 0 };
+        writer.write_all(&[flags])?;
+        writer.write_all(&self.r1cs_digest)?;
+        Ok(())
+    }
+    fn read(reader: &mut impl Read) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(anyhow!("not a public parameters file"));
+        }
+        let mut version = [0u8; 2];
+        reader.read_exact(&mut version)?;
+        let mut flags = [0u8; 1];
+        reader.read_exact(&mut flags)?;
+        let mut r1cs_digest = [0u8; 32];
+        reader.read_exact(&mut r1cs_digest)?;
+        Ok(Self {
+            version: u16::from_le_bytes(version),
+            compressed: flags[0] & FLAG_COMPRESSED != 0,
+            r1cs_digest,
+        })
+    }
+}
+
+/// Keccak256 digest of the shape and the constraints of `r1cs`
+pub fn r1cs_digest<Fr: PrimeField>(r1cs: &R1CS<Fr>) -> R1csDigest {
+    let mut hasher = Keccak256::new();
+    for n in [r1cs.num_inputs, r1cs.num_aux, r1cs.num_variables] {
+        hasher.update((n as u64).to_le_bytes());
+    }
+    hasher.update((r1cs.constraints.len() as u64).to_le_bytes());
+    for (a, b, c) in r1cs.constraints.iter() {
+        for lc in [a, b, c] {
+            hasher.update((lc.len() as u64).to_le_bytes());
+            for (index, coeff) in lc.iter() {
+                hasher.update((*index as u64).to_le_bytes());
+                hasher.update(coeff.to_repr());
+            }
+        }
+    }
+    hasher.finalize().into()
+}
+
+/// Content of a parameters file
+#[derive(Debug, PartialEq, Eq)]
+pub enum StoredParams<T> {
+    Current(T),
+    /// generated for another circuit
+    Stale {
+        r1cs_digest: R1csDigest,
+    },
+    /// another format version or the former json format
+    Outdated,
+}
+
+pub fn write_params<T: Serialize>(
+    params_file: &Path,
+    params: &T,
+    r1cs_digest: R1csDigest,
+    compressed: bool,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(params_file)?);
+    let header = ParamsHeader {
+        version: FORMAT_VERSION,
+        compressed,
+        r1cs_digest,
+    };
+    header.write(&mut writer)?;
+    if compressed {
+        let mut encoder = ZlibEncoder::new(writer, Compression::default());
+        bincode::serialize_into(&mut encoder, params)?;
+        encoder.finish()?.flush()?;
+    } else {
+        bincode::serialize_into(&mut writer, params)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Parameters from `params_file` if it has the current format version and was
+/// generated for the r1cs with `r1cs_digest`
+pub fn read_params<T: DeserializeOwned>(
+    params_file: &Path,
+    r1cs_digest: &R1csDigest,
+) -> Result<StoredParams<T>> {
+    let mut reader = BufReader::new(File::open(params_file)?);
+    let header = match ParamsHeader::read(&mut reader) {
+        Ok(header) if header.version == FORMAT_VERSION => header,
+        _ => return Ok(StoredParams::Outdated),
+    };
+    if header.r1cs_digest != *r1cs_digest {
+        return Ok(StoredParams::Stale {
+            r1cs_digest: header.r1cs_digest,
+        });
+    }
+    let params = if header.compressed {
+        bincode::deserialize_from(ZlibDecoder::new(reader))?
+    } else {
+        bincode::deserialize_from(reader)?
+    };
+    Ok(StoredParams::Current(params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::G1;
+    use nova_scotia::F;
+
+    fn temp_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{name}_{}", std::process::id()))
+    }
+
+    #[test]
+    fn test_params_round_trip() {
+        let params = (0..1000u64).collect::<Vec<_>>();
+        let r1cs_digest = [7u8; 32];
+        for compressed in [false, true] {
+            let params_file = temp_file(&format!("params_{compressed}"));
+            write_params(&params_file, &params, r1cs_digest, compressed).unwrap();
+            let read = read_params::<Vec<u64>>(&params_file, &r1cs_digest).unwrap();
+            assert_eq!(read, StoredParams::Current(params.clone()));
+            // a different circuit needs new parameters
+            let read = read_params::<Vec<u64>>(&params_file, &[8u8; 32]).unwrap();
+            assert_eq!(read, StoredParams::Stale { r1cs_digest });
+            std::fs::remove_file(&params_file).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_params_json() {
+        let params_file = temp_file("params_json");
+        std::fs::write(&params_file, "{\"params\": []}").unwrap();
+        let read = read_params::<Vec<u64>>(&params_file, &[0u8; 32]).unwrap();
+        assert_eq!(read, StoredParams::Outdated);
+        std::fs::remove_file(&params_file).unwrap();
+    }
+
+    #[test]
+    fn test_r1cs_digest() {
+        let one = F::<G1>::from(1);
+        let r1cs = R1CS {
+            num_inputs: 2,
+            num_aux: 1,
+            num_variables: 3,
+            constraints: vec![(vec![(0, one)], vec![(1, one)], vec![(2, one)])],
+        };
+        let mut other_r1cs = r1cs.clone();
+        assert_eq!(r1cs_digest(&r1cs), r1cs_digest(&other_r1cs));
+        other_r1cs.constraints[0].2 = vec![(2, F::<G1>::from(2))];
+        assert_ne!(r1cs_digest(&r1cs), r1cs_digest(&other_r1cs));
+        other_r1cs.constraints.push(r1cs.constraints[0].clone());
+        assert_ne!(r1cs_digest(&r1cs), r1cs_digest(&other_r1cs));
+    }
+}
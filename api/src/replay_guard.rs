@@ -0,0 +1,129 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+
+use crate::key_value_storage::KeyValueStorage;
+
+/// Timestamp of the latest accepted update of every address in a key value storage
+///
+/// The updates of an address have to carry strictly increasing timestamps, so a signed
+/// message cannot be submitted twice. The timestamp of an address is stored at
+/// `last_timestamp:<address>`, addresses are lowercase.
+pub struct ReplayGuard {
+    storage: Box<dyn KeyValueStorage + Send + Sync>,
+    /// makes the check and the update of a timestamp atomic
+    lock: Mutex<()>,
+}
+impl ReplayGuard {
+    pub fn new(storage: impl KeyValueStorage + Send + Sync + 'static) -> Self {
+        Self {
+            storage: Box::new(storage),
+            lock: Mutex::new(()),
+        }
+    }
+    pub fn last_timestamp(&self, address: &str) -> Result<Option<u64>> {
+        Ok(self
+            .storage
+            .get(&key(address))?
+            .and_then(|timestamp| timestamp.parse().ok()))
+    }
+    /// Record `timestamp` for `address` if it is later than the last one
+    ///
+    /// Returns the previous timestamp, to `restore` it if the update is not accepted
+    /// after all, or the last timestamp that `timestamp` does not exceed.
+    /// The outer error is a failure of the storage.
+    pub fn advance(&self, address: &str, timestamp: u64) -> Result<Result<Option<u64>, u64>> {
+        let _lock = self.lock.lock().unwrap();
+        let previous = self.last_timestamp(address)?;
+        match previous {
+            Some(last) if timestamp <= last => Ok(Err(last)),
+            _ => {
+                self.storage.set(&key(address), &timestamp.to_string())?;
+                Ok(Ok(previous))
+            }
+        }
+    }
+    /// Undo `advance` with the previous timestamp it returned
+    pub fn restore(&self, address: &str, previous: Option<u64>) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        self.set(address, previous)
+    }
+    /// Undo the `advance` to `timestamp` of an update that was reverted later on
+    ///
+    /// The timestamp is kept if a later update of `address` was accepted in the meantime.
+    pub fn rewind(&self, address: &str, timestamp: u64, previous: Option<u64>) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        if self.last_timestamp(address)? == Some(timestamp) {
+            self.set(address, previous)?;
+        }
+        Ok(())
+    }
+    fn set(&self, address: &str, timestamp: Option<u64>) -> Result<()> {
+        match timestamp {
+            Some(timestamp) => self.storage.set(&key(address), &timestamp.to_string()),
+            None => self.storage.del(&key(address)),
+        }
+    }
+}
+
+fn key(address: &str) -> String {
+    format!("last_timestamp:{}", address.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_value_storage::LocalStorage;
+
+    #[test]
+    fn test_replay_guard() {
+        let guard = ReplayGuard::new(LocalStorage::new());
+        let address = "0x631438556b66c4908579Eab920dc162FF58958ea";
+        assert_eq!(guard.last_timestamp(address).unwrap(), None);
+
+        assert_eq!(guard.advance(address, 1703459910).unwrap(), Ok(None));
+        // replay of the same message
+        assert_eq!(guard.advance(address, 1703459910).unwrap(), Err(1703459910));
+        assert_eq!(guard.advance(address, 1703459909).unwrap(), Err(1703459910));
+        // addresses are not case sensitive
+        assert_eq!(
+            guard.advance(&address.to_lowercase(), 1703459910).unwrap(),
+            Err(1703459910)
+        );
+        // other addresses are independent
+        let other = "0x53e16f6d33c1809c14ba489a6917e9de849ab20c";
+        assert_eq!(guard.advance(other, 1703459900).unwrap(), Ok(None));
+
+        assert_eq!(
+            guard.advance(address, 1703459911).unwrap(),
+            Ok(Some(1703459910))
+        );
+        guard.restore(address, Some(1703459910)).unwrap();
+        assert_eq!(guard.last_timestamp(address).unwrap(), Some(1703459910));
+        guard.restore(other, None).unwrap();
+        assert_eq!(guard.last_timestamp(other).unwrap(), None);
+    }
+
+    #[test]
+    fn test_replay_guard_rewind() {
+        let guard = ReplayGuard::new(LocalStorage::new());
+        let address = "0x631438556b66c4908579eab920dc162ff58958ea";
+        guard.advance(address, 1703459910).unwrap().unwrap();
+        guard.advance(address, 1703459911).unwrap().unwrap();
+        guard.rewind(address, 1703459911, Some(1703459910)).unwrap();
+        assert_eq!(guard.last_timestamp(address).unwrap(), Some(1703459910));
+        // the reverted message can be submitted again
+        assert_eq!(
+            guard.advance(address, 1703459911).unwrap(),
+            Ok(Some(1703459910))
+        );
+
+        // a later update accepted in the meantime is kept
+        guard.advance(address, 1703459912).unwrap().unwrap();
+        guard.rewind(address, 1703459911, Some(1703459910)).unwrap();
+        assert_eq!(guard.last_timestamp(address).unwrap(), Some(1703459912));
+
+        guard.rewind(address, 1703459912, None).unwrap();
+        assert_eq!(guard.last_timestamp(address).unwrap(), None);
+    }
+}
@@ -1,20 +1,45 @@
 use serde::{Deserialize, Serialize};
 
 use axum::{
-    debug_handler, extract::State, http::StatusCode, response::IntoResponse, routing::post, Json,
-    Router,
+    debug_handler,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::{get, post},
+    Json, Router,
 };
 
+use std::future::Future;
 use std::net::SocketAddr;
-use tokio::{net::TcpListener, sync::mpsc::Sender};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{error::TrySendError, Sender},
+};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 use web3::signing::recover;
 
-use crate::eff_ecdsa_input::hash_msg;
-use crate::user::UserProfile;
+use crate::eff_ecdsa_input::{fe_to_hex, hash_msg, DecodedSignature, HashMode, SignatureError};
+use crate::eip712::{digest, Eip712Domain, ProfileUpdateStruct, ProfileUpdateTypedData};
+use crate::merkle_tree_updater::{address_key, SharedTreeState};
+use crate::pipeline_events::{send_event, EventSender, PipelineEvent};
+use crate::proof_store::{ProofMetadata, ProofStore};
+use crate::replay_guard::ReplayGuard;
+use crate::update_tracker::{UpdateRecord, UpdateStatus, UpdateTracker};
+use crate::user::{ProfileError, UserProfile};
+use crate::CompressedProof;
+use common::utils::time::get_current_timestamp_ms;
+use common::BIT_SIZE;
+use merkle_tree::HashDirection;
+use sha3::{Digest, Keccak256};
 
 pub type Signature = String;
 
@@ -50,10 +75,160 @@ impl IntoResponse for ApiResult {
     }
 }
 
-pub async fn run_server(port: u16, tx: Sender<SignedUserProfileUpdate>) {
+/// Shared state of the request handlers
+#[derive(Clone)]
+pub struct AppState {
+    tx: Sender<SignedUserProfileUpdate>,
+    compression_trigger: Option<Sender<()>>,
+    proof_store: Option<Arc<ProofStore>>,
+    tree_state: Option<SharedTreeState>,
+    update_tracker: Option<Arc<UpdateTracker>>,
+    replay_guard: Option<Arc<ReplayGuard>>,
+    events: Option<EventSender>,
+    hash_mode: HashMode,
+    eip712_domain: Eip712Domain,
+    typed_updates: bool,
+    legacy_profiles: bool,
+    timestamp_window: TimestampWindow,
+    contract_verifier: Option<Arc<dyn SignatureVerifier>>,
+}
+impl AppState {
+    pub fn new(tx: Sender<SignedUserProfileUpdate>) -> Self {
+        Self {
+            tx,
+            compression_trigger: None,
+            proof_store: None,
+            tree_state: None,
+            update_tracker: None,
+            replay_guard: None,
+            events: None,
+            hash_mode: HashMode::default(),
+            eip712_domain: Eip712Domain::default(),
+            typed_updates: true,
+            legacy_profiles: false,
+            timestamp_window: TimestampWindow::default(),
+            contract_verifier: None,
+        }
+    }
+    /// Serve `GET /proofs/latest` and `GET /proofs/:id` from `proof_store`
+    pub fn with_proof_store(mut self, proof_store: Arc<ProofStore>) -> Self {
+        self.proof_store = Some(proof_store);
+        self
+    }
+    /// Serve `POST /compress` by sending a request to `compression_trigger`
+    pub fn with_compression_trigger(mut self, compression_trigger: Sender<()>) -> Self {
+        self.compression_trigger = Some(compression_trigger);
+        self
+    }
+    /// Serve `GET /root` and `GET /profile/:address/proof` from the Merkle tree in `tree_state`
+    pub fn with_tree_state(mut self, tree_state: SharedTreeState) -> Self {
+        self.tree_state = Some(tree_state);
+        self
+    }
+    /// Record accepted updates in `update_tracker` and serve `GET /updates/:id` from it
+    pub fn with_update_tracker(mut self, update_tracker: Arc<UpdateTracker>) -> Self {
+        self.update_tracker = Some(update_tracker);
+        self
+    }
+    /// Reject updates whose timestamp is not later than the last accepted update of the address
+    pub fn with_replay_guard(mut self, replay_guard: Arc<ReplayGuard>) -> Self {
+        self.replay_guard = Some(replay_guard);
+        self
+    }
+    /// Publish accepted updates to `events` and stream the pipeline events on `GET /events`
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+    /// Verify signatures over messages hashed like `hash_mode`, has to match the circuit
+    pub fn with_hash_mode(mut self, hash_mode: HashMode) -> Self {
+        self.hash_mode = hash_mode;
+        self
+    }
+    /// Verify typed updates signed in `eip712_domain`, its separator has to match the circuit
+    pub fn with_eip712_domain(mut self, eip712_domain: Eip712Domain) -> Self {
+        self.eip712_domain = eip712_domain;
+        self
+    }
+    /// Serve `POST /profile_update/typed` only if the circuit proves typed updates
+    pub fn with_typed_updates(mut self, typed_updates: bool) -> Self {
+        self.typed_updates = typed_updates;
+        self
+    }
+    /// Also accept messages with a legacy profile encoding, see `UserProfile::from_legacy`,
+    /// to migrate clients that still sign them
+    pub fn with_legacy_profiles(mut self, legacy_profiles: bool) -> Self {
+        self.legacy_profiles = legacy_profiles;
+        self
+    }
+    /// Reject updates whose timestamp is outside of `timestamp_window` around the current time
+    pub fn with_timestamp_window(mut self, timestamp_window: TimestampWindow) -> Self {
+        self.timestamp_window = timestamp_window;
+        self
+    }
+    /// Accept updates whose signature is rejected by ECDSA recovery but valid for `contract_verifier`,
+    /// e.g. of smart-contract wallets, as attested-only updates
+    pub fn with_contract_verifier(mut self, contract_verifier: Arc<dyn SignatureVerifier>) -> Self {
+        self.contract_verifier = Some(contract_verifier);
+        self
+    }
+}
+
+/// Timestamps accepted for updates, relative to the current time
+///
+/// Message timestamps are Unix seconds, as read by the circuit. No bound is checked by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimestampWindow {
+    /// how far ahead of the current time a timestamp may be, the tolerated clock skew
+    pub max_future_secs: Option<u64>,
+    /// how far behind the current time a timestamp may be
+    pub max_age_secs: Option<u64>,
+}
+impl TimestampWindow {
+    fn check(&self, timestamp: u64, now: u64) -> Result<(), ApiErrorCode> {
+        if let Some(max_future_secs) = self.max_future_secs {
+            if timestamp > now.saturating_add(max_future_secs) {
+                return Err(ApiErrorCode::TimestampInFuture);
+            }
+        }
+        if let Some(max_age_secs) = self.max_age_secs {
+            if timestamp < now.saturating_sub(max_age_secs) {
+                return Err(ApiErrorCode::TimestampTooOld);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub type VerifyFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<bool>> + Send + 'a>>;
+
+/// Verification of signatures that are not ECDSA signatures of the profile owner
+pub trait SignatureVerifier: Send + Sync {
+    /// Whether `signature` of the message hash `m_hash` is valid for `address`,
+    /// an error if the verification itself failed
+    fn verify<'a>(
+        &'a self,
+        address: &'a str,
+        m_hash: &'a [u8],
+        signature: &'a [u8],
+    ) -> VerifyFuture<'a>;
+}
+
+pub async fn run_server(port: u16, state: AppState) {
     let app = Router::new()
         .route("/profile_update", post(handle_post_signed_message))
-        .with_state(tx)
+        .route(
+            "/profile_update/typed",
+            post(handle_post_typed_signed_message),
+        )
+        .route("/compress", post(handle_post_compress))
+        .route("/proofs/latest", get(handle_get_latest_proof))
+        .route("/proofs/:id", get(handle_get_proof))
+        .route("/root", get(handle_get_root))
+        .route("/profile/:address/proof", get(handle_get_profile_proof))
+        .route("/updates/:id", get(handle_get_update))
+        .route("/events", get(handle_get_events))
+        .with_state(state)
         .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()));
 
     let addr = SocketAddr::from(([127, 0, 0, 1], port));
@@ -66,16 +241,123 @@ pub async fn run_server(port: u16, tx: Sender<SignedUserProfileUpdate>) {
         .unwrap();
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 enum ApiErrorCode {
     InvalidSig,
     SignatureNotDeser,
+    SignatureNotHex,
+    SignatureWrongLength,
+    InvalidRecoveryId,
+    SignatureMalleable,
+    KeyNotRecoverable,
+    PipelineUnavailable,
+    CompressionUnavailable,
+    ProofNotFound,
+    ProofStoreUnavailable,
+    ProfileNotFound,
+    TreeUnavailable,
+    UpdateNotFound,
+    UpdateTrackerUnavailable,
+    ReplayGuardUnavailable,
+    EventsUnavailable,
+    TypedDataUnsupported,
+    TypedDataInvalid,
+    ContractVerifierUnavailable,
+    TimestampNotIncreasing,
+    TimestampInFuture,
+    TimestampTooOld,
+    ProfileInvalid,
+    MessageTooLong,
 }
 impl ApiErrorCode {
     fn message(&self) -> &'static str {
         match self {
             ApiErrorCode::InvalidSig => "Invalid signature",
             ApiErrorCode::SignatureNotDeser => "Signature is not deserializable",
+            ApiErrorCode::SignatureNotHex => "Signature is not hex encoded",
+            ApiErrorCode::SignatureWrongLength => "Signature is not r, s and v",
+            ApiErrorCode::InvalidRecoveryId => "Signature recovery id is invalid",
+            ApiErrorCode::SignatureMalleable => {
+                "Signature s value is in the upper half of the curve order"
+            }
+            ApiErrorCode::KeyNotRecoverable => "No public key can be recovered from the signature",
+            ApiErrorCode::PipelineUnavailable => "Profile updates are not accepted at the moment",
+            ApiErrorCode::CompressionUnavailable => "Proof compression is not available",
+            ApiErrorCode::ProofNotFound => "Proof not found",
+            ApiErrorCode::ProofStoreUnavailable => "Proof store is not available",
+            ApiErrorCode::ProfileNotFound => "Profile not found",
+            ApiErrorCode::TreeUnavailable => "Merkle tree is not available",
+            ApiErrorCode::UpdateNotFound => "Update not found",
+            ApiErrorCode::UpdateTrackerUnavailable => "Update tracking is not available",
+            ApiErrorCode::ReplayGuardUnavailable => "Replay protection is not available",
+            ApiErrorCode::EventsUnavailable => "Event stream is not available",
+            ApiErrorCode::TypedDataUnsupported => "Typed data updates are not accepted",
+            ApiErrorCode::TypedDataInvalid => "Typed data is not a ProfileUpdate of this domain",
+            ApiErrorCode::TimestampNotIncreasing => {
+                "Timestamp is not later than the last update of this address"
+            }
+            ApiErrorCode::TimestampInFuture => "Timestamp is too far in the future",
+            ApiErrorCode::TimestampTooOld => "Timestamp is too far in the past",
+            ApiErrorCode::ProfileInvalid => "Profile field is not valid for its type",
+            ApiErrorCode::MessageTooLong => "Message does not fit in the circuit message",
+            ApiErrorCode::ContractVerifierUnavailable => {
+                "Wallet contract signatures cannot be verified at the moment"
+            }
+        }
+    }
+    /// Whether a signature that is not an ECDSA signature of the profile owner fails with this error
+    fn is_ecdsa_error(&self) -> bool {
+        matches!(
+            self,
+            ApiErrorCode::InvalidSig
+                | ApiErrorCode::SignatureWrongLength
+                | ApiErrorCode::InvalidRecoveryId
+                | ApiErrorCode::SignatureMalleable
+                | ApiErrorCode::KeyNotRecoverable
+        )
+    }
+    /// Status code of a response failing with this error
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiErrorCode::InvalidSig
+            | ApiErrorCode::SignatureNotDeser
+            | ApiErrorCode::SignatureNotHex
+            | ApiErrorCode::SignatureWrongLength
+            | ApiErrorCode::InvalidRecoveryId
+            | ApiErrorCode::SignatureMalleable
+            | ApiErrorCode::TimestampInFuture
+            | ApiErrorCode::TimestampTooOld
+            | ApiErrorCode::ProfileInvalid
+            | ApiErrorCode::TypedDataUnsupported
+            | ApiErrorCode::TypedDataInvalid => StatusCode::BAD_REQUEST,
+            ApiErrorCode::KeyNotRecoverable => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiErrorCode::MessageTooLong => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiErrorCode::TimestampNotIncreasing => StatusCode::CONFLICT,
+            ApiErrorCode::ProofNotFound
+            | ApiErrorCode::ProfileNotFound
+            | ApiErrorCode::UpdateNotFound => StatusCode::NOT_FOUND,
+            ApiErrorCode::PipelineUnavailable
+            | ApiErrorCode::CompressionUnavailable
+            | ApiErrorCode::ProofStoreUnavailable
+            | ApiErrorCode::TreeUnavailable
+            | ApiErrorCode::UpdateTrackerUnavailable
+            | ApiErrorCode::ReplayGuardUnavailable
+            | ApiErrorCode::EventsUnavailable
+            | ApiErrorCode::ContractVerifierUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl From<ProfileError> for ApiErrorCode {
+    fn from(e: ProfileError) -> Self {
+        match e {
+            ProfileError::MissingField(_) | ProfileError::InvalidEncoding => {
+                ApiErrorCode::SignatureNotDeser
+            }
+            ProfileError::InvalidField(_) | ProfileError::UnexpectedAttribute(_) => {
+                ApiErrorCode::ProfileInvalid
+            }
+            ProfileError::TooLong(_) => ApiErrorCode::MessageTooLong,
         }
     }
 }
@@ -87,17 +369,34 @@ pub struct UserProfileUpdate {
     pub unparsed_profile: String,
 }
 impl TryFrom<&str> for UserProfileUpdate {
-    type Error = &'static str;
+    type Error = ProfileError;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::parse(value, false)
+    }
+}
+impl UserProfileUpdate {
+    /// Update of a message with a canonical profile, or with `legacy_profiles` also with a
+    /// legacy profile, see `UserProfile::from_legacy`
+    pub fn parse(value: &str, legacy_profiles: bool) -> Result<Self, ProfileError> {
+        check_message_len(value)?;
         let mut parts = value.splitn(2, ", ");
         // get UserProfile from parts
-        let timestamp_ms = parts
+        let timestamp = parts
+            .next()
+            .filter(|timestamp| !timestamp.is_empty())
+            .ok_or(ProfileError::MissingField("timestamp"))?;
+        // Unix seconds, the circuit reads them from the first 10 bytes
+        if timestamp.len() != 10 || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ProfileError::InvalidField("timestamp"));
+        }
+        let timestamp_ms = timestamp.parse::<u64>().unwrap();
+        let profile = parts
             .next()
-            .ok_or("missing timestamp")?
-            .to_string()
-            .parse::<u64>()
-            .or(Err("timestamp is not a number"))?;
-        let profile = parts.next().ok_or("missing profile")?.try_into()?;
+            .ok_or(ProfileError::MissingField("wallet_address"))?;
+        let profile = match UserProfile::try_from(profile) {
+            Err(e) if legacy_profiles => UserProfile::from_legacy(profile).map_err(|_| e)?,
+            profile => profile?,
+        };
         Ok(UserProfileUpdate {
             timestamp_ms,
             parsed_profile: profile,
@@ -105,16 +404,18 @@ impl TryFrom<&str> for UserProfileUpdate {
         })
     }
 }
+/// The circuit hashes messages zero padded to `BIT_SIZE` bits
+fn check_message_len(message: &str) -> Result<(), ProfileError> {
+    if message.len() > BIT_SIZE / 8 {
+        return Err(ProfileError::TooLong(message.len()));
+    }
+    Ok(())
+}
 impl Default for UserProfileUpdate {
     fn default() -> Self {
         Self {
             timestamp_ms: 0,
-            parsed_profile: UserProfile {
-                wallet_address: "".to_string(),
-                first_name: "".to_string(),
-                last_name: "".to_string(),
-                email_address: None,
-            },
+            parsed_profile: UserProfile::default(),
             unparsed_profile: "".to_string(),
         }
     }
@@ -123,6 +424,10 @@ impl Default for UserProfileUpdate {
 pub struct SignedUserProfileUpdate {
     pub user_signature: String,
     pub profile_update: UserProfileUpdate,
+    /// domain of the EIP-712 `ProfileUpdate` struct the wallet signed,
+    /// `None` if it signed the message like the `HashMode` of the server
+    #[serde(default)]
+    pub eip712_domain: Option<Eip712Domain>,
 }
 impl Default for SignedUserProfileUpdate {
     fn default() -> Self {
@@ -130,14 +435,10 @@ impl Default for SignedUserProfileUpdate {
             user_signature: "".to_string(),
             profile_update: UserProfileUpdate {
                 timestamp_ms: 0,
-                parsed_profile: UserProfile {
-                    wallet_address: "".to_string(),
-                    first_name: "".to_string(),
-                    last_name: "".to_string(),
-                    email_address: None,
-                },
+                parsed_profile: UserProfile::default(),
                 unparsed_profile: "".to_string(),
             },
+            eip712_domain: None,
         }
     }
 }
@@ -152,82 +453,584 @@ impl SignedUserProfileUpdate {
         Self {
             user_signature,
             profile_update,
+            eip712_domain: None,
+        }
+    }
+    /// Hash the wallet signed, of the message hashed like `hash_mode` or of its typed data
+    pub fn message_hash(&self, hash_mode: HashMode) -> Vec<u8> {
+        let message = &self.profile_update.unparsed_profile;
+        match &self.eip712_domain {
+            Some(domain) => {
+                let profile_update = ProfileUpdateStruct::from_message(message)
+                    .expect("typed updates are checked by the server");
+                digest(domain, &profile_update).to_vec()
+            }
+            None => hash_msg(message.as_bytes(), hash_mode),
         }
     }
+    /// Receipt id to follow the update through the pipeline, keccak256 of the signature and the message
+    pub fn receipt_id(&self) -> String {
+        let mut hasher = Keccak256::new();
+        hasher.update(self.user_signature.as_bytes());
+        hasher.update(self.profile_update.unparsed_profile.as_bytes());
+        format!("0x{}", hex::encode(hasher.finalize()))
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ApiSignedMessage {
-    message: String, // UTF-8 encoded message "1702548662, 0x71C7656EC7ab88b098defB751B7401B5f6d8976F, Nick, Zakirov, nikolay.zakirov@terminal3.io"
+    message: String, // UTF-8 encoded message "1702548662, 0x71C7656EC7ab88b098defB751B7401B5f6d8976F, f4:Nickl7:Zakirov", see `UserProfile`
     signature: String, // Signature of the message, "0x...."
 }
 impl ApiSignedMessage {
-    fn get_signed_profile_update(&self) -> Result<SignedUserProfileUpdate, &'static str> {
-        let update = self.message.as_str().try_into()?;
-        Ok(SignedUserProfileUpdate {
-            user_signature: self.signature.clone(),
-            profile_update: update,
-        })
+    fn get_signed_profile_update(
+        &self,
+        legacy_profiles: bool,
+    ) -> Result<SignedUserProfileUpdate, ProfileError> {
+        let update = UserProfileUpdate::parse(&self.message, legacy_profiles)?;
+        Ok(SignedUserProfileUpdate::from_profile_update(
+            update,
+            self.signature.clone(),
+        ))
     }
-    fn get_checked_profile_update(&self) -> Result<SignedUserProfileUpdate, ApiErrorCode> {
+    fn get_checked_profile_update(
+        &self,
+        hash_mode: HashMode,
+        legacy_profiles: bool,
+    ) -> Result<SignedUserProfileUpdate, ApiErrorCode> {
         let message = &self.message;
         let signed_profile_update = self
-            .get_signed_profile_update()
-            .or(Err(ApiErrorCode::SignatureNotDeser))?;
+            .get_signed_profile_update(legacy_profiles)
+            .map_err(ApiErrorCode::from)?;
         let signature = &self.signature;
         let address = signed_profile_update.eth_address();
         // let m_hash = hash_message(message).to_fixed_bytes();
-        let m_hash = hash_msg(message.as_bytes());
-        let decoded_sig = hex::decode(&signature[2..]).unwrap();
-        let recovery_id = decoded_sig[64] as i32;
+        let m_hash = hash_msg(message.as_bytes(), hash_mode);
         // check that the signature is valid
-        let recovered_address = recover(&m_hash, &decoded_sig[..64], recovery_id - 27).unwrap();
+        let recovered_address = recover_address(&m_hash, signature)?;
         debug!("Recovered address: {:?}", recovered_address);
-        if "0x".to_owned() + &hex::encode(recovered_address) != address.to_lowercase() {
+        if recovered_address != address.to_lowercase() {
             return Err(ApiErrorCode::InvalidSig);
         }
         Ok(signed_profile_update)
     }
 }
 
+/// Lowercase address of the key that signed `m_hash`, `signature` is 0x prefixed hex of r, s and v
+///
+/// v may be in any of its encodings, signatures with a high s are rejected (EIP-2).
+fn recover_address(m_hash: &[u8], signature: &str) -> Result<String, ApiErrorCode> {
+    let signature = DecodedSignature::from_hex(signature).map_err(|e| match e {
+        SignatureError::NotHex => ApiErrorCode::SignatureNotHex,
+        SignatureError::WrongLength => ApiErrorCode::SignatureWrongLength,
+        SignatureError::InvalidRecoveryId => ApiErrorCode::InvalidRecoveryId,
+        SignatureError::OutOfRange => ApiErrorCode::KeyNotRecoverable,
+    })?;
+    let recovery_id = signature
+        .recovery_id
+        .ok_or(ApiErrorCode::SignatureWrongLength)?;
+    if signature.is_high_s() {
+        return Err(ApiErrorCode::SignatureMalleable);
+    }
+    let recovered_address = recover(m_hash, &signature.rs_bytes(), recovery_id)
+        .or(Err(ApiErrorCode::KeyNotRecoverable))?;
+    Ok("0x".to_owned() + &hex::encode(recovered_address))
+}
+
 #[debug_handler]
 async fn handle_post_signed_message(
-    State(tx): State<Sender<SignedUserProfileUpdate>>,
+    State(state): State<AppState>,
 
     Json(payload): Json<ApiSignedMessage>,
-) -> (StatusCode, ApiResult) {
-    let profile_update = match payload.get_checked_profile_update() {
-        Ok(u) => {
+) -> Result<Json<Receipt>, (StatusCode, ApiResult)> {
+    accept_signed_message(&state, payload).await
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiTypedSignedMessage {
+    typed_data: ProfileUpdateTypedData, // `eth_signTypedData_v4` payload, see `ProfileUpdateTypedData::new`
+    signature: String,                  // Signature of the typed data, "0x...."
+}
+impl ApiTypedSignedMessage {
+    fn get_checked_profile_update(
+        &self,
+        domain: &Eip712Domain,
+    ) -> Result<SignedUserProfileUpdate, ApiErrorCode> {
+        let message = self.typed_data.profile_message(domain).map_err(|e| {
+            info!("{e}");
+            ApiErrorCode::TypedDataInvalid
+        })?;
+        let profile_update = UserProfileUpdate::try_from(message.as_str())?;
+        let m_hash = digest(domain, &self.typed_data.message);
+        let recovered_address = recover_address(&m_hash, &self.signature)?;
+        debug!("Recovered address: {:?}", recovered_address);
+        if recovered_address != profile_update.parsed_profile.wallet_address.to_lowercase() {
+            return Err(ApiErrorCode::InvalidSig);
+        }
+        let mut signed_profile_update =
+            SignedUserProfileUpdate::from_profile_update(profile_update, self.signature.clone());
+        signed_profile_update.eip712_domain = Some(domain.clone());
+        Ok(signed_profile_update)
+    }
+}
+
+/// Profile updates signed with `eth_signTypedData_v4`, next to the messages of `POST /profile_update`
+#[debug_handler]
+async fn handle_post_typed_signed_message(
+    State(state): State<AppState>,
+
+    Json(payload): Json<ApiTypedSignedMessage>,
+) -> Result<Json<Receipt>, (StatusCode, ApiResult)> {
+    if !state.typed_updates {
+        return Err((
+            ApiErrorCode::TypedDataUnsupported.status(),
+            ApiErrorCode::TypedDataUnsupported.into(),
+        ));
+    }
+    match payload.get_checked_profile_update(&state.eip712_domain) {
+        Ok(profile_update) => {
             debug!("Signature is valid");
-            u
+            enqueue_profile_update(&state, profile_update).await
         }
         Err(e) => {
             info!("{:?}", e.message());
-            return (StatusCode::BAD_REQUEST, e.into());
+            Err((e.status(), e.into()))
         }
-    };
+    }
+}
+
+/// Verify the signature of `payload` and send the update to the pipeline
+async fn accept_signed_message(
+    state: &AppState,
+    payload: ApiSignedMessage,
+) -> Result<Json<Receipt>, (StatusCode, ApiResult)> {
+    let profile_update =
+        match payload.get_checked_profile_update(state.hash_mode, state.legacy_profiles) {
+            Ok(u) => {
+                debug!("Signature is valid");
+                u
+            }
+            Err(e) if e.is_ecdsa_error() && state.contract_verifier.is_some() => {
+                return accept_attested_message(state, payload, e).await;
+            }
+            Err(e) => {
+                info!("{:?}", e.message());
+                return Err((e.status(), e.into()));
+            }
+        };
+    enqueue_profile_update(state, profile_update).await
+}
+
+/// Send a profile update with a checked signature to the pipeline
+async fn enqueue_profile_update(
+    state: &AppState,
+    profile_update: SignedUserProfileUpdate,
+) -> Result<Json<Receipt>, (StatusCode, ApiResult)> {
+    let receipt_id = profile_update.receipt_id();
+    let address = profile_update.eth_address().to_lowercase();
+    check_timestamp_window(state, &profile_update)?;
+    let previous_timestamp = advance_timestamp(state, &profile_update)?;
+    // recorded before the update is sent, the pipeline overwrites it with later states
+    if let Some(update_tracker) = &state.update_tracker {
+        if let Err(e) = update_tracker.set_status(&receipt_id, UpdateStatus::Queued) {
+            error!("failed to store the status of update {receipt_id}: {e}");
+            restore_timestamp(state, &address, previous_timestamp);
+            return Err((
+                ApiErrorCode::UpdateTrackerUnavailable.status(),
+                ApiErrorCode::UpdateTrackerUnavailable.into(),
+            ));
+        }
+    }
     debug!("Sending profile to proof system");
-    tx.send(profile_update).await.unwrap();
-    (StatusCode::OK, ApiResult::default())
+    if state.tx.send(profile_update).await.is_err() {
+        error!("profile update pipeline is not running");
+        restore_timestamp(state, &address, previous_timestamp);
+        if let Some(update_tracker) = &state.update_tracker {
+            let status = UpdateStatus::Failed {
+                reason: ApiErrorCode::PipelineUnavailable.message().to_string(),
+            };
+            if let Err(e) = update_tracker.set_status(&receipt_id, status) {
+                error!("failed to store the status of update {receipt_id}: {e}");
+            }
+        }
+        return Err((
+            ApiErrorCode::PipelineUnavailable.status(),
+            ApiErrorCode::PipelineUnavailable.into(),
+        ));
+    }
+    send_event(
+        &state.events,
+        PipelineEvent::UpdateAccepted {
+            receipt_id: receipt_id.clone(),
+            address,
+        },
+    );
+    Ok(Json(Receipt {
+        receipt_id,
+        attested_only: false,
+    }))
+}
+
+/// Accept `payload` if its signature is valid for the wallet contract at the profile address
+///
+/// The circuit only checks ECDSA signatures, so such updates are not sent to the pipeline:
+/// they are recorded as attested-only in the update tracker, with the message and the signature.
+async fn accept_attested_message(
+    state: &AppState,
+    payload: ApiSignedMessage,
+    ecdsa_error: ApiErrorCode,
+) -> Result<Json<Receipt>, (StatusCode, ApiResult)> {
+    let contract_verifier = state.contract_verifier.as_deref().unwrap();
+    let profile_update = payload
+        .get_signed_profile_update(state.legacy_profiles)
+        .map_err(ApiErrorCode::from)
+        .map_err(|e| (e.status(), e.into()))?;
+    let signature = payload
+        .signature
+        .strip_prefix("0x")
+        .unwrap_or(&payload.signature);
+    let signature = hex::decode(signature).or(Err((
+        ApiErrorCode::SignatureNotHex.status(),
+        ApiErrorCode::SignatureNotHex.into(),
+    )))?;
+    let address = profile_update.eth_address().to_lowercase();
+    let m_hash = hash_msg(payload.message.as_bytes(), state.hash_mode);
+    match contract_verifier
+        .verify(&address, &m_hash, &signature)
+        .await
+    {
+        Ok(true) => debug!("Signature is valid for the wallet contract"),
+        Ok(false) => {
+            info!("{:?}", ecdsa_error.message());
+            return Err((ecdsa_error.status(), ecdsa_error.into()));
+        }
+        Err(e) => {
+            error!("failed to verify a signature for the wallet contract {address}: {e}");
+            return Err((
+                ApiErrorCode::ContractVerifierUnavailable.status(),
+                ApiErrorCode::ContractVerifierUnavailable.into(),
+            ));
+        }
+    }
+    check_timestamp_window(state, &profile_update)?;
+    let previous_timestamp = advance_timestamp(state, &profile_update)?;
+    let receipt_id = profile_update.receipt_id();
+    if let Some(update_tracker) = &state.update_tracker {
+        let status = UpdateStatus::Attested {
+            message: payload.message,
+            signature: payload.signature,
+        };
+        // the record is all that is kept of an attested update
+        if let Err(e) = update_tracker.set_status(&receipt_id, status) {
+            error!("failed to store the status of update {receipt_id}: {e}");
+            restore_timestamp(state, &address, previous_timestamp);
+            return Err((
+                ApiErrorCode::UpdateTrackerUnavailable.status(),
+                ApiErrorCode::UpdateTrackerUnavailable.into(),
+            ));
+        }
+    }
+    send_event(
+        &state.events,
+        PipelineEvent::UpdateAttested {
+            receipt_id: receipt_id.clone(),
+            address,
+        },
+    );
+    Ok(Json(Receipt {
+        receipt_id,
+        attested_only: true,
+    }))
+}
+
+fn check_timestamp_window(
+    state: &AppState,
+    profile_update: &SignedUserProfileUpdate,
+) -> Result<(), (StatusCode, ApiResult)> {
+    // the field holds the seconds of the message
+    let timestamp = profile_update.timestamp_ms();
+    state
+        .timestamp_window
+        .check(timestamp, get_current_timestamp_ms() / 1000)
+        .map_err(|e| {
+            info!("{:?}: {timestamp}", e.message());
+            (e.status(), e.into())
+        })
+}
+
+/// Record the timestamp of `profile_update` as the last one of its address,
+/// fails if it is not later than the last accepted update of the address
+///
+/// Returns the previous timestamp of the address.
+fn advance_timestamp(
+    state: &AppState,
+    profile_update: &SignedUserProfileUpdate,
+) -> Result<Option<u64>, (StatusCode, ApiResult)> {
+    let Some(replay_guard) = &state.replay_guard else {
+        return Ok(None);
+    };
+    let address = profile_update.eth_address();
+    match replay_guard.advance(&address, profile_update.timestamp_ms()) {
+        Ok(Ok(previous)) => Ok(previous),
+        Ok(Err(last)) => {
+            info!("update of {address} is not later than its last update at {last}");
+            Err((
+                ApiErrorCode::TimestampNotIncreasing.status(),
+                ApiErrorCode::TimestampNotIncreasing.into(),
+            ))
+        }
+        Err(e) => {
+            error!("failed to read the last timestamp of {address}: {e}");
+            Err((
+                ApiErrorCode::ReplayGuardUnavailable.status(),
+                ApiErrorCode::ReplayGuardUnavailable.into(),
+            ))
+        }
+    }
+}
+
+/// Undo `advance_timestamp` for an update that is not accepted after all
+fn restore_timestamp(state: &AppState, address: &str, previous_timestamp: Option<u64>) {
+    if let Some(replay_guard) = &state.replay_guard {
+        if let Err(e) = replay_guard.restore(address, previous_timestamp) {
+            error!("failed to restore the last timestamp of {address}: {e}");
+        }
+    }
+}
+
+/// Identifies an accepted update in `GET /updates/:id`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Receipt {
+    receipt_id: String,
+    /// the update was verified by a wallet contract only, it is not applied to the Merkle tree
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    attested_only: bool,
+}
+
+#[debug_handler]
+async fn handle_get_update(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UpdateRecord>, (StatusCode, ApiResult)> {
+    let update_tracker = state.update_tracker.as_deref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        ApiErrorCode::UpdateTrackerUnavailable.into(),
+    ))?;
+    match update_tracker.status(&id.to_lowercase()) {
+        Ok(Some(record)) => Ok(Json(record)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, ApiErrorCode::UpdateNotFound.into())),
+        Err(e) => {
+            error!("failed to read the status of update {id}: {e}");
+            Err((
+                ApiErrorCode::UpdateTrackerUnavailable.status(),
+                ApiErrorCode::UpdateTrackerUnavailable.into(),
+            ))
+        }
+    }
+}
+
+/// Request a compressed proof of the steps folded so far
+#[debug_handler]
+async fn handle_post_compress(State(state): State<AppState>) -> (StatusCode, ApiResult) {
+    let Some(compression_trigger) = &state.compression_trigger else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            ApiErrorCode::CompressionUnavailable.into(),
+        );
+    };
+    match compression_trigger.try_send(()) {
+        // a full channel means a compression is pending already
+        Ok(()) | Err(TrySendError::Full(())) => (StatusCode::ACCEPTED, ApiResult::default()),
+        Err(TrySendError::Closed(())) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            ApiErrorCode::CompressionUnavailable.into(),
+        ),
+    }
+}
+
+/// Compressed proof with its metadata, as returned by the proof endpoints
+#[derive(Serialize)]
+struct ProofResponse {
+    #[serde(flatten)]
+    metadata: ProofMetadata,
+    compressed_snark: CompressedProof,
+}
+
+#[debug_handler]
+async fn handle_get_latest_proof(
+    State(state): State<AppState>,
+) -> Result<Json<ProofResponse>, (StatusCode, ApiResult)> {
+    let proof_store = get_proof_store(&state)?;
+    match proof_store.latest_id() {
+        Ok(Some(id)) => get_proof(proof_store, id),
+        Ok(None) => Err((StatusCode::NOT_FOUND, ApiErrorCode::ProofNotFound.into())),
+        Err(e) => Err(proof_store_error(e)),
+    }
+}
+
+#[debug_handler]
+async fn handle_get_proof(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Json<ProofResponse>, (StatusCode, ApiResult)> {
+    get_proof(get_proof_store(&state)?, id)
+}
+
+fn get_proof_store(state: &AppState) -> Result<&ProofStore, (StatusCode, ApiResult)> {
+    state.proof_store.as_deref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        ApiErrorCode::ProofStoreUnavailable.into(),
+    ))
+}
+
+fn get_proof(
+    proof_store: &ProofStore,
+    id: u64,
+) -> Result<Json<ProofResponse>, (StatusCode, ApiResult)> {
+    let metadata = proof_store.metadata(id).map_err(proof_store_error)?;
+    let compressed_snark = proof_store
+        .compressed_proof(id)
+        .map_err(proof_store_error)?;
+    match (metadata, compressed_snark) {
+        (Some(metadata), Some(compressed_snark)) => Ok(Json(ProofResponse {
+            metadata,
+            compressed_snark,
+        })),
+        _ => Err((StatusCode::NOT_FOUND, ApiErrorCode::ProofNotFound.into())),
+    }
+}
+
+fn proof_store_error(e: anyhow::Error) -> (StatusCode, ApiResult) {
+    error!("failed to read from the proof store: {e}");
+    (
+        ApiErrorCode::ProofStoreUnavailable.status(),
+        ApiErrorCode::ProofStoreUnavailable.into(),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    /// only stream the events concerning this wallet address
+    address: Option<String>,
+}
+
+/// Server-sent events of the pipeline, events a client falls behind on are skipped
+#[debug_handler]
+async fn handle_get_events(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, (StatusCode, ApiResult)> {
+    let events = state.events.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        ApiErrorCode::EventsUnavailable.into(),
+    ))?;
+    let stream = BroadcastStream::new(events.subscribe()).filter_map(move |event| {
+        let event = event.ok()?;
+        if let Some(address) = &query.address {
+            if !event.concerns(address) {
+                return None;
+            }
+        }
+        Some(Event::default().event(event.name()).json_data(&event))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RootResponse {
+    root: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SiblingResponse {
+    hash: String,
+    /// "left" or "right" of the path from the leaf to the root
+    direction: String,
+}
+
+/// Membership proof of the leaf of a profile in the current Merkle tree
+#[derive(Debug, Deserialize, Serialize)]
+struct ProfileProofResponse {
+    root: String,
+    leaf_hash: String,
+    /// siblings from the leaf to the root
+    siblings: Vec<SiblingResponse>,
+    /// step at which the leaf was last folded, none if the latest update is not folded yet
+    last_updated_step: Option<usize>,
+}
+
+fn get_tree_state(state: &AppState) -> Result<&SharedTreeState, (StatusCode, ApiResult)> {
+    state.tree_state.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        ApiErrorCode::TreeUnavailable.into(),
+    ))
+}
+
+#[debug_handler]
+async fn handle_get_root(
+    State(state): State<AppState>,
+) -> Result<Json<RootResponse>, (StatusCode, ApiResult)> {
+    let tree_state = get_tree_state(&state)?.read().unwrap();
+    Ok(Json(RootResponse {
+        root: fe_to_hex(&tree_state.merkle_tree.root()),
+    }))
+}
+
+#[debug_handler]
+async fn handle_get_profile_proof(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<ProfileProofResponse>, (StatusCode, ApiResult)> {
+    let tree_state = get_tree_state(&state)?.read().unwrap();
+    let key = address_key(&address);
+    let (leaf_hash, siblings) = tree_state
+        .merkle_tree
+        .get_proof(&key)
+        .ok_or((StatusCode::NOT_FOUND, ApiErrorCode::ProfileNotFound.into()))?;
+    Ok(Json(ProfileProofResponse {
+        root: fe_to_hex(&tree_state.merkle_tree.root()),
+        leaf_hash: fe_to_hex(&leaf_hash),
+        siblings: siblings
+            .iter()
+            .map(|sibling| SiblingResponse {
+                hash: fe_to_hex(&sibling.hash),
+                direction: match sibling.direction {
+                    HashDirection::Left => "left",
+                    HashDirection::Right => "right",
+                }
+                .to_string(),
+            })
+            .collect(),
+        last_updated_step: tree_state.last_folded_step.get(&key).copied(),
+    }))
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::key_value_storage::tests::UnavailableStorage;
+    use crate::key_value_storage::LocalStorage;
+    use crate::merkle_tree_updater::TreeState;
     use rand::Rng;
     use reqwest::StatusCode;
     use serde_json::json;
     use std::net::TcpListener;
     use tracing_test::traced_test;
-    const MESSAGE: &str =
+    // signed with the first hardhat account
+    const MESSAGE: &str = "1703459910, 0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266, f4:Bradl4:Pitte19:brad.pitt@gmail.com";
+    const SIGNATURE: &str = "0xc2184b210de1038acc37ab9ecbc6e2d0ea61cb3cf2cdb4b6c3dc75d99feb1bb668d2b06dacd06d8c879098cd300e66f83d57ab38e3591121ca5fba6e6f2b40fa1c";
+    // message with a legacy profile, signed with metamask,
+    // s normalized to the lower half of the curve order (EIP-2)
+    const LEGACY_MESSAGE: &str =
         "1703459910, 0x631438556b66c4908579Eab920dc162FF58958ea, Brad, Pitt, brad.pitt@gmail.com";
-    const SIGNATURE: &str = "0x7c62b0e515eb044b731e244904d6efc7cb6dad49b061095b92c33443cb9bfa68f1a61d9f25c990e3c6fc94b280b181bfc77266eca37e77d123e0e67892ee5efc1b";
+    const LEGACY_SIGNATURE: &str = "0x7c62b0e515eb044b731e244904d6efc7cb6dad49b061095b92c33443cb9bfa680e59e260da366f1c39036b4d7f4e7e3ef33c75fa0bca286a9bf178143d47e2451c";
 
     #[test]
     fn deserialize_profile_update() {
         let profile_update: UserProfileUpdate =
-            "10234345, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, tom, hanks"
+            "1023434500, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, f3:toml5:hanks"
                 .try_into()
                 .unwrap();
         assert_eq!(
@@ -238,13 +1041,102 @@ mod tests {
         assert_eq!(profile_update.parsed_profile.last_name, "hanks");
     }
     #[test]
+    fn test_profile_update_message_len() {
+        let mut profile = UserProfile {
+            wallet_address: "0x53e16f6d33c1809c14ba489a6917e9de849ab20c".to_string(),
+            first_name: "tom".to_string(),
+            last_name: "hanks".to_string(),
+            residence_country: Some("US".to_string()),
+            date_of_birth: Some("1956-07-09".to_string()),
+            ..Default::default()
+        };
+        let message = format!("1703459910, {profile}");
+        assert_eq!(
+            message,
+            "1703459910, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, f3:toml5:hankscUSd19560709"
+        );
+        let profile_update = UserProfileUpdate::try_from(message.as_str()).unwrap();
+        assert_eq!(profile_update.parsed_profile, profile);
+        // the address stays at the byte offsets the circuit reads
+        assert_eq!(&message[12..54], profile.wallet_address);
+
+        profile.email_address = Some(format!("tom@{}.com", "h".repeat(34)));
+        let message = format!("1703459910, {profile}");
+        assert_eq!(message.len(), BIT_SIZE / 8);
+        assert!(UserProfileUpdate::try_from(message.as_str()).is_ok());
+
+        profile.email_address = Some(format!("tom@{}.com", "h".repeat(35)));
+        let message = format!("1703459910, {profile}");
+        assert_eq!(
+            UserProfileUpdate::try_from(message.as_str()).unwrap_err(),
+            ProfileError::TooLong(BIT_SIZE / 8 + 1)
+        );
+        assert_eq!(
+            ApiErrorCode::from(ProfileError::TooLong(BIT_SIZE / 8 + 1)),
+            ApiErrorCode::MessageTooLong
+        );
+        assert_eq!(
+            ApiErrorCode::from(ProfileError::InvalidField("date_of_birth")),
+            ApiErrorCode::ProfileInvalid
+        );
+        assert_eq!(
+            ApiErrorCode::from(ProfileError::InvalidEncoding),
+            ApiErrorCode::SignatureNotDeser
+        );
+        assert_eq!(
+            UserProfileUpdate::try_from("tom").unwrap_err(),
+            ProfileError::InvalidField("timestamp")
+        );
+        // 10 digits of Unix seconds
+        for timestamp in ["10234345", "1703459910000", "+703459910"] {
+            assert_eq!(
+                UserProfileUpdate::try_from(
+                    format!(
+                        "{timestamp}, 0x53e16f6d33c1809c14ba489a6917e9de849ab20c, f3:toml5:hanks"
+                    )
+                    .as_str()
+                )
+                .unwrap_err(),
+                ProfileError::InvalidField("timestamp")
+            );
+        }
+    }
+    #[test]
+    fn test_legacy_profile_update() {
+        let api_signed_message = ApiSignedMessage {
+            message: LEGACY_MESSAGE.to_string(),
+            signature: LEGACY_SIGNATURE.to_string(),
+        };
+        assert_eq!(
+            api_signed_message
+                .get_checked_profile_update(HashMode::Padded, false)
+                .unwrap_err(),
+            ApiErrorCode::ProfileInvalid
+        );
+        // accepted to migrate the clients that sign them
+        let profile_update = api_signed_message
+            .get_checked_profile_update(HashMode::Padded, true)
+            .unwrap();
+        let profile = &profile_update.profile_update.parsed_profile;
+        assert_eq!(profile.first_name, "Brad");
+        assert_eq!(
+            profile.email_address.as_deref(),
+            Some("brad.pitt@gmail.com")
+        );
+        assert_eq!(
+            profile_update.profile_update.unparsed_profile,
+            LEGACY_MESSAGE
+        );
+        assert!(UserProfileUpdate::parse(MESSAGE, true).is_ok());
+    }
+    #[test]
     fn test_get_profile_update() {
         let signed_message = json!({
             "message": MESSAGE,
             "signature": SIGNATURE,
         });
         let api_signed_message: ApiSignedMessage = serde_json::from_value(signed_message).unwrap();
-        let profile_update = api_signed_message.get_signed_profile_update().unwrap();
+        let profile_update = api_signed_message.get_signed_profile_update(false).unwrap();
         assert_eq!(profile_update.timestamp_ms(), 1703459910);
         assert_eq!(
             profile_update.profile_update.parsed_profile.first_name,
@@ -264,11 +1156,541 @@ mod tests {
         );
     }
     #[test]
+    fn test_signature_errors() {
+        let check = |signature: &str| {
+            let api_signed_message = ApiSignedMessage {
+                message: MESSAGE.to_string(),
+                signature: signature.to_string(),
+            };
+            api_signed_message
+                .get_checked_profile_update(HashMode::Padded, false)
+                .unwrap_err()
+        };
+        let bad_recovery_id = format!("{}05", &SIGNATURE[..130]);
+        let zero_signature = format!("0x{}1b", "00".repeat(64));
+        let wrong_signature = SIGNATURE.replace('a', "1");
+        // the same signature with s = n - s
+        let high_s = "0xc2184b210de1038acc37ab9ecbc6e2d0ea61cb3cf2cdb4b6c3dc75d99feb1bb6972d4f92532f9273786f6732cff199067d5731adcbef8f19f572a41e610b00471b";
+        let cases = [
+            ("0xzz", ApiErrorCode::SignatureNotHex, 400),
+            ("0x", ApiErrorCode::SignatureWrongLength, 400),
+            (&SIGNATURE[..130], ApiErrorCode::SignatureWrongLength, 400),
+            (
+                bad_recovery_id.as_str(),
+                ApiErrorCode::InvalidRecoveryId,
+                400,
+            ),
+            (
+                zero_signature.as_str(),
+                ApiErrorCode::KeyNotRecoverable,
+                422,
+            ),
+            (wrong_signature.as_str(), ApiErrorCode::InvalidSig, 400),
+            (high_s, ApiErrorCode::SignatureMalleable, 400),
+        ];
+        for (signature, code, status) in cases {
+            let error = check(signature);
+            assert_eq!(error, code, "signature {signature}");
+            assert_eq!(error.status().as_u16(), status);
+        }
+        // a short non-ascii signature must not panic
+        assert_eq!(check("é"), ApiErrorCode::SignatureNotHex);
+    }
+    #[test]
+    fn test_verify_signature_v_encodings() {
+        let rs = &SIGNATURE[..130];
+        // 27/28, 0/1, EIP-155 on chain 1 and on chain 137
+        for v in ["1c", "01", "26", "0136"] {
+            let api_signed_message = ApiSignedMessage {
+                message: MESSAGE.to_string(),
+                signature: format!("{rs}{v}"),
+            };
+            assert!(
+                api_signed_message
+                    .get_checked_profile_update(HashMode::Padded, false)
+                    .is_ok(),
+                "v {v}"
+            );
+        }
+        for v in ["1b", "00", "25", "0135"] {
+            let api_signed_message = ApiSignedMessage {
+                message: MESSAGE.to_string(),
+                signature: format!("{rs}{v}"),
+            };
+            assert_eq!(
+                api_signed_message
+                    .get_checked_profile_update(HashMode::Padded, false)
+                    .unwrap_err(),
+                ApiErrorCode::InvalidSig,
+                "v {v}"
+            );
+        }
+    }
+    #[test]
+    fn test_verify_eip191_signature() {
+        use web3::signing::{Key, SecretKey, SecretKeyRef};
+        let secret_key = SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let key = SecretKeyRef::new(&secret_key);
+        let message = format!(
+            "1703459910, 0x{}, f4:Bradl4:Pitt",
+            hex::encode(key.address())
+        );
+        let signature = key
+            .sign(&hash_msg(message.as_bytes(), HashMode::Eip191), None)
+            .unwrap();
+        let signature = format!(
+            "0x{}{}{:02x}",
+            hex::encode(signature.r),
+            hex::encode(signature.s),
+            signature.v
+        );
+        let api_signed_message = ApiSignedMessage { message, signature };
+        assert!(api_signed_message
+            .get_checked_profile_update(HashMode::Eip191, false)
+            .is_ok());
+        assert_eq!(
+            api_signed_message
+                .get_checked_profile_update(HashMode::Padded, false)
+                .unwrap_err(),
+            ApiErrorCode::InvalidSig
+        );
+    }
+    #[test]
+    fn test_verify_personal_sign_vector() {
+        // personal_sign of the message with the first hardhat account
+        // 0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266
+        let api_signed_message = ApiSignedMessage {
+            message: "1703459910, 0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266, f4:Bradl4:Pitt"
+                .to_string(),
+            signature: "0x234d3c84767ebd91076162f812d1c7ca85d855db13197fbc11059d8166e87249\
+                2f162f85098600acacaf74593b48561bbd3beed39322fd15df517b442caf99b81b"
+                .to_string(),
+        };
+        let hash = hash_msg(api_signed_message.message.as_bytes(), HashMode::Eip191);
+        assert_eq!(
+            hex::encode(hash),
+            "65109e0ba7913b262866c8347fba383fc5b90db3b0b70034450f07991db6279b"
+        );
+        let profile_update = api_signed_message
+            .get_checked_profile_update(HashMode::Eip191, false)
+            .unwrap();
+        assert_eq!(
+            profile_update.eth_address(),
+            "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266"
+        );
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_post_typed_profile_update() {
+        use web3::signing::{Key, SecretKey, SecretKeyRef};
+        let secret_key = SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let key = SecretKeyRef::new(&secret_key);
+        let address = format!("0x{}", hex::encode(key.address()));
+        let sign = |hash: &[u8]| {
+            let signature = key.sign(hash, None).unwrap();
+            format!(
+                "0x{}{}{:02x}",
+                hex::encode(signature.r),
+                hex::encode(signature.s),
+                signature.v
+            )
+        };
+        let domain = Eip712Domain::default();
+        let message = format!("1703459910, {address}, f4:Bradl4:Pitt");
+        let typed_data = ProfileUpdateTypedData::new(&domain, &message).unwrap();
+        let signature = sign(&digest(&domain, &typed_data.message));
+
+        let client = reqwest::Client::new();
+        let port = get_free_port();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        tokio::spawn(run_server(port, AppState::new(tx)));
+        let server_response = client
+            .post(format!("http://localhost:{port}/profile_update/typed"))
+            .json(&json!({ "typedData": typed_data, "signature": signature }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::OK);
+        let update = rx.recv().await.unwrap();
+        assert_eq!(update.profile_update.unparsed_profile, message);
+        assert_eq!(update.eip712_domain, Some(domain.clone()));
+        assert_eq!(
+            update.message_hash(HashMode::Padded),
+            digest(&domain, &typed_data.message)
+        );
+
+        // next to the messages signed like the hash mode of the server
+        let message = format!("1703459911, {address}, f4:Bradl4:Pitt");
+        let server_response = client
+            .post(format!("http://localhost:{port}/profile_update"))
+            .json(&json!({
+                "message": message,
+                "signature": sign(&hash_msg(message.as_bytes(), HashMode::Padded)),
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::OK);
+        assert_eq!(rx.recv().await.unwrap().eip712_domain, None);
+
+        let mut other_domain = typed_data.clone();
+        other_domain.domain.chain_id = 5;
+        let server_response = client
+            .post(format!("http://localhost:{port}/profile_update/typed"))
+            .json(&json!({ "typedData": other_domain, "signature": signature }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::BAD_REQUEST);
+        let result: ApiResult = server_response.json().await.unwrap();
+        assert_eq!(result.errors[0].code, ApiErrorCode::TypedDataInvalid);
+
+        // a server configured for another domain
+        let contract = "0xcccccccccccccccccccccccccccccccccccccccc";
+        let domain = Eip712Domain::from_config(Some("5"), Some(contract), None).unwrap();
+        let port = get_free_port();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_eip712_domain(domain.clone()),
+        ));
+        let message = format!("1703459912, {address}, f4:Bradl4:Pitt");
+        let typed_data = ProfileUpdateTypedData::new(&domain, &message).unwrap();
+        let server_response = client
+            .post(format!("http://localhost:{port}/profile_update/typed"))
+            .json(&json!({
+                "typedData": typed_data,
+                "signature": sign(&digest(&domain, &typed_data.message)),
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::OK);
+        assert_eq!(rx.recv().await.unwrap().eip712_domain, Some(domain));
+
+        // the circuit does not prove typed updates
+        let port = get_free_port();
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_typed_updates(false),
+        ));
+        let server_response = client
+            .post(format!("http://localhost:{port}/profile_update/typed"))
+            .json(&json!({ "typedData": typed_data, "signature": signature }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::BAD_REQUEST);
+        let result: ApiResult = server_response.json().await.unwrap();
+        assert_eq!(result.errors[0].code, ApiErrorCode::TypedDataUnsupported);
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_post_contract_wallet_update() {
+        use crate::erc1271::{
+            tests::{run_mock_node, WALLET, WALLET_SIGNATURE},
+            Erc1271Verifier,
+        };
+        let client = reqwest::Client::new();
+        let port = get_free_port();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let update_tracker = Arc::new(UpdateTracker::new(LocalStorage::new()));
+        let verifier = Arc::new(Erc1271Verifier::new(&run_mock_node().await));
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx)
+                .with_update_tracker(Arc::clone(&update_tracker))
+                .with_contract_verifier(verifier),
+        ));
+        let message = format!("1703459910, {WALLET}, f4:Bradl4:Pitt");
+
+        let receipt: Receipt = client
+            .post(format!("http://localhost:{port}/profile_update"))
+            .json(&json!({ "message": message, "signature": WALLET_SIGNATURE }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(receipt.attested_only);
+        // attested-only updates are not proven
+        assert!(rx.try_recv().is_err());
+        let record = update_tracker.status(&receipt.receipt_id).unwrap().unwrap();
+        assert_eq!(
+            record.status,
+            UpdateStatus::Attested {
+                message: message.clone(),
+                signature: WALLET_SIGNATURE.to_string(),
+            }
+        );
+
+        // rejected by the wallet contract
+        let server_response = client
+            .post(format!("http://localhost:{port}/profile_update"))
+            .json(&json!({ "message": message, "signature": "0x0506" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::BAD_REQUEST);
+        let result: ApiResult = server_response.json().await.unwrap();
+        assert_eq!(result.errors[0].code, ApiErrorCode::SignatureWrongLength);
+
+        // ECDSA signatures of the owner still go to the pipeline
+        let receipt: Receipt = client
+            .post(format!("http://localhost:{port}/profile_update"))
+            .json(&json!({ "message": MESSAGE, "signature": SIGNATURE }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(!receipt.attested_only);
+        assert_eq!(rx.recv().await.unwrap().receipt_id(), receipt.receipt_id);
+
+        // the node is down
+        let port = get_free_port();
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx)
+                .with_contract_verifier(Arc::new(Erc1271Verifier::new("http://127.0.0.1:1"))),
+        ));
+        let server_response = client
+            .post(format!("http://localhost:{port}/profile_update"))
+            .json(&json!({ "message": message, "signature": WALLET_SIGNATURE }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let result: ApiResult = server_response.json().await.unwrap();
+        assert_eq!(
+            result.errors[0].code,
+            ApiErrorCode::ContractVerifierUnavailable
+        );
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_post_profile_pipeline_unavailable() {
+        let client = reqwest::Client::new();
+        let port = get_free_port();
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        drop(rx);
+        tokio::spawn(run_server(port, AppState::new(tx)));
+
+        let server_response = client
+            .post(format!("http://localhost:{port}/profile_update"))
+            .json(&json!({
+                "message": MESSAGE,
+                "signature": SIGNATURE,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let result: ApiResult = server_response.json().await.unwrap();
+        assert_eq!(result.errors[0].code, ApiErrorCode::PipelineUnavailable);
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_post_profile_replay() {
+        let client = reqwest::Client::new();
+        let port = get_free_port();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let replay_guard = Arc::new(ReplayGuard::new(LocalStorage::new()));
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_replay_guard(Arc::clone(&replay_guard)),
+        ));
+        let post = || {
+            client
+                .post(format!("http://localhost:{port}/profile_update"))
+                .json(&json!({
+                    "message": MESSAGE,
+                    "signature": SIGNATURE,
+                }))
+                .send()
+        };
+
+        assert_eq!(post().await.unwrap().status(), StatusCode::OK);
+        assert!(rx.recv().await.is_some());
+        assert_eq!(
+            replay_guard.last_timestamp(&MESSAGE[12..54]).unwrap(),
+            Some(1703459910)
+        );
+
+        let server_response = post().await.unwrap();
+        assert_eq!(server_response.status(), StatusCode::CONFLICT);
+        let result: ApiResult = server_response.json().await.unwrap();
+        assert_eq!(result.errors[0].code, ApiErrorCode::TimestampNotIncreasing);
+        assert!(rx.try_recv().is_err());
+
+        // an update the pipeline did not take can be sent again
+        let port = get_free_port();
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        drop(rx);
+        let replay_guard = Arc::new(ReplayGuard::new(LocalStorage::new()));
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_replay_guard(Arc::clone(&replay_guard)),
+        ));
+        let server_response = client
+            .post(format!("http://localhost:{port}/profile_update"))
+            .json(&json!({
+                "message": MESSAGE,
+                "signature": SIGNATURE,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(replay_guard.last_timestamp(&MESSAGE[12..54]).unwrap(), None);
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_post_profile_storage_unavailable() {
+        let client = reqwest::Client::new();
+        let post = |port: u16| {
+            client
+                .post(format!("http://localhost:{port}/profile_update"))
+                .json(&json!({
+                    "message": MESSAGE,
+                    "signature": SIGNATURE,
+                }))
+                .send()
+        };
+
+        let port = get_free_port();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let replay_guard = Arc::new(ReplayGuard::new(UnavailableStorage));
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_replay_guard(replay_guard),
+        ));
+        let server_response = post(port).await.unwrap();
+        assert_eq!(server_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let result: ApiResult = server_response.json().await.unwrap();
+        assert_eq!(result.errors[0].code, ApiErrorCode::ReplayGuardUnavailable);
+        assert!(rx.try_recv().is_err());
+
+        // the update is not queued without a receipt that can be looked up
+        let port = get_free_port();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let replay_guard = Arc::new(ReplayGuard::new(LocalStorage::new()));
+        let update_tracker = Arc::new(UpdateTracker::new(UnavailableStorage));
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx)
+                .with_replay_guard(Arc::clone(&replay_guard))
+                .with_update_tracker(Arc::clone(&update_tracker)),
+        ));
+        let server_response = post(port).await.unwrap();
+        assert_eq!(server_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let result: ApiResult = server_response.json().await.unwrap();
+        assert_eq!(
+            result.errors[0].code,
+            ApiErrorCode::UpdateTrackerUnavailable
+        );
+        assert!(rx.try_recv().is_err());
+        assert_eq!(replay_guard.last_timestamp(&MESSAGE[12..54]).unwrap(), None);
+
+        let server_response = client
+            .get(format!("http://localhost:{port}/updates/0x01"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+    #[test]
+    fn test_timestamp_window() {
+        let now = 1_700_000_000;
+        assert_eq!(TimestampWindow::default().check(u64::MAX, now), Ok(()));
+        assert_eq!(TimestampWindow::default().check(0, now), Ok(()));
+        let window = TimestampWindow {
+            max_future_secs: Some(60),
+            max_age_secs: Some(3_600),
+        };
+        assert_eq!(window.check(now, now), Ok(()));
+        assert_eq!(window.check(now + 60, now), Ok(()));
+        assert_eq!(
+            window.check(now + 61, now),
+            Err(ApiErrorCode::TimestampInFuture)
+        );
+        assert_eq!(window.check(now - 3_600, now), Ok(()));
+        assert_eq!(
+            window.check(now - 3_601, now),
+            Err(ApiErrorCode::TimestampTooOld)
+        );
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_post_profile_timestamp_window() {
+        use web3::signing::{Key, SecretKey, SecretKeyRef};
+        let secret_key = SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let key = SecretKeyRef::new(&secret_key);
+        let signed_message = |timestamp: u64| {
+            let message = format!(
+                "{timestamp}, 0x{}, f4:Bradl4:Pitt",
+                hex::encode(key.address())
+            );
+            let signature = key
+                .sign(&hash_msg(message.as_bytes(), HashMode::Padded), None)
+                .unwrap();
+            let signature = format!(
+                "0x{}{}{:02x}",
+                hex::encode(signature.r),
+                hex::encode(signature.s),
+                signature.v
+            );
+            json!({ "message": message, "signature": signature })
+        };
+
+        let client = reqwest::Client::new();
+        let port = get_free_port();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_timestamp_window(TimestampWindow {
+                max_future_secs: Some(60),
+                max_age_secs: Some(3_600),
+            }),
+        ));
+        let now = get_current_timestamp_ms() / 1000;
+        let cases = [
+            (now, None),
+            (now + 3_600, Some(ApiErrorCode::TimestampInFuture)),
+            (9_999_999_999, Some(ApiErrorCode::TimestampInFuture)),
+            (now - 7_200, Some(ApiErrorCode::TimestampTooOld)),
+        ];
+        for (timestamp, error) in cases {
+            let server_response = client
+                .post(format!("http://localhost:{port}/profile_update"))
+                .json(&signed_message(timestamp))
+                .send()
+                .await
+                .unwrap();
+            match error {
+                None => {
+                    assert_eq!(server_response.status(), StatusCode::OK);
+                    assert_eq!(rx.recv().await.unwrap().timestamp_ms(), timestamp);
+                }
+                Some(code) => {
+                    assert_eq!(server_response.status(), StatusCode::BAD_REQUEST);
+                    let result: ApiResult = server_response.json().await.unwrap();
+                    assert_eq!(result.errors[0].code, code);
+                }
+            }
+        }
+        assert!(rx.try_recv().is_err());
+    }
+    #[test]
     fn test_verify_signature() {
         // following signature was obtain with personal_sign method in metamask
-        let address = &MESSAGE.to_string()[12..54];
-        let mut m_hash = hash_msg(MESSAGE.as_bytes());
-        let decoded_sig = hex::decode(&SIGNATURE[2..]).unwrap();
+        let address = &LEGACY_MESSAGE.to_string()[12..54];
+        let mut m_hash = hash_msg(LEGACY_MESSAGE.as_bytes(), HashMode::Padded);
+        let decoded_sig = hex::decode(&LEGACY_SIGNATURE[2..]).unwrap();
         let recovery_id = decoded_sig[64] as i32;
         // check that the signature is valid
         let recovered_address = recover(&m_hash, &decoded_sig[..64], recovery_id - 27).unwrap();
@@ -284,10 +1706,7 @@ mod tests {
             address.to_lowercase()
         );
     }
-    #[tokio::test]
-    #[traced_test]
-    async fn test_post_profile() {
-        let client = reqwest::Client::new();
+    fn get_free_port() -> u16 {
         const PORT_FROM: u16 = 5000;
         const PORT_TO: u16 = 6000;
         // Generate a random port number
@@ -303,6 +1722,13 @@ mod tests {
                 }
             }
         }
+        port
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_post_profile() {
+        let client = reqwest::Client::new();
+        let port = get_free_port();
 
         let profile_update: UserProfileUpdate = MESSAGE.try_into().unwrap();
         let signed_profile_update =
@@ -310,7 +1736,7 @@ mod tests {
         let (tx, mut rx) = tokio::sync::mpsc::channel(100);
 
         // Start the server
-        tokio::spawn(run_server(port, tx));
+        tokio::spawn(run_server(port, AppState::new(tx)));
 
         // test ok response
         let signed_message = json!({
@@ -350,4 +1776,249 @@ mod tests {
             .await;
         assert_eq!(server_response.unwrap().status(), StatusCode::BAD_REQUEST);
     }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_post_compress() {
+        let client = reqwest::Client::new();
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+
+        // without compression trigger
+        let port = get_free_port();
+        tokio::spawn(run_server(port, AppState::new(tx.clone())));
+        let server_response = client
+            .post(format!("http://localhost:{port}/compress"))
+            .send()
+            .await;
+        assert_eq!(
+            server_response.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // with compression trigger
+        let port = get_free_port();
+        let (compression_trigger, mut rx_trigger) = tokio::sync::mpsc::channel(1);
+        let state = AppState::new(tx).with_compression_trigger(compression_trigger);
+        tokio::spawn(run_server(port, state));
+        for _ in 0..2 {
+            let server_response = client
+                .post(format!("http://localhost:{port}/compress"))
+                .send()
+                .await;
+            assert_eq!(server_response.unwrap().status(), StatusCode::ACCEPTED);
+        }
+        // the second request is merged into the pending one
+        assert_eq!(rx_trigger.recv().await, Some(()));
+        assert!(rx_trigger.try_recv().is_err());
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_get_proofs() {
+        let client = reqwest::Client::new();
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+
+        // without proof store
+        let port = get_free_port();
+        tokio::spawn(run_server(port, AppState::new(tx.clone())));
+        let server_response = client
+            .get(format!("http://localhost:{port}/proofs/latest"))
+            .send()
+            .await;
+        assert_eq!(
+            server_response.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // with an empty proof store
+        let port = get_free_port();
+        let proof_store = Arc::new(ProofStore::new(LocalStorage::new()));
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_proof_store(proof_store),
+        ));
+        for path in ["proofs/latest", "proofs/1"] {
+            let server_response = client
+                .get(format!("http://localhost:{port}/{path}"))
+                .send()
+                .await;
+            assert_eq!(server_response.unwrap().status(), StatusCode::NOT_FOUND);
+        }
+        let server_response = client
+            .get(format!("http://localhost:{port}/proofs/first"))
+            .send()
+            .await;
+        assert_eq!(server_response.unwrap().status(), StatusCode::BAD_REQUEST);
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_get_profile_proof() {
+        let client = reqwest::Client::new();
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+        let address = "0x631438556b66c4908579Eab920dc162FF58958ea";
+        let mut merkle_tree = merkle_tree::MerkleTree::new(3);
+        let (leaf_hash, root, siblings) = merkle_tree
+            .insert_leaf(&address_key(address), MESSAGE.as_bytes())
+            .unwrap();
+        let tree_state = Arc::new(std::sync::RwLock::new(TreeState {
+            merkle_tree,
+            last_folded_step: [(address_key(address), 3)].into_iter().collect(),
+        }));
+        let port = get_free_port();
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_tree_state(tree_state),
+        ));
+
+        let root_response: RootResponse = client
+            .get(format!("http://localhost:{port}/root"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(root_response.root, fe_to_hex(&root));
+
+        // addresses are not case sensitive
+        let proof: ProfileProofResponse = client
+            .get(format!(
+                "http://localhost:{port}/profile/{}/proof",
+                address.to_lowercase()
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(proof.root, fe_to_hex(&root));
+        assert_eq!(proof.leaf_hash, fe_to_hex(&leaf_hash));
+        assert_eq!(proof.siblings.len(), siblings.len());
+        assert_eq!(proof.siblings[0].hash, fe_to_hex(&siblings[0].hash));
+        assert_eq!(proof.siblings[0].direction, "right");
+        assert_eq!(proof.last_updated_step, Some(3));
+
+        let server_response = client
+            .get(format!(
+                "http://localhost:{port}/profile/0x53e16f6d33c1809c14ba489a6917e9de849ab20c/proof"
+            ))
+            .send()
+            .await;
+        assert_eq!(server_response.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_get_update() {
+        let client = reqwest::Client::new();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let signed_message = json!({
+            "message": MESSAGE,
+            "signature": SIGNATURE,
+        });
+
+        // without update tracker
+        let port = get_free_port();
+        tokio::spawn(run_server(port, AppState::new(tx.clone())));
+        let server_response = client
+            .get(format!("http://localhost:{port}/updates/0x01"))
+            .send()
+            .await;
+        assert_eq!(
+            server_response.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let port = get_free_port();
+        let update_tracker = Arc::new(UpdateTracker::new(LocalStorage::new()));
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_update_tracker(Arc::clone(&update_tracker)),
+        ));
+        let receipt: Receipt = client
+            .post(format!("http://localhost:{port}/profile_update"))
+            .json(&signed_message)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let update = rx.recv().await.unwrap();
+        assert_eq!(receipt.receipt_id, update.receipt_id());
+
+        let record: UpdateRecord = client
+            .get(format!(
+                "http://localhost:{port}/updates/{}",
+                receipt.receipt_id
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(record.status, UpdateStatus::Queued);
+
+        // later stages overwrite the status
+        update_tracker
+            .set_status(&receipt.receipt_id, UpdateStatus::Folded { step: 1 })
+            .unwrap();
+        let record: UpdateRecord = client
+            .get(format!(
+                "http://localhost:{port}/updates/{}",
+                receipt.receipt_id
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(record.status, UpdateStatus::Folded { step: 1 });
+
+        let server_response = client
+            .get(format!("http://localhost:{port}/updates/0x01"))
+            .send()
+            .await;
+        assert_eq!(server_response.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_get_events() {
+        let client = reqwest::Client::new();
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+        let (events, _) = tokio::sync::broadcast::channel(16);
+        let port = get_free_port();
+        tokio::spawn(run_server(
+            port,
+            AppState::new(tx).with_events(events.clone()),
+        ));
+
+        let address = "0x631438556b66c4908579Eab920dc162FF58958ea";
+        let mut response = client
+            .get(format!("http://localhost:{port}/events?address={address}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // events of other addresses are filtered out
+        events
+            .send(PipelineEvent::UpdateAccepted {
+                receipt_id: "0x01".to_string(),
+                address: "0x53e16f6d33c1809c14ba489a6917e9de849ab20c".to_string(),
+            })
+            .unwrap();
+        events
+            .send(PipelineEvent::RootUpdated {
+                receipt_id: "0x02".to_string(),
+                address: address.to_lowercase(),
+                root: "0x03".to_string(),
+            })
+            .unwrap();
+        let chunk = response.chunk().await.unwrap().unwrap();
+        let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+        assert!(chunk.starts_with("event: root_updated"));
+        assert!(chunk.contains(r#""receipt_id":"0x02""#));
+        assert!(!chunk.contains("0x01"));
+    }
 }
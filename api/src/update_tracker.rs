@@ -0,0 +1,210 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use common::utils::time::get_current_timestamp_ms;
+use serde::{Deserialize, Serialize};
+
+use crate::key_value_storage::KeyValueStorage;
+
+/// State of a profile update in the pipeline
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum UpdateStatus {
+    /// accepted by the server, waiting in the priority queue
+    Queued,
+    /// applied to the Merkle tree with the resulting root, waiting to be folded
+    Applied {
+        root: String,
+    },
+    /// folded into the recursive SNARK at step `step`
+    Folded {
+        step: usize,
+    },
+    /// folded at step `step` and covered by the compressed proof `proof_id`
+    Proven {
+        step: usize,
+        proof_id: u64,
+    },
+    Failed {
+        reason: String,
+    },
+    /// verified by the wallet contract of the profile (ERC-1271) only,
+    /// not applied to the Merkle tree and not proven
+    Attested {
+        message: String,
+        signature: String,
+    },
+}
+
+/// Latest status of the update with receipt `id`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UpdateRecord {
+    pub id: String,
+    #[serde(flatten)]
+    pub status: UpdateStatus,
+    pub updated_at_ms: u64,
+}
+
+/// Status of profile updates by receipt id in a key value storage
+///
+/// The record of an update is stored at `update:<id>`, each pipeline stage overwrites it
+/// with the state the update reached.
+pub struct UpdateTracker {
+    storage: Box<dyn KeyValueStorage + Send + Sync>,
+    /// ids and steps of folded updates that are not covered by a compressed proof yet
+    unproven: Mutex<Vec<(String, usize)>>,
+}
+impl UpdateTracker {
+    pub fn new(storage: impl KeyValueStorage + Send + Sync + 'static) -> Self {
+        Self {
+            storage: Box::new(storage),
+            unproven: Mutex::new(vec![]),
+        }
+    }
+    pub fn set_status(&self, id: &str, status: UpdateStatus) -> Result<()> {
+        if let UpdateStatus::Folded { step } = status {
+            self.unproven.lock().unwrap().push((id.to_string(), step));
+        }
+        let record = UpdateRecord {
+            id: id.to_string(),
+            status,
+            updated_at_ms: get_current_timestamp_ms(),
+        };
+        self.storage
+            .set(&format!("update:{id}"), &serde_json::to_string(&record)?)
+    }
+    /// Mark the updates folded up to step `num_steps` as covered by the compressed proof `proof_id`
+    ///
+    /// All updates are marked even if some of them fail, the first error is returned.
+    pub fn set_proven(&self, proof_id: u64, num_steps: usize) -> Result<()> {
+        let proven = {
+            let mut unproven = self.unproven.lock().unwrap();
+            let (proven, rest): (Vec<_>, Vec<_>) =
+                unproven.drain(..).partition(|(_, step)| *step <= num_steps);
+            *unproven = rest;
+            proven
+        };
+        let mut result = Ok(());
+        for (id, step) in proven.into_iter() {
+            let stored = self.set_status(&id, UpdateStatus::Proven { step, proof_id });
+            if result.is_ok() {
+                result = stored;
+            }
+        }
+        result
+    }
+    /// Mark the updates folded up to step `num_steps` that are not proven yet as failed
+    ///
+    /// They stay in the recursive SNARK, a later compressed proof covering their steps
+    /// marks them as proven.
+    pub fn set_compression_failed(&self, num_steps: usize, reason: &str) -> Result<()> {
+        let failed = self
+            .unproven
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, step)| *step <= num_steps)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        let mut result = Ok(());
+        for id in failed.into_iter() {
+            let reason = reason.to_string();
+            let stored = self.set_status(&id, UpdateStatus::Failed { reason });
+            if result.is_ok() {
+                result = stored;
+            }
+        }
+        result
+    }
+    pub fn status(&self, id: &str) -> Result<Option<UpdateRecord>> {
+        self.storage
+            .get(&format!("update:{id}"))?
+            .map(|record| serde_json::from_str(&record))
+            .transpose()
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_value_storage::LocalStorage;
+
+    #[test]
+    fn test_update_tracker() {
+        let tracker = UpdateTracker::new(LocalStorage::new());
+        assert_eq!(tracker.status("0x01").unwrap(), None);
+
+        tracker.set_status("0x01", UpdateStatus::Queued).unwrap();
+        tracker.set_status("0x02", UpdateStatus::Queued).unwrap();
+        assert_eq!(
+            tracker.status("0x01").unwrap().unwrap().status,
+            UpdateStatus::Queued
+        );
+        tracker
+            .set_status("0x01", UpdateStatus::Folded { step: 1 })
+            .unwrap();
+        tracker
+            .set_status("0x02", UpdateStatus::Folded { step: 2 })
+            .unwrap();
+
+        // the proof covers the first step only
+        tracker.set_proven(1, 1).unwrap();
+        assert_eq!(
+            tracker.status("0x01").unwrap().unwrap().status,
+            UpdateStatus::Proven {
+                step: 1,
+                proof_id: 1
+            }
+        );
+        assert_eq!(
+            tracker.status("0x02").unwrap().unwrap().status,
+            UpdateStatus::Folded { step: 2 }
+        );
+        tracker.set_proven(2, 2).unwrap();
+        let record = tracker.status("0x02").unwrap().unwrap();
+        assert_eq!(
+            record.status,
+            UpdateStatus::Proven {
+                step: 2,
+                proof_id: 2
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&record).unwrap()["status"],
+            serde_json::json!("proven")
+        );
+    }
+
+    #[test]
+    fn test_compression_failed() {
+        let tracker = UpdateTracker::new(LocalStorage::new());
+        tracker
+            .set_status("0x01", UpdateStatus::Folded { step: 1 })
+            .unwrap();
+        tracker
+            .set_status("0x02", UpdateStatus::Folded { step: 2 })
+            .unwrap();
+
+        tracker.set_compression_failed(1, "test").unwrap();
+        assert_eq!(
+            tracker.status("0x01").unwrap().unwrap().status,
+            UpdateStatus::Failed {
+                reason: "test".to_string()
+            }
+        );
+        assert_eq!(
+            tracker.status("0x02").unwrap().unwrap().status,
+            UpdateStatus::Folded { step: 2 }
+        );
+        // a later proof covers the step again
+        tracker.set_proven(1, 2).unwrap();
+        assert_eq!(
+            tracker.status("0x01").unwrap().unwrap().status,
+            UpdateStatus::Proven {
+                step: 1,
+                proof_id: 1
+            }
+        );
+    }
+}
@@ -1,65 +1,378 @@
 use serde::{Deserialize, Serialize};
 
+use std::fmt;
 use std::option::Option;
 
-// #[derive(Debug, Deserialize)]
-// struct UserProfile {
-//     wallet_address: Option<String>, // TODO: Change to Ethereum address
-//     residence_country: Option<String>,
-//     residence_province: Option<String>,
-//     residence_city: Option<String>,
-//     gender: Option<String>,
-//     date_of_birth: Option<String>,
-//     language_primary: Option<String>,
-//     employment_status: Option<String>,
-//     employment_industry: Option<u32>,
-//     marital_status: Option<String>,
-//     education: Option<String>,
-//     household_income: Option<String>,
-//     first_name: String,
-//     middle_name: Option<String>,
-//     last_name: String,
-//     user_name: Option<String>,
-//     email_address: Option<String>,
-//     mobile_number: Option<u64>,
-//     mobile_country_code: Option<String>,
-//     avatar: Option<String>,
-//     language_others: Option<Vec<String>>,
-// }
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+/// Width of the encoded value of a profile field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Width {
+    /// a value of this many bytes
+    Fixed(usize),
+    /// `<length>:<value>` with the number of bytes of the value
+    Variable,
+}
+
+/// Tag and width of the profile fields, in the order of the canonical encoding
+const FIELDS: [(u8, Width); 10] = [
+    (b'f', Width::Variable),
+    (b'l', Width::Variable),
+    (b'e', Width::Variable),
+    (b'c', Width::Fixed(2)),
+    (b'g', Width::Fixed(1)),
+    (b'd', Width::Fixed(8)),
+    (b'a', Width::Fixed(2)),
+    (b'w', Width::Fixed(1)),
+    (b'u', Width::Fixed(1)),
+    (b'm', Width::Variable),
+];
+
+/// Profile of a wallet, the leaf message of its address
+///
+/// The canonical encoding is `<wallet_address>, ` followed by the present fields in the order
+/// of `FIELDS`, each one a tag byte and its value: `<length>:<value>` for text and the value
+/// itself for fixed width fields, e.g.
+/// `0x53e1..., f3:toml5:hankse13:tom@hanks.comcUSgmd19560709aenwe` for a profile with the
+/// date of birth 1956-07-09. Values are read by their length, so they can hold any
+/// separator, and the address stays at a fixed byte offset of the message for the circuit.
+///
+/// The circuit message leaves 72 bytes for the fields, so the schema only has the attributes
+/// that fit together and codes are a single character.
+///
+/// `try_from` only accepts the canonical encoding, legacy messages are parsed by `from_legacy`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
 pub struct UserProfile {
     pub wallet_address: String,
     pub first_name: String,
     pub last_name: String,
     pub email_address: Option<String>,
+    /// ISO 3166-1 alpha-2 code, "CH"
+    pub residence_country: Option<String>,
+    /// single character code, "f"
+    pub gender: Option<String>,
+    /// "YYYY-MM-DD", encoded without the dashes
+    pub date_of_birth: Option<String>,
+    /// ISO 639-1 code, "en"
+    pub language_primary: Option<String>,
+    /// single character code, "e"
+    pub employment_status: Option<String>,
+    /// single character code, "b"
+    pub education: Option<String>,
+    /// E.164 number, "+41791234567"
+    pub mobile_number: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileError {
+    /// a mandatory field is missing
+    MissingField(&'static str),
+    /// the value of a field is not valid for its type
+    InvalidField(&'static str),
+    /// a field tag is unknown, repeated or out of order, or a legacy message has more fields
+    UnexpectedAttribute(String),
+    /// a field value does not have its width or its `<length>:` prefix is invalid
+    InvalidEncoding,
+    /// the message has more bytes than the circuit message
+    TooLong(usize),
+}
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::MissingField(field) => write!(f, "missing {field}"),
+            ProfileError::InvalidField(field) => write!(f, "invalid {field}"),
+            ProfileError::UnexpectedAttribute(attribute) => {
+                write!(f, "unexpected attribute {attribute}")
+            }
+            ProfileError::InvalidEncoding => write!(f, "invalid encoding"),
+            ProfileError::TooLong(len) => write!(f, "message of {len} bytes is too long"),
+        }
+    }
+}
+
+impl UserProfile {
+    /// Check every present field against its type
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        let check = |valid: bool, field: &'static str| {
+            if valid {
+                Ok(())
+            } else {
+                Err(ProfileError::InvalidField(field))
+            }
+        };
+        let optional = |value: &Option<String>, is_valid: fn(&str) -> bool| {
+            value.as_deref().is_none_or(is_valid)
+        };
+        check(is_address(&self.wallet_address), "wallet_address")?;
+        check(is_text(&self.first_name), "first_name")?;
+        check(is_text(&self.last_name), "last_name")?;
+        check(optional(&self.email_address, is_email), "email_address")?;
+        check(
+            optional(&self.residence_country, is_country),
+            "residence_country",
+        )?;
+        check(optional(&self.gender, is_code), "gender")?;
+        check(optional(&self.date_of_birth, is_date), "date_of_birth")?;
+        check(
+            optional(&self.language_primary, is_language),
+            "language_primary",
+        )?;
+        check(
+            optional(&self.employment_status, is_code),
+            "employment_status",
+        )?;
+        check(optional(&self.education, is_code), "education")?;
+        check(
+            optional(&self.mobile_number, is_phone_number),
+            "mobile_number",
+        )?;
+        Ok(())
+    }
+    /// Encoded values of the fields in the order of `FIELDS`
+    fn values(&self) -> [Option<String>; 10] {
+        [
+            Some(self.first_name.clone()),
+            Some(self.last_name.clone()),
+            self.email_address.clone(),
+            self.residence_country.clone(),
+            self.gender.clone(),
+            self.date_of_birth
+                .as_ref()
+                .map(|date| date.replace('-', "")),
+            self.language_primary.clone(),
+            self.employment_status.clone(),
+            self.education.clone(),
+            self.mobile_number.clone(),
+        ]
+    }
+    /// Set the field at `position` of `FIELDS` from its encoded value
+    fn set_field(&mut self, position: usize, value: &str) -> Result<(), ProfileError> {
+        let text = Some(value.to_string());
+        match position {
+            0 => self.first_name = value.to_string(),
+            1 => self.last_name = value.to_string(),
+            2 => self.email_address = text,
+            3 => self.residence_country = text,
+            4 => self.gender = text,
+            5 => {
+                if !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(ProfileError::InvalidField("date_of_birth"));
+                }
+                self.date_of_birth =
+                    Some(format!("{}-{}-{}", &value[..4], &value[4..6], &value[6..]))
+            }
+            6 => self.language_primary = text,
+            7 => self.employment_status = text,
+            8 => self.education = text,
+            9 => self.mobile_number = text,
+            _ => unreachable!("{position} is not a position of FIELDS"),
+        }
+        Ok(())
+    }
+    /// Profile of a legacy message `<wallet_address>, <first_name>, <last_name>[, <email_address>]`
+    ///
+    /// Only to migrate messages signed before the canonical encoding, the values of such
+    /// messages cannot contain ", ".
+    pub fn from_legacy(value: &str) -> Result<Self, ProfileError> {
+        let mut parts = value.split(", ");
+        let mut profile = UserProfile {
+            wallet_address: parts.next().unwrap_or_default().to_string(),
+            first_name: parts
+                .next()
+                .ok_or(ProfileError::MissingField("first_name"))?
+                .to_string(),
+            last_name: parts
+                .next()
+                .ok_or(ProfileError::MissingField("last_name"))?
+                .to_string(),
+            ..Default::default()
+        };
+        profile.email_address = parts.next().map(str::to_string);
+        if let Some(part) = parts.next() {
+            return Err(ProfileError::UnexpectedAttribute(part.to_string()));
+        }
+        profile.validate()?;
+        Ok(profile)
+    }
+}
+
+/// Canonical encoding, parsed back to the same profile by `try_from` if the profile is valid
+impl fmt::Display for UserProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, ", self.wallet_address)?;
+        for ((tag, width), value) in FIELDS.iter().zip(self.values()) {
+            let Some(value) = value else {
+                continue;
+            };
+            write!(f, "{}", *tag as char)?;
+            if *width == Width::Variable {
+                write!(f, "{}:", value.len())?;
+            }
+            write!(f, "{value}")?;
+        }
+        Ok(())
+    }
 }
 
 impl TryFrom<&str> for UserProfile {
-    type Error = &'static str;
+    type Error = ProfileError;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let mut parts = value.split(", ");
-        // get UserProfile from parts
-        let wallet_address = parts.next().ok_or("missing wallet_address")?.to_string();
-        let first_name = parts.next().ok_or("missing first_name")?.to_string();
-        let last_name = parts.next().ok_or("missing last_name")?.to_string();
-        let email_address = parts.next().map(|s| s.to_string());
-        Ok(UserProfile {
-            wallet_address,
-            first_name,
-            last_name,
-            email_address,
-        })
+        let (wallet_address, fields) = value
+            .split_once(", ")
+            .ok_or(ProfileError::MissingField("first_name"))?;
+        let mut profile = UserProfile {
+            wallet_address: wallet_address.to_string(),
+            ..Default::default()
+        };
+        for (position, value) in decode_fields(fields)? {
+            profile.set_field(position, value)?;
+        }
+        if profile.first_name.is_empty() {
+            return Err(ProfileError::MissingField("first_name"));
+        }
+        if profile.last_name.is_empty() {
+            return Err(ProfileError::MissingField("last_name"));
+        }
+        profile.validate()?;
+        Ok(profile)
+    }
+}
+
+/// `(position in FIELDS, value)` of the tagged fields, in canonical order
+///
+/// Lengths have no leading zeros and values are not empty, so a list of fields has a single encoding.
+fn decode_fields(mut encoded: &str) -> Result<Vec<(usize, &str)>, ProfileError> {
+    let mut fields = vec![];
+    let mut previous = None;
+    while let Some(&tag) = encoded.as_bytes().first() {
+        let position = FIELDS.iter().position(|(t, _)| *t == tag);
+        // canonical order, which also excludes repeated tags
+        if position.is_none() || position <= previous {
+            let attribute = encoded.chars().next().unwrap_or_default();
+            return Err(ProfileError::UnexpectedAttribute(attribute.to_string()));
+        }
+        previous = position;
+        let position = position.unwrap();
+        // tags are ASCII
+        let rest = &encoded[1..];
+        let (len, rest) = match FIELDS[position].1 {
+            Width::Fixed(len) => (len, rest),
+            Width::Variable => {
+                let (len, rest) = rest.split_once(':').ok_or(ProfileError::InvalidEncoding)?;
+                if len.starts_with('0') {
+                    return Err(ProfileError::InvalidEncoding);
+                }
+                (
+                    parse_number(len).ok_or(ProfileError::InvalidEncoding)?,
+                    rest,
+                )
+            }
+        };
+        // `get` also fails if the length splits a character
+        let value = rest.get(..len).ok_or(ProfileError::InvalidEncoding)?;
+        fields.push((position, value));
+        encoded = &rest[len..];
+    }
+    Ok(fields)
+}
+
+/// 0x prefixed 20 bytes hex, the circuit reads it at fixed byte offsets
+fn is_address(value: &str) -> bool {
+    value.len() == 42
+        && value.starts_with("0x")
+        && value[2..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Free text without control characters or surrounding whitespace
+fn is_text(value: &str) -> bool {
+    !value.is_empty() && value.trim() == value && !value.chars().any(char::is_control)
+}
+
+fn is_email(value: &str) -> bool {
+    match value.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+                && !value.chars().any(|c| c.is_whitespace() || c.is_control())
+        }
+        None => false,
+    }
+}
+
+/// Value of an enumeration, a lowercase letter or a digit
+fn is_code(value: &str) -> bool {
+    value.len() == 1
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+}
+
+fn is_country(value: &str) -> bool {
+    value.len() == 2 && value.bytes().all(|b| b.is_ascii_uppercase())
+}
+
+fn is_language(value: &str) -> bool {
+    value.len() == 2 && value.bytes().all(|b| b.is_ascii_lowercase())
+}
+
+/// "+" followed by the country calling code and the national number, at most 15 digits (E.164)
+fn is_phone_number(value: &str) -> bool {
+    (3..=16).contains(&value.len())
+        && value.starts_with('+')
+        && value[1..].bytes().all(|b| b.is_ascii_digit())
+        && !value[1..].starts_with('0')
+}
+
+/// Calendar date "YYYY-MM-DD"
+fn is_date(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return false;
+    };
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return false;
+    }
+    let (Some(year), Some(month), Some(day)) = (
+        parse_number::<u32>(year),
+        parse_number::<u32>(month),
+        parse_number::<u32>(day),
+    ) else {
+        return false;
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days).contains(&day)
+}
+
+/// Decimal digits only, `parse` also accepts a leading '+'
+fn parse_number<T: std::str::FromStr>(value: &str) -> Option<T> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
     }
+    value.parse().ok()
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use common::BIT_SIZE;
+    use proptest::option;
+    use proptest::prelude::*;
+
+    const ADDRESS: &str = "0x53e16f6d33c1809c14ba489a6917e9de849ab20c";
 
     #[test]
     fn deserialize_profile() {
-        let profile: UserProfile = "0x53e16f6d33c1809c14ba489a6917e9de849ab20c, tom, hanks"
+        let profile: UserProfile = "0x53e16f6d33c1809c14ba489a6917e9de849ab20c, f3:toml5:hanks"
             .try_into()
             .unwrap();
         assert_eq!(
@@ -69,4 +382,265 @@ mod tests {
         assert_eq!(profile.first_name, "tom");
         assert_eq!(profile.last_name, "hanks");
     }
+    #[test]
+    fn test_profile_encoding() {
+        // every field of the schema
+        let profile = UserProfile {
+            wallet_address: ADDRESS.to_string(),
+            first_name: "Tom".to_string(),
+            last_name: "Hanks".to_string(),
+            email_address: Some("tom@hanks.com".to_string()),
+            residence_country: Some("US".to_string()),
+            gender: Some("m".to_string()),
+            date_of_birth: Some("1956-07-09".to_string()),
+            language_primary: Some("en".to_string()),
+            employment_status: Some("e".to_string()),
+            education: Some("b".to_string()),
+            mobile_number: Some("+14155550100".to_string()),
+        };
+        let encoded = profile.to_string();
+        assert_eq!(
+            encoded,
+            format!(
+                "{ADDRESS}, f3:Toml5:Hankse13:tom@hanks.comcUSgmd19560709aenweubm12:+14155550100"
+            )
+        );
+        assert_eq!(UserProfile::try_from(encoded.as_str()), Ok(profile));
+        // fits in the circuit message with the timestamp
+        assert!(format!("1703459910, {encoded}").len() <= BIT_SIZE / 8);
+
+        // values hold separators and tags, lengths count bytes
+        let message = format!("{ADDRESS}, f9:tom, l5:xl9:hanks=:é");
+        let profile = UserProfile::try_from(message.as_str()).unwrap();
+        assert_eq!(profile.first_name, "tom, l5:x");
+        assert_eq!(profile.last_name, "hanks=:é");
+        assert_eq!(profile.email_address, None);
+        assert_eq!(
+            UserProfile::try_from(format!("{ADDRESS}, f3:toml5:hankscZü").as_str()),
+            Err(ProfileError::InvalidEncoding)
+        );
+        assert_eq!(profile.to_string(), message);
+    }
+    #[test]
+    fn test_legacy_profile() {
+        let message = format!("{ADDRESS}, tom, hanks, tom@hanks.com");
+        // only parsed to migrate legacy messages
+        assert!(UserProfile::try_from(message.as_str()).is_err());
+        let profile = UserProfile::from_legacy(&message).unwrap();
+        assert_eq!(profile.first_name, "tom");
+        assert_eq!(profile.last_name, "hanks");
+        assert_eq!(profile.email_address.as_deref(), Some("tom@hanks.com"));
+        assert_eq!(
+            profile.to_string(),
+            format!("{ADDRESS}, f3:toml5:hankse13:tom@hanks.com")
+        );
+        assert_eq!(
+            UserProfile::try_from(profile.to_string().as_str()).unwrap(),
+            profile
+        );
+
+        let check = |profile: &str| UserProfile::from_legacy(profile).unwrap_err();
+        assert_eq!(check(ADDRESS), ProfileError::MissingField("first_name"));
+        assert_eq!(
+            check(&format!("{ADDRESS}, tom")),
+            ProfileError::MissingField("last_name")
+        );
+        assert_eq!(
+            check(&format!("{ADDRESS}, , hanks")),
+            ProfileError::InvalidField("first_name")
+        );
+        assert_eq!(
+            check(&format!("{ADDRESS}, tom, hanks, tom@hanks.com, US")),
+            ProfileError::UnexpectedAttribute("US".to_string())
+        );
+    }
+    #[test]
+    fn test_profile_errors() {
+        let check = |profile: &str| UserProfile::try_from(profile).unwrap_err();
+        assert_eq!(check(ADDRESS), ProfileError::MissingField("first_name"));
+        assert_eq!(
+            check(&format!("{ADDRESS}, l5:hanks")),
+            ProfileError::MissingField("first_name")
+        );
+        assert_eq!(
+            check("0x53e1, f3:toml5:hanks"),
+            ProfileError::InvalidField("wallet_address")
+        );
+        assert_eq!(
+            check(&format!("{ADDRESS}, f1: l5:hanks")),
+            ProfileError::InvalidField("first_name")
+        );
+        assert_eq!(
+            check(&format!("{ADDRESS}, f3:toml5:hankse13:tom.hanks.com")),
+            ProfileError::InvalidField("email_address")
+        );
+        let invalid = [
+            ("cus", "residence_country"),
+            ("gM", "gender"),
+            ("d19560230", "date_of_birth"),
+            ("d1956-7-9", "date_of_birth"),
+            ("aEN", "language_primary"),
+            ("w_", "employment_status"),
+            ("u-", "education"),
+            ("m8:41555501", "mobile_number"),
+            ("m9:+05550100", "mobile_number"),
+            ("m17:+1234567890123456", "mobile_number"),
+        ];
+        for (field, name) in invalid {
+            assert_eq!(
+                check(&format!("{ADDRESS}, f3:toml5:hanks{field}")),
+                ProfileError::InvalidField(name),
+                "{field}"
+            );
+        }
+        for fields in [
+            "f3:tom",
+            "f3:toml5:hanks:",
+            "f4:tom",
+            "f03:toml5:hanks",
+            "f0:l5:hanks",
+            "f3tom, l5:hanks",
+            "f3:toml+5:hanks",
+            "f3:toml9:hanks",
+            "f3:toml1:é",
+            "f3:toml",
+            "f3:toml5:hanksd1956",
+        ] {
+            let expected = match fields {
+                "f3:tom" => ProfileError::MissingField("last_name"),
+                "f3:toml5:hanks:" => ProfileError::UnexpectedAttribute(":".to_string()),
+                _ => ProfileError::InvalidEncoding,
+            };
+            assert_eq!(check(&format!("{ADDRESS}, {fields}")), expected, "{fields}");
+        }
+        // unknown, repeated and out of order tags
+        for fields in [
+            "f3:tomf3:toml5:hanks",
+            "f3:tome13:tom@hanks.coml5:hanks",
+            "f3:toml5:hanksx5:tommy",
+            "f3:toml5:hankscUScFR",
+        ] {
+            assert!(matches!(
+                check(&format!("{ADDRESS}, {fields}")),
+                ProfileError::UnexpectedAttribute(_)
+            ));
+        }
+        assert_eq!(
+            check(&format!("{ADDRESS}, f3:toml6:hanks\n")),
+            ProfileError::InvalidField("last_name")
+        );
+    }
+    /// Valid profiles, with separators, tags and multi-byte characters in their text fields
+    fn profile_strategy() -> impl Strategy<Value = UserProfile> {
+        let text = || {
+            "[a-zA-Z0-9 ,=:|'é名-]{1,16}".prop_filter("surrounding whitespace", |s| s.trim() == s)
+        };
+        let code = || "[a-z0-9]";
+        (
+            "0x[0-9a-fA-F]{40}",
+            text(),
+            text(),
+            option::of("[a-z0-9.]{1,8}@[a-z]{1,8}\\.[a-z]{2,3}"),
+            option::of("[A-Z]{2}"),
+            option::of(code()),
+            option::of(
+                (1900..2100u32, 1..=12u32, 1..=28u32)
+                    .prop_map(|(year, month, day)| format!("{year}-{month:02}-{day:02}")),
+            ),
+            option::of("[a-z]{2}"),
+            option::of(code()),
+            option::of(code()),
+            option::of("\\+[1-9][0-9]{1,14}"),
+        )
+            .prop_map(
+                |(
+                    wallet_address,
+                    first_name,
+                    last_name,
+                    email_address,
+                    residence_country,
+                    gender,
+                    date_of_birth,
+                    language_primary,
+                    employment_status,
+                    education,
+                    mobile_number,
+                )| UserProfile {
+                    wallet_address,
+                    first_name,
+                    last_name,
+                    email_address,
+                    residence_country,
+                    gender,
+                    date_of_birth,
+                    language_primary,
+                    employment_status,
+                    education,
+                    mobile_number,
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn test_encoding_round_trip(profile in profile_strategy()) {
+            prop_assert_eq!(profile.validate(), Ok(()));
+            let encoded = profile.to_string();
+            prop_assert_eq!(UserProfile::try_from(encoded.as_str()), Ok(profile));
+        }
+        /// a decoded message is the encoding of its profile, so no two messages decode to the same profile
+        #[test]
+        fn test_decoding_canonical(
+            first in "[a-z0-9é ,=:]{1,12}",
+            last in "[a-z0-9é ,=:]{1,12}",
+            country in option::of("[A-Za-z]{2}"),
+        ) {
+            let mut message = format!("{ADDRESS}, f{}:{first}l{}:{last}", first.len(), last.len());
+            if let Some(country) = country {
+                message.push_str(&format!("c{country}"));
+            }
+            if let Ok(profile) = UserProfile::try_from(message.as_str()) {
+                prop_assert_eq!(profile.to_string(), message);
+            }
+        }
+        /// other encodings of a profile are rejected
+        #[test]
+        fn test_non_canonical_rejected(profile in profile_strategy()) {
+            let encoded = profile.to_string();
+            let fields = encoded.strip_prefix(&format!("{}, ", profile.wallet_address)).unwrap();
+            let first = format!("f{}:{}", profile.first_name.len(), profile.first_name);
+            let last = format!("l{}:{}", profile.last_name.len(), profile.last_name);
+            let rest = fields.strip_prefix(&format!("{first}{last}")).unwrap();
+            let non_canonical = [
+                // legacy
+                format!("{}, {}, {}", profile.wallet_address, profile.first_name, profile.last_name),
+                // length with a leading zero
+                format!("{}, f0{}", profile.wallet_address, &fields[1..]),
+                // fields out of order
+                format!("{}, {last}{first}{rest}", profile.wallet_address),
+                // repeated field
+                format!("{encoded}{first}"),
+                // separated fields
+                format!("{}, {first}, {last}{rest}", profile.wallet_address),
+            ];
+            for message in non_canonical {
+                prop_assert!(UserProfile::try_from(message.as_str()).is_err(), "{}", message);
+            }
+        }
+        #[test]
+        fn test_decoding_any_message(message in any::<String>()) {
+            let _ = UserProfile::try_from(message.as_str());
+        }
+    }
+    #[test]
+    fn test_is_date() {
+        assert!(is_date("2000-02-29"));
+        assert!(!is_date("1900-02-29"));
+        assert!(is_date("2024-12-31"));
+        assert!(!is_date("2024-13-01"));
+        assert!(!is_date("2024-00-10"));
+        assert!(!is_date("2024-01-00"));
+        assert!(!is_date("24-01-01"));
+        assert!(!is_date("2024-01-01-01"));
+    }
 }
@@ -0,0 +1,253 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use ff::PrimeField;
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::{Num, Zero};
+use serde_json::Value;
+use wasmer::{imports, Function, Instance, Module, RuntimeError, Store};
+
+/// In-process witness calculator for circom 2 wasm artifacts
+///
+/// Rust port of the `witness_calculator.js` generated next to the wasm by circom,
+/// so the witness of a folding step is computed without spawning node and without
+/// writing the input and the witness to the filesystem.
+pub struct WitnessCalculator {
+    instance: Instance,
+    /// number of 32 bit limbs of a field element
+    n32: u32,
+    prime: BigUint,
+}
+
+impl WitnessCalculator {
+    pub fn from_file(witness_wasm: &Path) -> Result<Self> {
+        let store = Store::default();
+        let module = Module::from_file(&store, witness_wasm)?;
+        // like the JS runtime, an exception aborts the calculation, the messages are only reported
+        let import_object = imports! {
+            "runtime" => {
+                "exceptionHandler" => Function::new_native(&store, exception_handler),
+                "printErrorMessage" => Function::new_native(&store, ignore_message),
+                "writeBufferMessage" => Function::new_native(&store, ignore_message),
+                "showSharedRWMemory" => Function::new_native(&store, ignore_message),
+            }
+        };
+        let instance = Instance::new(&module, &import_object)?;
+        let mut calculator = Self {
+            instance,
+            n32: 0,
+            prime: BigUint::zero(),
+        };
+        calculator.n32 = calculator.call_0("getFieldNumLen32")? as u32;
+        calculator.call_void_0("getRawPrime")?;
+        calculator.prime = calculator.read_shared_rw_memory()?;
+        Ok(calculator)
+    }
+
+    /// Calculate the witness for the JSON input of the circuit,
+    /// e.g. `{"step_in": ["1", "2"], "message": ["0", "1", ...], ...}`
+    pub fn calculate_witness<Fr: PrimeField>(&mut self, input_json: &str) -> Result<Vec<Fr>> {
+        let input: serde_json::Map<String, Value> = serde_json::from_str(input_json)?;
+        self.call_void_1("init", 0)?;
+        for (name, value) in input.iter() {
+            let (h_msb, h_lsb) = fnv_hash(name);
+            let mut values = vec![];
+            flatten_signal_values(value, &mut values)?;
+            // the wasm does not check the index, missing elements would be left unset
+            let size = self.input_size(name)?;
+            if values.len() != size {
+                return Err(anyhow!(
+                    "input signal {name} has {} elements, the circuit expects {size}",
+                    values.len()
+                ));
+            }
+            for (i, value) in values.iter().enumerate() {
+                let value = self.to_field(value);
+                self.write_shared_rw_memory(&value)?;
+                self.instance
+                    .exports
+                    .get_native_function::<(i32, i32, i32), ()>("setInputSignal")?
+                    .call(h_msb as i32, h_lsb as i32, i as i32)
+                    .map_err(|e| anyhow!("failed to set input signal {name}[{i}]: {e}"))?;
+            }
+        }
+        let witness_size = self.call_0("getWitnessSize")? as u32;
+        let mut witness = Vec::with_capacity(witness_size as usize);
+        for i in 0..witness_size {
+            self.call_void_1("getWitness", i as i32)?;
+            let value = self.read_shared_rw_memory()?;
+            let value = Fr::from_str_vartime(&value.to_str_radix(10))
+                .ok_or_else(|| anyhow!("witness value is not a field element"))?;
+            witness.push(value);
+        }
+        Ok(witness)
+    }
+
+    /// Whether the circuit has the input signal `name`
+    pub fn has_input(&self, name: &str) -> Result<bool> {
+        // unknown signals have size 0, inputs have at least one element
+        Ok(self.input_size(name)? > 0)
+    }
+
+    /// Number of field elements of the input signal `name`, all dimensions flattened
+    pub fn input_size(&self, name: &str) -> Result<usize> {
+        let (h_msb, h_lsb) = fnv_hash(name);
+        let size = self
+            .instance
+            .exports
+            .get_native_function::<(i32, i32), i32>("getInputSignalSize")?
+            .call(h_msb as i32, h_lsb as i32)?;
+        Ok(size.max(0) as usize)
+    }
+
+    fn to_field(&self, value: &BigInt) -> BigUint {
+        let prime = BigInt::from_biguint(Sign::Plus, self.prime.clone());
+        let value = ((value % &prime) + &prime) % &prime;
+        value.to_biguint().unwrap()
+    }
+
+    fn read_shared_rw_memory(&self) -> Result<BigUint> {
+        let read = self
+            .instance
+            .exports
+            .get_native_function::<i32, i32>("readSharedRWMemory")?;
+        let mut limbs = Vec::with_capacity(self.n32 as usize);
+        for j in 0..self.n32 {
+            limbs.push(read.call(j as i32)? as u32);
+        }
+        Ok(BigUint::from_slice(&limbs))
+    }
+
+    fn write_shared_rw_memory(&self, value: &BigUint) -> Result<()> {
+        let write = self
+            .instance
+            .exports
+            .get_native_function::<(i32, i32), ()>("writeSharedRWMemory")?;
+        let limbs = value.to_u32_digits();
+        for j in 0..self.n32 as usize {
+            let limb = limbs.get(j).copied().unwrap_or(0);
+            write.call(j as i32, limb as i32)?;
+        }
+        Ok(())
+    }
+
+    fn call_0(&self, name: &str) -> Result<i32> {
+        let function = self.instance.exports.get_native_function::<(), i32>(name)?;
+        Ok(function.call()?)
+    }
+
+    fn call_void_0(&self, name: &str) -> Result<()> {
+        let function = self.instance.exports.get_native_function::<(), ()>(name)?;
+        Ok(function.call()?)
+    }
+
+    fn call_void_1(&self, name: &str, arg: i32) -> Result<()> {
+        let function = self.instance.exports.get_native_function::<i32, ()>(name)?;
+        function
+            .call(arg)
+            .map_err(|e| anyhow!("{name} failed, circuit constraint or input error: {e}"))
+    }
+}
+
+/// Abort the call into the circuit with the error of circom's `exceptionHandler`
+fn exception_handler(code: i32) -> Result<(), RuntimeError> {
+    let error = match code {
+        1 => "Signal not found",
+        2 => "Too many signals set",
+        3 => "Signal already set",
+        4 => "Assert Failed",
+        5 => "Not enough memory",
+        6 => "Input signal array access exceeds the size",
+        _ => "Unknown error",
+    };
+    Err(RuntimeError::new(format!(
+        "circuit exception {code}: {error}"
+    )))
+}
+
+fn ignore_message() {}
+
+/// 64 bit FNV-1a hash of the signal name split into its most and least significant halves,
+/// as used by circom to look up input signals
+fn fnv_hash(name: &str) -> (u32, u32) {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    ((hash >> 32) as u32, hash as u32)
+}
+
+/// Flatten (nested) arrays of decimal strings or numbers in row-major order
+fn flatten_signal_values(value: &Value, values: &mut Vec<BigInt>) -> Result<()> {
+    match value {
+        Value::Array(array) => {
+            for value in array {
+                flatten_signal_values(value, values)?;
+            }
+        }
+        Value::String(s) => values.push(BigInt::from_str_radix(s, 10)?),
+        Value::Number(n) => values.push(BigInt::from_str_radix(&n.to_string(), 10)?),
+        _ => return Err(anyhow!("unsupported signal value {value}")),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::G1;
+    use nova_scotia::F;
+    use serde_json::json;
+
+    #[test]
+    fn test_fnv_hash() {
+        // FNV-1a test vectors
+        assert_eq!(fnv_hash(""), (0xcbf29ce4, 0x84222325));
+        assert_eq!(fnv_hash("a"), (0xaf63dc4c, 0x8601ec8c));
+    }
+
+    #[test]
+    fn test_has_input() {
+        let calculator =
+            WitnessCalculator::from_file(Path::new("../circuits/src/merkle_tree/ivc_js/ivc.wasm"))
+                .unwrap();
+        assert!(calculator.has_input("step_in").unwrap());
+        assert!(calculator.has_input("message").unwrap());
+        assert!(!calculator.has_input("not_a_signal").unwrap());
+        assert_eq!(calculator.input_size("step_in").unwrap(), 2);
+        assert_eq!(calculator.input_size("signatures").unwrap(), 6);
+        assert_eq!(calculator.input_size("not_a_signal").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_calculate_witness_input_size() {
+        let mut calculator =
+            WitnessCalculator::from_file(Path::new("../circuits/src/merkle_tree/ivc_js/ivc.wasm"))
+                .unwrap();
+        let e = calculator
+            .calculate_witness::<F<G1>>(r#"{"step_in": ["1"]}"#)
+            .unwrap_err();
+        assert!(e.to_string().contains("step_in"));
+        let e = calculator
+            .calculate_witness::<F<G1>>(r#"{"not_a_signal": "1"}"#)
+            .unwrap_err();
+        assert!(e.to_string().contains("not_a_signal"));
+    }
+
+    #[test]
+    fn test_flatten_signal_values() {
+        let mut values = vec![];
+        flatten_signal_values(&json!([["1", "2"], ["3", 4]]), &mut values).unwrap();
+        assert_eq!(
+            values,
+            vec![
+                BigInt::from(1),
+                BigInt::from(2),
+                BigInt::from(3),
+                BigInt::from(4)
+            ]
+        );
+        assert!(flatten_signal_values(&json!({"a": "1"}), &mut values).is_err());
+    }
+}
@@ -0,0 +1,12 @@
+/*
+EIP-712 domain separator of the signed profile updates, as in api/src/eip712.rs:
+
+   EIP712Domain(string name,string version,uint256 chainId) = ("t3 proof system", "1", 1)
+
+A deployment with another chain id, verifying contract or salt replaces the bytes with
+the separator the api logs at startup, and compiles new artifacts and public parameters.
+The api refuses to start with typed updates while its configured separator differs from these bytes.
+*/
+function eip712_domain_separator() {
+  return [195, 188, 174, 76, 84, 21, 250, 23, 111, 150, 74, 122, 108, 214, 130, 55, 4, 101, 164, 76, 191, 33, 145, 57, 150, 214, 52, 141, 237, 216, 138, 52];
+}
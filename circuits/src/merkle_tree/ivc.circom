@@ -7,6 +7,8 @@ include "ascii_binary_to_decimal.circom";
 include "ethr_address_ascii_binary_to_decimal.circom";
 include "../poseidon/poseidon.circom";
 include "merkletreeupdate.circom";
+include "previous_timestamp.circom";
+include "keccak_eip712.circom";
 template ivc(N_DEPTH) {
    /*--------------------------------------------------------------------------
    Public Inputs  - Merkle Root, Previous Timestamp from Profile
@@ -20,8 +22,12 @@ template ivc(N_DEPTH) {
 
     //Version of profile before the update
     signal input old_message_poseidon_hash[1];
+    //Binary Representation of the Previous Message, zero if the address has no profile yet
+    signal input old_message[1024];
     //Binary Representation of Message
     signal input message[1024];
+    //1 if the wallet signed the EIP-712 ProfileUpdate struct of the message, 0 otherwise
+    signal input typed[1];
     //Signature = Signature  = (r^-1,s,Tx,Ty,Ux,Uy)
     signal input signatures[6];
     //Generator Point of secp256k1
@@ -59,6 +65,16 @@ template ivc(N_DEPTH) {
    for (var i=0; i<1024; i++){
     msg_hash.input_message[i] <== message[i];
     }
+    //Typed updates: the wallet signed the EIP-712 ProfileUpdate struct of the message
+    typed[0] * (typed[0] - 1) === 0;
+    component typed_msg_hash = keccak_hash_eip712_message(1024);
+   for (var i=0; i<1024; i++){
+    typed_msg_hash.input_message[i] <== message[i];
+    }
+    signal signed_hash[256];
+   for (var i=0; i<256; i++){
+    signed_hash[i] <== typed[0] * (typed_msg_hash.output_hash[i] - msg_hash.output_hash[i]) + msg_hash.output_hash[i];
+    }
     //Assert U = - r^-1 * Hash(Message) * G
 
     //Converting Output Binary Hash to Finite Field Element
@@ -66,7 +82,7 @@ template ivc(N_DEPTH) {
 signal intermidiate[256];
    for (var i = 0; i< 256/8; i++){
   for (var j = 0; j < 8; j++) {
-intermidiate[7-j+8*i] <-- msg_hash.output_hash[8*i+j];
+intermidiate[7-j+8*i] <-- signed_hash[8*i+j];
   }
 
 }
@@ -230,6 +246,26 @@ comp.in[1] <== step_in[1];
 comp.out === 1;
 step_out[1] <== unix_epoch_from_msg_decimal.out;
 
+/*------------------------------------------------------------------------------
+Step5: CHECK THE TIMESTAMP IS LATER THAN THE ONE OF THE REPLACED PROFILE
+
+Replay protection, an address only moves to strictly later versions of its profile
+------------------------------------------------------------------------------ */
+component previous_timestamp = previous_profile_timestamp();
+
+for (var i = 0; i < 1024; i++)
+{
+  previous_timestamp.old_message[i] <== old_message[i];
+}
+previous_timestamp.old_message_poseidon_hash <== old_message_poseidon_hash[0];
+
+component later_than_previous = GreaterThan(32);
+
+later_than_previous.in[0] <== unix_epoch_from_msg_decimal.out;
+later_than_previous.in[1] <== previous_timestamp.timestamp;
+
+later_than_previous.out === 1;
+
 /*------------------------------------------------------------------------------
 Step4: Assert Poseidon Hash of Old Message Corresponds to Old Merkle Root
 
@@ -1,5 +1,3 @@
-
- 
 include "../eff_ecdsa_membership/eff_ecdsa_to_addr.circom";
 include "../../node_modules/circomlib/circuits/comparators.circom";
 include "keccak_test.circom";
@@ -7,11 +5,23 @@ include "ascii_binary_to_decimal.circom";
 include "ethr_address_ascii_binary_to_decimal.circom";
 include "../poseidon/poseidon.circom";
 include "merkletreeupdate.circom";
+include "previous_timestamp.circom";
 template ivc(N_DEPTH,N_SIGS) {
    /*--------------------------------------------------------------------------
    Public Inputs  - Merkle Root, Previous Timestamp from Profile
-   Private Inputs - Message, Signature, Path Indices to Leaf Node, Siblings
-   Outputs        - New Merkle Root, Timestamp from Message
+   Private Inputs - N_SIGS x (Message, Signature, Path Indices to Leaf Node, Siblings)
+   Outputs        - New Merkle Root, Timestamp from last Message
+
+   Updates are applied sequentially: update j is checked against the root
+   produced by update j-1 (step_in[0] for j = 0), so the siblings of update j
+   must be taken from the tree after updates 0..j-1 have been inserted.
+
+   N_DEPTH is the number of siblings of a leaf, one less than the depth of the
+   api's MerkleTree (MERKLE_TREE_DEPTH = 3 counts the leaf level): ivc(2,N_SIGS).
+
+   Slots with enabled[j] = 0 pad a partial batch: they repeat a valid update,
+   its signature and timestamps are still checked, but the Merkle root passes
+   through unchanged.
    ----------------------------------------------------------------------------*/
 
 
@@ -20,330 +30,202 @@ template ivc(N_DEPTH,N_SIGS) {
 
     //Version of profile before the update
     signal input old_message_poseidon_hash[N_SIGS][1];
+    //Binary Representation of the Previous Message, zero if the address has no profile yet
+    signal input old_message[N_SIGS][1024];
     //Binary Representation of Message
     signal input message[N_SIGS][1024];
     //Signature = Signature  = (r^-1,s,Tx,Ty,Ux,Uy)
     signal input signatures[N_SIGS][6];
-    //Generator Point of secp256k1
-   
+
     signal input pathIndices[N_SIGS][N_DEPTH];
-    signal input siblings[N_SIGS][N_DEPTH]; 
-    
+    signal input siblings[N_SIGS][N_DEPTH];
+    //1 for an update, 0 for a padding slot
+    signal input enabled[N_SIGS];
+
     signal output step_out[2];
-   
 
+    //roots[j] is the Merkle root before update j, roots[N_SIGS] is the final root
+    signal roots[N_SIGS + 1];
+    //timestamps[j] is the timestamp before update j
+    signal timestamps[N_SIGS + 1];
+    //change of the root by update j, zero for a padding slot
+    signal root_delta[N_SIGS];
+
+    roots[0] <== step_in[0];
+    timestamps[0] <== step_in[1];
 
    /* ---------------------------------------------------------------------------
-      VERIFY INPUT SIGNATURE CORRESPONDS TO INPUT MESSAGE OR NOT 
+      VERIFY INPUT SIGNATURE CORRESPONDS TO INPUT MESSAGE OR NOT
    Step 1 = Assert U = - r^-1 * Hash(Message) * G
 
     ----------------------------------------------------------------------------*/
-   
-   
-  /*  log("r_inverse_input",signatures[0]);
-   log("s_input",signatures[1]);
-   log("Tx_input",signatures[2]);
-   log("Ty_input",signatures[3]);
-   log("Ux_input",signatures[4]);
-   log("Uy_input",signatures[5]);
-   for(var i =0; i<N_DEPTH; i++)
-   { log("Path Indice -> ",i,"->",pathIndices[i]);
-
-   }
-
-   for(var i =0; i<N_DEPTH; i++)
-   { log("Sibling  -> ",i,"->",siblings[i]);
-   
-   } */
-   //Hashing Binary Mesage message[1024]->msg_hash[256] 
+   //Hashing Binary Mesage message[1024]->msg_hash[256]
     component msg_hash[N_SIGS];
-   
-
-    for (var i =0; i<N_SIGS;i++){
-      msg_hash[i]   = keccak_hash_message(1024);
-   for (var j=0; j<1024; i++){
-    msg_hash[i].input_message[j] <== message[i][j];
-    }       
-    }
-    //Assert U = - r^-1 * Hash(Message) * G
-
-    //Converting Output Binary Hash to Finite Field Element
-    log("Message Hash Prime Field Repn");
-signal intermidiate[N_SIGS][256];
-  for( var k =0; k < N_SIGS; k++){
-   for (var i = 0; i< 256/8; i++){     
-  for (var j = 0; j < 8; j++) {
-intermidiate[k][7-j+8*i] <-- msg_hash[k].output_hash[8*i+j];
-  }    
-   }
-}
-/* log("Endianness Check");
-
- for (var k =0; k<256; k++){
-    log(intermidiate[k]);
-  }  */
-    
-  var sum =0;
-  signal hash_decimal[N_SIGS];
-
-log("Sample Message");
-
-for (var j=0; j<N_SIGS;j++)
-{
-  for(var i=0; i<256;i++)
-  {
-    sum += 2 **i * intermidiate[j][256-i-1];
-
-  }
- 
- log("Finite Field Representation");
-  hash_decimal[j] <-- sum;
-  log(hash_decimal[j]);
-}
-  
-
-     //Inermidiate variable to store r^-1
-     signal inter_mul[N_SIGS];
-
-     //Intermidiate variable to store hash(message)*r^-1
-     signal inter_mul1[N_SIGS];
-
-     signal inter_mul2[N_SIGS];
-
-    for (var i =0; i< N_SIGS;i++)
-     //inter_mul = r^-1
-    { inter_mul[i]  <-- signatures[i*6][0] ;
-     log("r_inverse",inter_mul[i]);
-    
- 
-     //Inter_mul1 = -r^-1 * hash(m)
-     inter_mul1[i] <== hash_decimal[i] * (-inter_mul[i]);
-     log("w= -r^-1 * hash(m)",inter_mul1[i]);
+    signal intermidiate[N_SIGS][256];
+    signal hash_decimal[N_SIGS];
+
+    //Inermidiate variable to store r^-1
+    signal inter_mul[N_SIGS];
+    //Intermidiate variable to store hash(message)*r^-1
+    signal inter_mul1[N_SIGS];
+    signal inter_mul2[N_SIGS];
+
+    component sMultU1[N_SIGS];
+    component sMultU2[N_SIGS];
+
+    component ethr_addr_from_sig[N_SIGS];
+    component ethr_addr_from_msg[N_SIGS];
+    component ethereum_address_comparator[N_SIGS];
+
+    component unix_epoch_from_msg_decimal[N_SIGS];
+    component comp[N_SIGS];
+    component previous_timestamp[N_SIGS];
+    component later_than_previous[N_SIGS];
+
+    component old_leaf_assert[N_SIGS];
+    component old_leaf_comp[N_SIGS];
+
+    component messsage_finite_field[N_SIGS];
+    component leaf_hash[N_SIGS];
+    component merkle_update[N_SIGS];
+
+    for (var k = 0; k < N_SIGS; k++) {
+      msg_hash[k] = keccak_hash_message(1024);
+      for (var i = 0; i < 1024; i++) {
+        msg_hash[k].input_message[i] <== message[k][i];
+      }
+
+      //Converting Output Binary Hash to Finite Field Element
+      for (var i = 0; i < 256/8; i++) {
+        for (var j = 0; j < 8; j++) {
+          intermidiate[k][7-j+8*i] <-- msg_hash[k].output_hash[8*i+j];
+        }
+      }
+
+      var sum = 0;
+      for (var i = 0; i < 256; i++) {
+        sum += 2 ** i * intermidiate[k][256-i-1];
+      }
+      hash_decimal[k] <-- sum;
+
+      //inter_mul = r^-1
+      inter_mul[k] <-- signatures[k][0];
+      //Inter_mul1 = -r^-1 * hash(m)
+      inter_mul1[k] <== hash_decimal[k] * (-inter_mul[k]);
+      inter_mul2[k] <== 5827542974853635922205193225510230345443287737019294244905648782786748292217 + inter_mul1[k];
+
+      sMultU1[k] = Secp256k1Mul();
+      sMultU1[k].scalar <== hash_decimal[k];
+      sMultU1[k].xP <== 55066263022277343669578718895168534326250603453777594175500187360389116729240;
+      sMultU1[k].yP <== 32670510020758816978083085130507043184471273380659243275938904335757337482424;
+
+      sMultU2[k] = Secp256k1Mul();
+                                  // q - r_inverse
+      sMultU2[k].scalar <== 115792089237316195423570985008687907852837564279074904382605163141518161494337-inter_mul[k];
+      sMultU2[k].xP <== sMultU1[k].outX;
+      sMultU2[k].yP <== sMultU1[k].outY;
+
+      //Assert U = -r^-1 * H(Message) * G
+      signatures[k][4] === sMultU2[k].outX;
+      signatures[k][5] === sMultU2[k].outY;
 
-     inter_mul2[i] <== 5827542974853635922205193225510230345443287737019294244905648782786748292217 + inter_mul1[i];
-
-    }
-
-  component sMultU1[N_SIGS];
-  for ( var i =0; i <N_SIGS; i++)
-  {sMultU1[i]= Secp256k1Mul();
-    sMultU1[i].scalar <== hash_decimal[i];
-    sMultU1[i].xP <== 55066263022277343669578718895168534326250603453777594175500187360389116729240;
-    sMultU1[i].yP <== 32670510020758816978083085130507043184471273380659243275938904335757337482424;
-
-    //Assert U_x = -r^-1 * H(Message) * G_x
-   //signatures[4] === sMultU.outX;
-   
-   log("sMultU1.outX",sMultU1[i].outX);
-   
-    //Assert U_Y = -r^-1 * H(Message) * G_y
-   //signatures[5] === sMultU.outY;
-   log("sMultU1.outY",sMultU1[i].outY);
-  }
-   component sMultU2[N_SIGS];
-
-   for( var i =0; i< N_SIGS; i++ )
-   {                            
-   sMultU2[i] = Secp256k1Mul();
-     // q - r_inverse
-   sMultU2[i].scalar <== 115792089237316195423570985008687907852837564279074904382605163141518161494337-inter_mul[i];
-    sMultU2[i].xP <== sMultU1[i].outX;
-    sMultU2[i].yP <== sMultU1[i].outY;
-log("Ux",signatures[i][6*i+4]);
-log("sMultU2.outX",sMultU2[i].outX);
-
-signatures[i][6*i+4] === sMultU2[i].outX;
-signatures[i][6*i+5] === sMultU2[i].outY;
-   }
-
-    
 /*------------------------------------------------------------------------------
       EXTRACT ETHEREUM ADDRESSS FROM SIGNATURE
    Step 2 = Assert U = - r^-1 * Hash(Message) * G
    Signature = Signature  = (r,s,Tx,Ty,Ux,Uy)
  ------------------------------------------------------------------------------*/
+      ethr_addr_from_sig[k] = EfficientECDSAToAddr();
+      ethr_addr_from_sig[k].s  <== signatures[k][1];
+      ethr_addr_from_sig[k].Tx <== signatures[k][2];
+      ethr_addr_from_sig[k].Ty <== signatures[k][3];
+      ethr_addr_from_sig[k].Ux <== signatures[k][4];
+      ethr_addr_from_sig[k].Uy <== signatures[k][5];
 
-
-   component ethr_addr_from_sig[N_SIGS];
-
-   for(var i = 0; i<N_SIGS;i++)
-{
-  ethr_addr_from_sig[i]= EfficientECDSAToAddr();
-   ethr_addr_from_sig[i].s  <==  signatures[i][6*i+1];
-   ethr_addr_from_sig[i].Tx <==  signatures[i][6*i+2];
-   ethr_addr_from_sig[i].Ty <==  signatures[i][6*i+3];
-   ethr_addr_from_sig[i].Ux <==  signatures[i][6*i+4];
-   ethr_addr_from_sig[i].Uy <==  signatures[i][6*i+5];
-   log("Ethereum Address from Signature Output",ethr_addr_from_sig[i].addr);
-}
-
-
-  
 /*------------------------------------------------------------------------------
 Step3:    EXTRACT ETHEREUM ADDRESS FROM MESSAGE
 
-For now we always assume Message is of this form, we can re-work it later
-
-Message : 1703459910, 0x631438556b66c4908579Eab920dc162FF58958ea, Brad, Pitt, brad.pitt@gmail.com
-Note: We assume Message has 1024 bits and the rest of the characters are NULL characters
-Note:Ethreum Address is 42 characters long
-
+Same message layout as in ivc.circom:
 First 10 bytes are Unix Epoch
-11th byte is a comma
-12th byte is a space
-13th byte is a 0
-14th byte is a x
 15th byte to 54th byte is Ethereum Address
 ------------------------------------------------------------------------------ */
-component ethr_addr_from_msg[N_SIGS];
-for(var j=0; j<N_SIGS;j++){
-  ethr_addr_from_msg[j]= ethr_address_ascii_binary_to_decimal();
-for (var i =0; i<320; i++){
-  ethr_addr_from_msg[j].in[i] <== message[j][14*8+i];
-}
-
-log("Ethereum_address_from_message",ethr_addr_from_msg[j].out);
-}
-
-
+      ethr_addr_from_msg[k] = ethr_address_ascii_binary_to_decimal();
+      for (var i = 0; i < 320; i++) {
+        ethr_addr_from_msg[k].in[i] <== message[k][14*8+i];
+      }
 
-//Assert Ethereum Address from Signature === Ethereum Address from Message
-
-component ethereum_address_comparator[N_SIGS];
-
-for (var i =0; i<N_SIGS; i++){
-   ethereum_address_comparator[i] = IsEqual();
-ethereum_address_comparator[i].in[0] <== ethr_addr_from_msg[i].out;
-ethereum_address_comparator[i].in[1] <== ethr_addr_from_sig[i].addr;
-ethereum_address_comparator[i].out === 1;
-}
-
-
-
-  //constrain_addr.in[0] <== ethr_addr_from_msg.out;
-  //constrain_addr.in[1] <== ethr_addr_from_sig.out;
-
-  //0 === constrain_addr.out; 
-
- // Note : Could also do ethr_addr_from_msg.out === ethr_addr_from_sig.out
- 
+      //Assert Ethereum Address from Signature === Ethereum Address from Message
+      ethereum_address_comparator[k] = IsEqual();
+      ethereum_address_comparator[k].in[0] <== ethr_addr_from_msg[k].out;
+      ethereum_address_comparator[k].in[1] <== ethr_addr_from_sig[k].addr;
+      ethereum_address_comparator[k].out === 1;
 
 /*------------------------------------------------------------------------------
-Step4: EXTRACT TIMESTAMP FROM MESSAGE AND CHECK IT IS GREATER THAN INPUT TIMESTAMP
-
-For now we always assume Message is of this form, we can re-work it later
-
-Message : 1703459910, 0x631438556b66c4908579Eab920dc162FF58958ea, Brad, Pitt, brad.pitt@gmail.com
-Note: We assume Message has 1024 bits and the rest of the characters are NULL characters
-Note:Ethreum Address is 42 characters long
-
-First 10 bytes are Unix Epoch
-
+Step4: EXTRACT TIMESTAMP FROM MESSAGE AND CHECK IT IS GREATER THAN THE PREVIOUS ONE
 ------------------------------------------------------------------------------ */
-component unix_epoch_from_msg_decimal[N_SIGS];
-
-for( var j =0; j<N_SIGS; j++)
-{
-  unix_epoch_from_msg_decimal[j]= ascii_binary_string_to_decimal(80);
-for(var i=0;i<8*10;i++)
-{
-  unix_epoch_from_msg_decimal[j].ascii_binary_string[i] <== message[j][i];
-}
-}
+      unix_epoch_from_msg_decimal[k] = ascii_binary_string_to_decimal(80);
+      for (var i = 0; i < 8*10; i++) {
+        unix_epoch_from_msg_decimal[k].ascii_binary_string[i] <== message[k][i];
+      }
+
+      comp[k] = GreaterEqThan(32);
+      comp[k].in[0] <== unix_epoch_from_msg_decimal[k].out;
+      comp[k].in[1] <== timestamps[k];
+      comp[k].out === 1;
+      timestamps[k + 1] <== unix_epoch_from_msg_decimal[k].out;
+
+      //Replay protection, the timestamp is later than the one of the replaced profile
+      previous_timestamp[k] = previous_profile_timestamp();
+      for (var i = 0; i < 1024; i++) {
+        previous_timestamp[k].old_message[i] <== old_message[k][i];
+      }
+      previous_timestamp[k].old_message_poseidon_hash <== old_message_poseidon_hash[k][0];
+
+      later_than_previous[k] = GreaterThan(32);
+      later_than_previous[k].in[0] <== unix_epoch_from_msg_decimal[k].out;
+      later_than_previous[k].in[1] <== previous_timestamp[k].timestamp;
+      later_than_previous[k].out === 1;
 
-
-component comp[N_SIGS];
-
-for ( var i=0; i<N_SIGS;i++)
-{
- comp[i] = GreaterThan(32);
-comp[i].in[0] <== unix_epoch_from_msg_decimal[i].out;
-comp[i].in[1] <== step_in[1];
-log(unix_epoch_from_msg_decimal[i].out);
-log(step_in[1]);
-log(comp[i].out);
-comp[i].out === 1; 
-step_out[1] <== unix_epoch_from_msg_decimal[i].out;
-}
 /*------------------------------------------------------------------------------
-Step4: Assert Poseidon Hash of Old Message Corresponds to Old Merkle Root
-
+Step5: Assert Poseidon Hash of Old Message Corresponds to the Current Merkle Root
 ------------------------------------------------------------------------------ */
+      old_leaf_assert[k] = MerkleTreeInclusionProof(N_DEPTH);
+      old_leaf_assert[k].leaf <== old_message_poseidon_hash[k][0];
+      for (var i = 0; i < N_DEPTH; i++) {
+        old_leaf_assert[k].siblings[i] <== siblings[k][i];
+        old_leaf_assert[k].pathIndices[i] <== pathIndices[k][i];
+      }
+
+      old_leaf_comp[k] = IsEqual();
+      old_leaf_comp[k].in[0] <== old_leaf_assert[k].root;
+      old_leaf_comp[k].in[1] <== roots[k];
+      //padding slots repeat an update that is already in roots[k]
+      enabled[k] * (enabled[k] - 1) === 0;
+      enabled[k] * (old_leaf_comp[k].out - 1) === 0;
 
-
-component old_leaf_comp = IsEqual();
-
-component old_leaf_assert[N_SIGS] ;
-for(var j =0; j<N_SIGS; j++){
- old_leaf_assert[j] = MerkleTreeInclusionProof(N_DEPTH);
-old_leaf_assert[j].leaf <== old_message_poseidon_hash[j][0];
-for (var i =0; i<N_DEPTH; i++){
-
-old_leaf_assert[j].siblings[i] <== siblings[j][i];
-old_leaf_assert[j].pathIndices[i] <== pathIndices[j][i];
-}
-//old_leaf_assert.root <== step_in[0];
-
-
-
-old_leaf_comp.in[0] <== old_leaf_assert[j].root;
-old_leaf_comp.in[1] <== step_in[0];
-
-old_leaf_comp.out === 1;
-
-}
-/* 
 /*------------------------------------------------------------------------------
-Step6: Calculate Poseidon Hash of Message
-
+Step6: Calculate Poseidon Hash of Message and Add it to the Merkle Tree
 ------------------------------------------------------------------------------ */
-/*Convert Message to FiniteField Element
-component messsage_finite_field[N_SIGS];
-
-
-for(var j =0; j<N_SIGS; j++)
-{
- message_finite_field[j] = Bits2Num(1024);
-for(var i =0; i <1024; i++)
-{
-  messsage_finite_field[j].in[i] <== message[j][i];
-
-
-}
-}
-//Calculate Poseidon Hash of message_finite_field.out
-
-component leaf_hash[N_SIGS];
-for(var i =0; i<N_SIGS;i++){
-leaf_hash [i]= Poseidon();
-
-leaf_hash[i].inputs[0] <==  messsage_finite_field[i].out;
-leaf_hash[i].inputs[1] <== 0;
-
-log("leaf_hash ", leaf_hash[i].out);
-}
-//Add leaf to Merkle Tree
-
-component merkle_update[N_SIGS];
-
-for(var j =0; j<N_SIGS; j++)
-{
-merkle_update[j]= MerkleTreeIncrement(N_DEPTH);
-merkle_update.leaf <== leaf_hash[j].out ;
-
-for (var i=0; i<N_DEPTH;i++)
-{
-merkle_update.pathIndices[i] <== pathIndices[i]; 
-merkle_update.siblings[i] <== siblings[i];
-
-}
-
-log("New Root Hash",merkle_update[i].root);
-}
-step_out[0] <== merkle_update[0].root; 
-
+      messsage_finite_field[k] = Bits2Num(1024);
+      for (var i = 0; i < 1024; i++) {
+        messsage_finite_field[k].in[i] <== message[k][i];
+      }
+
+      leaf_hash[k] = Poseidon();
+      leaf_hash[k].inputs[0] <== messsage_finite_field[k].out;
+      leaf_hash[k].inputs[1] <== 0;
+
+      merkle_update[k] = MerkleTreeIncrement(N_DEPTH);
+      merkle_update[k].leaf <== leaf_hash[k].out;
+      for (var i = 0; i < N_DEPTH; i++) {
+        merkle_update[k].pathIndices[i] <== pathIndices[k][i];
+        merkle_update[k].siblings[i] <== siblings[k][i];
+      }
+
+      root_delta[k] <== enabled[k] * (merkle_update[k].root - roots[k]);
+      roots[k + 1] <== roots[k] + root_delta[k];
+    }
 
+    step_out[0] <== roots[N_SIGS];
+    step_out[1] <== timestamps[N_SIGS];
 }
- */
-}
-component main{public[step_in]}  = ivc(2,2);
\ No newline at end of file
+component main{public[step_in]}  = ivc(2,2);
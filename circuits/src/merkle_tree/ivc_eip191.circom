@@ -0,0 +1,340 @@
+
+
+include "../eff_ecdsa_membership/eff_ecdsa_to_addr.circom";
+include "../../node_modules/circomlib/circuits/comparators.circom";
+include "keccak_eip191.circom";
+include "ascii_binary_to_decimal.circom";
+include "ethr_address_ascii_binary_to_decimal.circom";
+include "../poseidon/poseidon.circom";
+include "merkletreeupdate.circom";
+include "previous_timestamp.circom";
+include "keccak_eip712.circom";
+template ivc(N_DEPTH) {
+   /*--------------------------------------------------------------------------
+   Public Inputs  - Merkle Root, Previous Timestamp from Profile
+   Private Inputs - Message, Signature, Path Indices to Leaf Node, Siblings
+   Outputs        - New Merkle Root, Timestamp from Message
+   ----------------------------------------------------------------------------*/
+
+
+     //Step in = Things to be incremented = (State/Merkle Root, Timestamp for current version of user profile)
+    signal input step_in[2];
+
+    //Version of profile before the update
+    signal input old_message_poseidon_hash[1];
+    //Binary Representation of the Previous Message, zero if the address has no profile yet
+    signal input old_message[1024];
+    //Binary Representation of Message
+    signal input message[1024];
+    //1 if the wallet signed the EIP-712 ProfileUpdate struct of the message, 0 otherwise
+    signal input typed[1];
+    //Signature = Signature  = (r^-1,s,Tx,Ty,Ux,Uy)
+    signal input signatures[6];
+    //Generator Point of secp256k1
+
+    signal input pathIndices[N_DEPTH];
+    signal input siblings[N_DEPTH];
+
+    signal output step_out[2];
+
+
+
+   /* ---------------------------------------------------------------------------
+      VERIFY INPUT SIGNATURE CORRESPONDS TO INPUT MESSAGE OR NOT
+   Step 1 = Assert U = - r^-1 * Hash(Message) * G
+
+    ----------------------------------------------------------------------------*/
+   /*log("r_inverse_input",signatures[0]);
+   log("s_input",signatures[1]);
+   log("Tx_input",signatures[2]);
+   log("Ty_input",signatures[3]);
+   log("Ux_input",signatures[4]);
+   log("Uy_input",signatures[5]);
+   for(var i =0; i<N_DEPTH; i++)
+   { log("Path Indice -> ",i,"->",pathIndices[i]);
+
+   }
+
+   for(var i =0; i<N_DEPTH; i++)
+   { log("Sibling  -> ",i,"->",siblings[i]);
+
+   }
+   */
+   //Hashing Binary Mesage message[1024]->msg_hash[256]
+   //EIP-191: the wallet signed "\x19Ethereum Signed Message:\n" followed by the length and the message without padding
+    component msg_hash = keccak_hash_eip191_message(1024);
+   for (var i=0; i<1024; i++){
+    msg_hash.input_message[i] <== message[i];
+    }
+    //Typed updates: the wallet signed the EIP-712 ProfileUpdate struct of the message
+    typed[0] * (typed[0] - 1) === 0;
+    component typed_msg_hash = keccak_hash_eip712_message(1024);
+   for (var i=0; i<1024; i++){
+    typed_msg_hash.input_message[i] <== message[i];
+    }
+    signal signed_hash[256];
+   for (var i=0; i<256; i++){
+    signed_hash[i] <== typed[0] * (typed_msg_hash.output_hash[i] - msg_hash.output_hash[i]) + msg_hash.output_hash[i];
+    }
+    //Assert U = - r^-1 * Hash(Message) * G
+
+    //Converting Output Binary Hash to Finite Field Element
+   // log("Message Hash Prime Field Repn");
+signal intermidiate[256];
+   for (var i = 0; i< 256/8; i++){
+  for (var j = 0; j < 8; j++) {
+intermidiate[7-j+8*i] <-- signed_hash[8*i+j];
+  }
+
+}
+//log("Endianness Check");
+
+ /*for (var k =0; k<256; k++){
+    log(intermidiate[k]);
+  }*/
+
+  var sum =0;
+  signal hash_decimal;
+
+//log("Sample Message");
+  for(var i=0; i<256;i++)
+  {
+    sum += 2 **i * intermidiate[256-i-1];
+
+  }
+
+ //log("Finite Field Representation");
+  hash_decimal <-- sum;
+ // log(hash_decimal);
+
+
+
+     //Inermidiate variable to store r^-1
+     signal inter_mul;
+
+     //Intermidiate variable to store hash(message)*r^-1
+     signal inter_mul1;
+
+     signal inter_mul2;
+
+     //inter_mul = r^-1
+     inter_mul  <-- signatures[0] ;
+     //log("r_inverse",inter_mul);
+
+
+     //Inter_mul1 = -r^-1 * hash(m)
+     inter_mul1 <== hash_decimal * (-inter_mul);
+     //log("w= -r^-1 * hash(m)",inter_mul1);
+
+     inter_mul2 <== 5827542974853635922205193225510230345443287737019294244905648782786748292217 + inter_mul1;
+
+  component sMultU1 = Secp256k1Mul();
+    sMultU1.scalar <== hash_decimal;
+    sMultU1.xP <== 55066263022277343669578718895168534326250603453777594175500187360389116729240;
+    sMultU1.yP <== 32670510020758816978083085130507043184471273380659243275938904335757337482424;
+
+    //Assert U_x = -r^-1 * H(Message) * G_x
+   //signatures[4] === sMultU.outX;
+
+   //log("sMultU1.outX",sMultU1.outX);
+
+    //Assert U_Y = -r^-1 * H(Message) * G_y
+   //signatures[5] === sMultU.outY;
+   //log("sMultU1.outY",sMultU1.outY);
+
+   component sMultU2 = Secp256k1Mul();
+                                // q - r_inverse
+   sMultU2.scalar <== 115792089237316195423570985008687907852837564279074904382605163141518161494337-inter_mul;
+    sMultU2.xP <== sMultU1.outX;
+    sMultU2.yP <== sMultU1.outY;
+//log("Ux",signatures[4]);
+//log("sMultU2.outX",sMultU2.outX);
+
+signatures[4] === sMultU2.outX;
+signatures[5] === sMultU2.outY;
+
+
+
+/*------------------------------------------------------------------------------
+      EXTRACT ETHEREUM ADDRESSS FROM SIGNATURE
+   Step 2 = Assert U = - r^-1 * Hash(Message) * G
+   Signature = Signature  = (r,s,Tx,Ty,Ux,Uy)
+ ------------------------------------------------------------------------------*/
+
+
+   component ethr_addr_from_sig = EfficientECDSAToAddr();
+
+   ethr_addr_from_sig.s  <==  signatures[1];
+   ethr_addr_from_sig.Tx <==  signatures[2];
+   ethr_addr_from_sig.Ty <==  signatures[3];
+   ethr_addr_from_sig.Ux <==  signatures[4];
+   ethr_addr_from_sig.Uy <==  signatures[5];
+
+//log("Ethereum Address from Signature Output",ethr_addr_from_sig.addr);
+
+/*------------------------------------------------------------------------------
+Step3:    EXTRACT ETHEREUM ADDRESS FROM MESSAGE
+
+For now we always assume Message is of this form, we can re-work it later
+
+Message : 1703459910, 0x631438556b66c4908579Eab920dc162FF58958ea, Brad, Pitt, brad.pitt@gmail.com
+Note: We assume Message has 1024 bits and the rest of the characters are NULL characters
+Note:Ethreum Address is 42 characters long
+
+First 10 bytes are Unix Epoch
+11th byte is a comma
+12th byte is a space
+13th byte is a 0
+14th byte is a x
+15th byte to 54th byte is Ethereum Address
+------------------------------------------------------------------------------ */
+component ethr_addr_from_msg = ethr_address_ascii_binary_to_decimal();
+
+for (var i =0; i<320; i++){
+  ethr_addr_from_msg.in[i] <== message[14*8+i];
+}
+
+//log("Ethereum_address_from_message",ethr_addr_from_msg.out);
+
+//Assert Ethereum Address from Signature === Ethereum Address from Message
+
+component ethereum_address_comparator = IsEqual();
+
+ethereum_address_comparator.in[0] <== ethr_addr_from_msg.out;
+ethereum_address_comparator.in[1] <== ethr_addr_from_sig.addr;
+
+ethereum_address_comparator.out === 1;
+
+
+  //constrain_addr.in[0] <== ethr_addr_from_msg.out;
+  //constrain_addr.in[1] <== ethr_addr_from_sig.out;
+
+  //0 === constrain_addr.out;
+
+ // Note : Could also do ethr_addr_from_msg.out === ethr_addr_from_sig.out
+
+
+/*------------------------------------------------------------------------------
+Step4: EXTRACT TIMESTAMP FROM MESSAGE AND CHECK IT IS GREATER THAN INPUT TIMESTAMP
+
+For now we always assume Message is of this form, we can re-work it later
+
+Message : 1703459910, 0x631438556b66c4908579Eab920dc162FF58958ea, Brad, Pitt, brad.pitt@gmail.com
+Note: We assume Message has 1024 bits and the rest of the characters are NULL characters
+Note:Ethreum Address is 42 characters long
+
+First 10 bytes are Unix Epoch
+
+------------------------------------------------------------------------------ */
+component unix_epoch_from_msg_decimal = ascii_binary_string_to_decimal(80);
+
+for(var i=0;i<8*10;i++)
+{
+  unix_epoch_from_msg_decimal.ascii_binary_string[i] <== message[i];
+}
+
+
+
+component comp = GreaterEqThan(32);
+
+comp.in[0] <== unix_epoch_from_msg_decimal.out;
+comp.in[1] <== step_in[1];
+//log(unix_epoch_from_msg_decimal.out);
+//log(step_in[1]);
+//log(comp.out);
+
+
+comp.out === 1;
+step_out[1] <== unix_epoch_from_msg_decimal.out;
+
+/*------------------------------------------------------------------------------
+Step5: CHECK THE TIMESTAMP IS LATER THAN THE ONE OF THE REPLACED PROFILE
+
+Replay protection, an address only moves to strictly later versions of its profile
+------------------------------------------------------------------------------ */
+component previous_timestamp = previous_profile_timestamp();
+
+for (var i = 0; i < 1024; i++)
+{
+  previous_timestamp.old_message[i] <== old_message[i];
+}
+previous_timestamp.old_message_poseidon_hash <== old_message_poseidon_hash[0];
+
+component later_than_previous = GreaterThan(32);
+
+later_than_previous.in[0] <== unix_epoch_from_msg_decimal.out;
+later_than_previous.in[1] <== previous_timestamp.timestamp;
+
+later_than_previous.out === 1;
+
+/*------------------------------------------------------------------------------
+Step4: Assert Poseidon Hash of Old Message Corresponds to Old Merkle Root
+
+------------------------------------------------------------------------------ */
+
+
+
+component old_leaf_assert = MerkleTreeInclusionProof(N_DEPTH);
+
+old_leaf_assert.leaf <== old_message_poseidon_hash[0];
+for (var i =0; i<N_DEPTH; i++){
+
+old_leaf_assert.siblings[i] <== siblings[i];
+old_leaf_assert.pathIndices[i] <== pathIndices[i];
+}
+
+//old_leaf_assert.root <== step_in[0];
+
+component old_leaf_comp = IsEqual();
+
+old_leaf_comp.in[0] <== old_leaf_assert.root;
+old_leaf_comp.in[1] <== step_in[0];
+
+old_leaf_comp.out === 1;
+
+
+
+/*------------------------------------------------------------------------------
+Step6: Calculate Poseidon Hash of Message
+
+------------------------------------------------------------------------------ */
+//Convert Message to FiniteField Element
+component messsage_finite_field = Bits2Num(1024);
+
+for(var i =0; i <1024; i++)
+{
+  messsage_finite_field.in[i] <== message[i];
+
+
+}
+
+//Calculate Poseidon Hash of message_finite_field.out
+
+component leaf_hash = Poseidon();
+
+leaf_hash.inputs[0] <==  messsage_finite_field.out;
+leaf_hash.inputs[1] <== 0;
+
+log("leaf_hash ", leaf_hash.out);
+
+//Add leaf to Merkle Tree
+
+component merkle_update = MerkleTreeIncrement(N_DEPTH);
+
+merkle_update.leaf <== leaf_hash.out ;
+
+for (var i=0; i<N_DEPTH;i++)
+{
+merkle_update.pathIndices[i] <== pathIndices[i];
+merkle_update.siblings[i] <== siblings[i];
+
+}
+
+//log("New Root Hash",merkle_update.root);
+
+step_out[0] <== merkle_update.root;
+
+
+}
+
+component main{public[step_in]}  = ivc(2);
@@ -0,0 +1,150 @@
+include "../eff_ecdsa_membership/to_address/vocdoni-keccak/keccak.circom";
+include "../../node_modules/circomlib/circuits/bitify.circom";
+include "../../node_modules/circomlib/circuits/comparators.circom";
+include "message_length.circom";
+
+/*
+EIP-191 (personal_sign) hash of a message zero padded to numbits:
+
+   keccak256("\x19Ethereum Signed Message:\n" || decimal(len) || message[0..len])
+
+len is the length of the message without the zero padding, see MessageLength.
+For the 1024 bit profile messages the hashed input is 28 to 157 bytes long, so it
+spans one or two keccak blocks. Both blocks are absorbed and the state after the
+block holding the keccak padding is squeezed.
+*/
+
+// Same input and output bit order as keccak_hash_message in keccak_test.circom
+template keccak_hash_eip191_message(numbits)
+{
+  //Input Message in Little Endian Byte Representation
+  signal input input_message[numbits];
+
+  //Output 256 bit length Keccak Hash
+  signal output output_hash[256];
+
+  // "\x19Ethereum Signed Message:\n"
+  var prefix[26] = [25, 69, 116, 104, 101, 114, 101, 117, 109, 32, 83, 105, 103, 110, 101, 100, 32, 77, 101, 115, 115, 97, 103, 101, 58, 10];
+  var max_len = numbits \ 8;
+  var block_size = 136;
+  var n_bytes = 2 * block_size;
+  // prefix, up to 3 length digits, message and at least one padding byte
+  assert(max_len < 1000 && 26 + 3 + max_len < n_bytes);
+  var i;
+  var j;
+  var d;
+
+  // message bytes
+  signal msg[max_len];
+  for (i = 0; i < max_len; i++) {
+    var byte = 0;
+    for (j = 0; j < 8; j++) {
+      byte += input_message[8*i + j] * 2**(7-j);
+    }
+    msg[i] <== byte;
+  }
+
+  component length = MessageLength(max_len);
+  for (i = 0; i < max_len; i++) {
+    length.msg[i] <== msg[i];
+  }
+  signal msg_len <== length.len;
+
+  // decimal digits of the length
+  signal digit[3];
+  digit[0] <-- msg_len \ 100;
+  digit[1] <-- (msg_len \ 10) % 10;
+  digit[2] <-- msg_len % 10;
+  msg_len === 100*digit[0] + 10*digit[1] + digit[2];
+  component digit_range[3];
+  for (i = 0; i < 3; i++) {
+    digit_range[i] = LessThan(4);
+    digit_range[i].in[0] <== digit[i];
+    digit_range[i].in[1] <== 10;
+    digit_range[i].out === 1;
+  }
+  component below_10 = LessThan(10);
+  below_10.in[0] <== msg_len;
+  below_10.in[1] <== 10;
+  component below_100 = LessThan(10);
+  below_100.in[0] <== msg_len;
+  below_100.in[1] <== 100;
+  // num_digits[d - 1] is 1 if the length has d digits
+  signal num_digits[3];
+  num_digits[0] <== below_10.out;
+  num_digits[1] <== below_100.out - below_10.out;
+  num_digits[2] <== 1 - below_100.out;
+
+  // the bytes after the prefix for every number of digits, only one of them is not zero
+  signal shifted[3][n_bytes - 26];
+  for (d = 1; d <= 3; d++) {
+    for (i = 0; i < n_bytes - 26; i++) {
+      if (i < d) {
+        shifted[d-1][i] <== num_digits[d-1] * (48 + digit[3 - d + i]);
+      } else if (i - d < max_len) {
+        shifted[d-1][i] <== num_digits[d-1] * msg[i - d];
+      } else {
+        shifted[d-1][i] <== 0;
+      }
+    }
+  }
+
+  // keccak padding: 0x01 right after the message and 0x80 at the end of its block
+  signal end;
+  end <== 26 + num_digits[0] + 2*num_digits[1] + 3*num_digits[2] + msg_len;
+  component one_block = LessThan(10);
+  one_block.in[0] <== end;
+  one_block.in[1] <== block_size;
+  component is_end[n_bytes - 26];
+
+  signal bits[8*n_bytes];
+  for (i = 0; i < 26; i++) {
+    for (j = 0; j < 8; j++) {
+      bits[8*i + j] <== (prefix[i] >> j) & 1;
+    }
+  }
+  component byte_bits[n_bytes - 26];
+  for (i = 0; i < n_bytes - 26; i++) {
+    is_end[i] = IsEqual();
+    is_end[i].in[0] <== 26 + i;
+    is_end[i].in[1] <== end;
+    var padded_byte = shifted[0][i] + shifted[1][i] + shifted[2][i] + is_end[i].out;
+    if (26 + i == block_size - 1) {
+      padded_byte += 0x80 * one_block.out;
+    }
+    if (26 + i == n_bytes - 1) {
+      padded_byte += 0x80 * (1 - one_block.out);
+    }
+    byte_bits[i] = Num2Bits(8);
+    byte_bits[i].in <== padded_byte;
+    for (j = 0; j < 8; j++) {
+      bits[8*(26 + i) + j] <== byte_bits[i].out[j];
+    }
+  }
+
+  component absorb[2];
+  for (d = 0; d < 2; d++) {
+    absorb[d] = Absorb();
+    for (i = 0; i < 8*block_size; i++) {
+      absorb[d].block[i] <== bits[8*block_size*d + i];
+    }
+    for (i = 0; i < 25*64; i++) {
+      if (d == 0) {
+        absorb[d].s[i] <== 0;
+      } else {
+        absorb[d].s[i] <== absorb[d-1].out[i];
+      }
+    }
+  }
+  signal state[25*64];
+  for (i = 0; i < 25*64; i++) {
+    state[i] <== one_block.out * (absorb[0].out[i] - absorb[1].out[i]) + absorb[1].out[i];
+  }
+  component squeeze = Squeeze(256);
+  for (i = 0; i < 25*64; i++) {
+    squeeze.s[i] <== state[i];
+  }
+  for (i = 0; i < 256; i++) {
+    output_hash[i] <== squeeze.out[i];
+  }
+}
@@ -0,0 +1,161 @@
+include "../eff_ecdsa_membership/to_address/vocdoni-keccak/keccak.circom";
+include "../../node_modules/circomlib/circuits/bitify.circom";
+include "../../node_modules/circomlib/circuits/comparators.circom";
+include "ascii_binary_to_decimal.circom";
+include "ethr_address_ascii_binary_to_decimal.circom";
+include "message_length.circom";
+include "eip712_domain.circom";
+
+/*
+EIP-712 hash of the ProfileUpdate struct of a profile message zero padded to numbits bits:
+
+   ProfileUpdate(uint256 timestamp,address account,string profile)
+
+   keccak256("\x19\x01" || domainSeparator || hashStruct)
+   hashStruct = keccak256(typeHash || uint256(timestamp) || uint256(account) || keccak256(profile))
+
+The message is "<timestamp>, <account>, <profile>": 10 timestamp digits at bytes 0 to 9,
+the account at bytes 12 to 53 and the profile fields from byte 56 to the end of the
+message. The profile is at most numbits / 8 - 56 bytes long, its keccak is a single block.
+*/
+
+// Bits of 32 constant bytes in the bit order of the Keccak template
+function bytes32_to_keccak_bits(bytes) {
+  var bits[256];
+  for (var i = 0; i < 32; i++) {
+    for (var j = 0; j < 8; j++) {
+      bits[8*i + j] = (bytes[i] >> j) & 1;
+    }
+  }
+  return bits;
+}
+
+// Bits of a big endian uint256 in the bit order of the Keccak template
+template Uint256KeccakBits(nBits) {
+  signal input in;
+  signal output out[256];
+  component bits = Num2Bits(nBits);
+  bits.in <== in;
+  for (var i = 0; i < 32; i++) {
+    for (var j = 0; j < 8; j++) {
+      var bit = 8*(31 - i) + j;
+      if (bit < nBits) {
+        out[8*i + j] <== bits.out[bit];
+      } else {
+        out[8*i + j] <== 0;
+      }
+    }
+  }
+}
+
+// Same input and output bit order as keccak_hash_message in keccak_test.circom
+template keccak_hash_eip712_message(numbits)
+{
+  //Input Message in Little Endian Byte Representation
+  signal input input_message[numbits];
+
+  //Output 256 bit length Keccak Hash
+  signal output output_hash[256];
+
+  // keccak256("ProfileUpdate(uint256 timestamp,address account,string profile)")
+  var type_hash[32] = [71, 2, 79, 103, 169, 244, 108, 179, 8, 17, 67, 106, 37, 148, 54, 74, 164, 87, 255, 131, 7, 28, 170, 31, 61, 128, 131, 125, 175, 191, 152, 176];
+  var type_hash_bits[256] = bytes32_to_keccak_bits(type_hash);
+  var domain_separator_bits[256] = bytes32_to_keccak_bits(eip712_domain_separator());
+  var max_len = numbits \ 8;
+  var profile_start = 56;
+  var max_profile_len = max_len - profile_start;
+  var block_size = 136;
+  assert(max_profile_len < block_size);
+  var i;
+  var j;
+
+  // message bytes
+  signal msg[max_len];
+  for (i = 0; i < max_len; i++) {
+    var byte = 0;
+    for (j = 0; j < 8; j++) {
+      byte += input_message[8*i + j] * 2**(7-j);
+    }
+    msg[i] <== byte;
+  }
+
+  // uint256(timestamp)
+  component timestamp = ascii_binary_string_to_decimal(80);
+  for (i = 0; i < 80; i++) {
+    timestamp.ascii_binary_string[i] <== input_message[i];
+  }
+  component timestamp_bits = Uint256KeccakBits(64);
+  timestamp_bits.in <== timestamp.out;
+
+  // uint256(account), the 40 hex digits after "0x"
+  component account = ethr_address_ascii_binary_to_decimal();
+  for (i = 0; i < 320; i++) {
+    account.in[i] <== input_message[14*8 + i];
+  }
+  component account_bits = Uint256KeccakBits(160);
+  account_bits.in <== account.out;
+
+  // keccak256(profile), the profile bytes and the keccak padding fill a single block
+  component length = MessageLength(max_len);
+  for (i = 0; i < max_len; i++) {
+    length.msg[i] <== msg[i];
+  }
+  component has_profile = GreaterEqThan(10);
+  has_profile.in[0] <== length.len;
+  has_profile.in[1] <== profile_start;
+  has_profile.out === 1;
+  signal profile_len <== length.len - profile_start;
+  component is_end[max_profile_len + 1];
+  component profile_bits[block_size];
+  component profile_hash = Absorb();
+  for (i = 0; i < block_size; i++) {
+    var padded_byte = 0;
+    if (i < max_profile_len) {
+      padded_byte += msg[profile_start + i];
+    }
+    // 0x01 right after the profile, the bytes after the message are zero
+    if (i <= max_profile_len) {
+      is_end[i] = IsEqual();
+      is_end[i].in[0] <== i;
+      is_end[i].in[1] <== profile_len;
+      padded_byte += is_end[i].out;
+    }
+    if (i == block_size - 1) {
+      padded_byte += 0x80;
+    }
+    profile_bits[i] = Num2Bits(8);
+    profile_bits[i].in <== padded_byte;
+    for (j = 0; j < 8; j++) {
+      profile_hash.block[8*i + j] <== profile_bits[i].out[j];
+    }
+  }
+  for (i = 0; i < 25*64; i++) {
+    profile_hash.s[i] <== 0;
+  }
+  component profile_digest = Squeeze(256);
+  for (i = 0; i < 25*64; i++) {
+    profile_digest.s[i] <== profile_hash.out[i];
+  }
+
+  // hashStruct
+  component struct_hash = Keccak(4*256, 256);
+  for (i = 0; i < 256; i++) {
+    struct_hash.in[i] <== type_hash_bits[i];
+    struct_hash.in[256 + i] <== timestamp_bits.out[i];
+    struct_hash.in[2*256 + i] <== account_bits.out[i];
+    struct_hash.in[3*256 + i] <== profile_digest.out[i];
+  }
+
+  // keccak256("\x19\x01" || domainSeparator || hashStruct)
+  component digest = Keccak(16 + 512, 256);
+  for (j = 0; j < 8; j++) {
+    digest.in[j] <== (0x19 >> j) & 1;
+    digest.in[8 + j] <== (0x01 >> j) & 1;
+  }
+  for (i = 0; i < 256; i++) {
+    digest.in[16 + i] <== domain_separator_bits[i];
+    digest.in[16 + 256 + i] <== struct_hash.out[i];
+  }
+
+  output_hash <== digest.out;
+}
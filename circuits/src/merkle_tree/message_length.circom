@@ -0,0 +1,53 @@
+include "../../node_modules/circomlib/circuits/bitify.circom";
+include "../../node_modules/circomlib/circuits/comparators.circom";
+
+/*
+Length of a message zero padded to max_len bytes
+
+The bytes after the message are zero and its last byte is not, so the length is
+unique. Profile messages are text, they contain no zero bytes.
+*/
+template MessageLength(max_len) {
+  signal input msg[max_len];
+  signal output len;
+  assert(max_len < 1024);
+  var i;
+
+  var hint = 0;
+  for (i = 0; i < max_len; i++) {
+    if (msg[i] != 0) {
+      hint = i + 1;
+    }
+  }
+  len <-- hint;
+  component len_bits = Num2Bits(10);
+  len_bits.in <== len;
+  component len_max = LessThan(10);
+  len_max.in[0] <== len;
+  len_max.in[1] <== max_len + 1;
+  len_max.out === 1;
+
+  // zero padding
+  component in_msg[max_len];
+  for (i = 0; i < max_len; i++) {
+    in_msg[i] = LessThan(10);
+    in_msg[i].in[0] <== i;
+    in_msg[i].in[1] <== len;
+    msg[i] * (1 - in_msg[i].out) === 0;
+  }
+
+  // last byte of the message
+  component is_last[max_len];
+  signal last_byte[max_len];
+  var last = 0;
+  for (i = 0; i < max_len; i++) {
+    is_last[i] = IsEqual();
+    is_last[i].in[0] <== i + 1;
+    is_last[i].in[1] <== len;
+    last_byte[i] <== is_last[i].out * msg[i];
+    last += last_byte[i];
+  }
+  signal last_inverse;
+  last_inverse <-- last != 0 ? 1 / last : 0;
+  last * last_inverse === 1;
+}
@@ -0,0 +1,43 @@
+include "../../node_modules/circomlib/circuits/bitify.circom";
+include "../../node_modules/circomlib/circuits/comparators.circom";
+include "ascii_binary_to_decimal.circom";
+include "../poseidon/poseidon.circom";
+
+/*
+Timestamp of the profile message an update replaces, 0 if the address had no profile yet.
+
+old_message is the previous profile message in the bit order of message, all zero for a new
+address. It has to hash to the old leaf the way a new leaf is hashed from the message,
+Poseidon(Bits2Num(old_message), 0). Empty leaves are Poseidon(0, 0), so an all zero
+old_message only matches an address without a profile.
+*/
+template previous_profile_timestamp()
+{
+  signal input old_message[1024];
+  signal input old_message_poseidon_hash;
+
+  signal output timestamp;
+
+  component old_message_finite_field = Bits2Num(1024);
+  for (var i = 0; i < 1024; i++) {
+    old_message_finite_field.in[i] <== old_message[i];
+  }
+  component old_leaf_hash = Poseidon();
+  old_leaf_hash.inputs[0] <== old_message_finite_field.out;
+  old_leaf_hash.inputs[1] <== 0;
+  old_leaf_hash.out === old_message_poseidon_hash;
+
+  // profile messages start with the 10 ascii digits of their timestamp, never with zero bytes
+  component epoch_bits = Bits2Num(80);
+  for (var i = 0; i < 80; i++) {
+    epoch_bits.in[i] <== old_message[i];
+  }
+  component is_new = IsZero();
+  is_new.in <== epoch_bits.out;
+
+  component epoch = ascii_binary_string_to_decimal(80);
+  for (var i = 0; i < 80; i++) {
+    epoch.ascii_binary_string[i] <== old_message[i];
+  }
+  timestamp <== (1 - is_new.out) * epoch.out;
+}
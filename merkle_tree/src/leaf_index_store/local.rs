@@ -47,6 +47,22 @@ impl<T: Clone> LeafIndexStore<T> for LocalLeafIndexStore<T> {
             depth: self.depth,
         }
     }
+    fn remove_last(&mut self, key: &Key) -> Result<AbsIndex> {
+        let abs_index = match self.leaf_index.get(key) {
+            None => return Err(anyhow::anyhow!("key not found")),
+            Some(x) => AbsIndex(x.0),
+        };
+        let last_index: AbsIndex = LeafIndex {
+            i: self.leaf_index.len() - 1,
+            depth: self.depth,
+        }
+        .into();
+        if abs_index != last_index {
+            return Err(anyhow::anyhow!("key is not the last added key"));
+        }
+        self.leaf_index.remove(key);
+        Ok(abs_index)
+    }
 }
 
 #[cfg(test)]
@@ -127,4 +143,21 @@ mod tests {
         let index1 = store.get_new_index();
         assert_eq!(index1.i, 1);
     }
+
+    #[test]
+    fn test_remove_last() {
+        let depth = 3;
+        let mut store = LocalLeafIndexStore::<Hash>::new(depth);
+        let hash = Arc::new(Hash::from(0));
+        let key1 = vec![0 as u8; 4];
+        let key2 = vec![1 as u8; 4];
+        store.put(key1.clone(), (AbsIndex(3), hash.clone()));
+        store.put(key2.clone(), (AbsIndex(4), hash.clone()));
+
+        assert!(store.remove_last(&key1).is_err());
+        assert_eq!(store.remove_last(&key2).unwrap(), AbsIndex(4));
+        assert_eq!(store.get_new_index().i, 1);
+        assert_eq!(store.remove_last(&key1).unwrap(), AbsIndex(3));
+        assert!(store.remove_last(&key1).is_err());
+    }
 }
@@ -11,4 +11,6 @@ pub trait LeafIndexStore<T> {
     fn get(&self, key: &Key) -> Result<(AbsIndex, Arc<T>)>;
     fn put(&mut self, key: Key, value: (AbsIndex, Arc<T>));
     fn get_new_index(&self) -> LeafIndex;
+    /// Removes the most recently added key, fails for any other key
+    fn remove_last(&mut self, key: &Key) -> Result<AbsIndex>;
 }
@@ -167,12 +167,49 @@ impl MerkleTree {
     }
 
     pub fn insert_leaf(&mut self, key: &Key, data: &Data) -> Result<(Hash, Hash, Vec<Sibling>)> {
+        self.insert_leaf_hash(key, &hash_data(data))
+    }
+    /// Same as `insert_leaf` for an already hashed leaf,
+    /// e.g. to rebuild a tree from its leaf hashes in insertion order
+    pub fn insert_leaf_hash(
+        &mut self,
+        key: &Key,
+        hash: &Hash,
+    ) -> Result<(Hash, Hash, Vec<Sibling>)> {
         // check if leaf is there already
         let abs_index = self.leaf_index.get(key)?.0;
-        let hash = hash_data(data);
         self.leaf_index
             .put(key.clone(), (abs_index, Arc::new(hash.clone())));
-        Ok(self.insert_to_hashes(abs_index, &hash))
+        Ok(self.insert_to_hashes(abs_index, hash))
+    }
+    /// Removes the leaf of the most recently added key, e.g. to revert its insertion
+    ///
+    /// Returns the new root hash
+    pub fn remove_last_leaf(&mut self, key: &Key) -> Result<Hash> {
+        let abs_index = self.leaf_index.remove_last(key)?;
+        let null_hash = self.hashes.return_hash_of_null(abs_index);
+        Ok(self.insert_to_hashes(abs_index, &null_hash).1)
+    }
+    /// Restores the leaf of `key` to `old_leaf`, the value `get_leaf` returned before the insertion
+    ///
+    /// A key that was not in the tree before is removed again, so insertions have to be
+    /// reverted in reverse order. Returns the new root hash
+    pub fn revert_leaf(&mut self, key: &Key, old_leaf: &Hash) -> Result<Hash> {
+        let abs_index = self.leaf_index.get(key)?.0;
+        if *old_leaf == self.hashes.return_hash_of_null(abs_index) {
+            self.remove_last_leaf(key)
+        } else {
+            Ok(self.insert_leaf_hash(key, old_leaf)?.1)
+        }
+    }
+    /// Leaf hash and siblings from the leaf to the root of `key`, `None` if the key is not in the tree
+    pub fn get_proof(&self, key: &Key) -> Option<(Hash, Vec<Sibling>)> {
+        let (abs_index, hash) = self.leaf_index.get(key).ok()?;
+        // keys that are not in the tree get the next free index
+        if abs_index == AbsIndex::from(self.leaf_index.get_new_index()) {
+            return None;
+        }
+        Some((hash.as_ref().clone(), self.get_siblings(abs_index)))
     }
     pub fn get_leaf(&self, key: &Key) -> Result<Hash> {
         self.leaf_index.get(key).map(|x| x.1.as_ref().clone())
@@ -380,6 +417,70 @@ mod tests {
         assert_eq!(new_expected_root, new_root);
     }
 
+    #[test]
+    fn test_insert_leaf_hash() {
+        let mut tree = MerkleTree::new(3);
+        let key = ETH_ADRESS.as_bytes().to_vec();
+        let data = MESSAGE.bytes().collect::<Vec<u8>>();
+        let (leaf_hash, root, _) = tree.insert_leaf(&key, &data).unwrap();
+
+        let mut rebuilt_tree = MerkleTree::new(3);
+        let (_, rebuilt_root, _) = rebuilt_tree.insert_leaf_hash(&key, &leaf_hash).unwrap();
+        assert_eq!(root, rebuilt_root);
+        assert_eq!(tree, rebuilt_tree);
+    }
+
+    #[test]
+    fn test_remove_last_leaf() {
+        let mut tree = MerkleTree::new(3);
+        let empty_root = tree.root();
+        let key = ETH_ADRESS.as_bytes().to_vec();
+        let data = MESSAGE.bytes().collect::<Vec<u8>>();
+        tree.insert_leaf(&key, &data).unwrap();
+
+        assert_eq!(tree.remove_last_leaf(&key).unwrap(), empty_root);
+        assert_eq!(tree.root(), empty_root);
+        assert!(tree.remove_last_leaf(&key).is_err());
+    }
+
+    #[test]
+    fn test_revert_leaf() {
+        let mut tree = MerkleTree::new(3);
+        let empty_root = tree.root();
+        let key1 = vec![1u8; 20];
+        let key2 = vec![2u8; 20];
+
+        let old_leaf1 = tree.get_leaf(&key1).unwrap();
+        let (_, root1, _) = tree.insert_leaf(&key1, &vec![1u8; 32]).unwrap();
+        let old_leaf2 = tree.get_leaf(&key2).unwrap();
+        let (_, root2, _) = tree.insert_leaf(&key2, &vec![2u8; 32]).unwrap();
+        let old_leaf1_update = tree.get_leaf(&key1).unwrap();
+        tree.insert_leaf(&key1, &vec![3u8; 32]).unwrap();
+
+        assert_eq!(tree.revert_leaf(&key1, &old_leaf1_update).unwrap(), root2);
+        assert_eq!(tree.revert_leaf(&key2, &old_leaf2).unwrap(), root1);
+        assert_eq!(tree.revert_leaf(&key1, &old_leaf1).unwrap(), empty_root);
+        assert_eq!(tree.root(), empty_root);
+    }
+
+    #[test]
+    fn test_get_proof() {
+        let mut tree = MerkleTree::new(3);
+        let key = ETH_ADRESS.as_bytes().to_vec();
+        let data = MESSAGE.bytes().collect::<Vec<u8>>();
+        assert_eq!(tree.get_proof(&key), None);
+
+        let (leaf_hash, root, siblings) = tree.insert_leaf(&key, &data).unwrap();
+        let (proof_leaf_hash, proof_siblings) = tree.get_proof(&key).unwrap();
+        assert_eq!(proof_leaf_hash, leaf_hash);
+        assert_eq!(proof_siblings, siblings);
+        assert_eq!(
+            MerkleTree::get_new_hashes(&proof_leaf_hash, &proof_siblings).last(),
+            Some(&root)
+        );
+        assert_eq!(tree.get_proof(&vec![1u8; 20]), None);
+    }
+
     #[test]
     fn test_get_path_to_root_indices() {
         let abs_index = AbsIndex(6);